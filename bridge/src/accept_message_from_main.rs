@@ -17,9 +17,11 @@
 use contracts;
 use error::{self, ResultExt};
 use futures::{Async, Future, Poll};
-use helpers::{self, AsyncCall, AsyncTransaction};
+use helpers::{self, AsyncCall};
+use journal::{Journal, RelayJournal, RelayKind};
 use main_contract::MainContract;
 use relay_stream::LogToFuture;
+use send_tx_with_receipt::TrackedTransaction;
 use side_contract::SideContract;
 use web3::types::{Address, Log, H256};
 use web3::Transport;
@@ -28,13 +30,20 @@ use web3::Transport;
 pub struct LogToAcceptMessageFromMain<T> {
 	pub main: MainContract<T>,
 	pub side: SideContract<T>,
+	pub journal: Journal,
 }
 
 impl<T: Transport> LogToFuture for LogToAcceptMessageFromMain<T> {
 	type Future = AcceptMessageFromMain<T>;
 
+	fn log_seen(&self, log: &Log) {
+		self.journal
+			.relay_of_log(RelayKind::MainToSideSign, log)
+			.seen()
+	}
+
 	fn log_to_future(&self, log: &Log) -> Self::Future {
-		AcceptMessageFromMain::new(log, self.side.clone(), self.main.clone())
+		AcceptMessageFromMain::new(log, self.side.clone(), self.main.clone(), &self.journal)
 	}
 }
 
@@ -47,7 +56,7 @@ enum State<T: Transport> {
 			contracts::side::functions::has_authority_accepted_message_from_main::Decoder,
 		>,
 	},
-	AwaitTxSent(AsyncTransaction<T>),
+	AwaitTxSent(TrackedTransaction<T>),
 }
 
 pub struct AcceptMessageFromMain<T: Transport> {
@@ -56,13 +65,23 @@ pub struct AcceptMessageFromMain<T: Transport> {
 	sender: Address,
 	recipient: Address,
 	side: SideContract<T>,
+	journal: RelayJournal,
 }
 
 impl<T: Transport> AcceptMessageFromMain<T> {
-	pub fn new(raw_log: &Log, side: SideContract<T>, main: MainContract<T>) -> Self {
+	pub fn new(
+		raw_log: &Log,
+		side: SideContract<T>,
+		main: MainContract<T>,
+		journal: &Journal,
+	) -> Self {
 		let main_tx_hash = raw_log
 			.transaction_hash
 			.expect("`log` must be mined and contain `transaction_hash`. q.e.d.");
+		let log_index = raw_log
+			.log_index
+			.expect("`log` must be mined and contain `log_index`. q.e.d.")
+			.as_u64();
 
 		let log = helpers::parse_log(contracts::main::events::relay_message::parse_log, raw_log)
 			.expect("`log` must be for a relay message. q.e.d.");
@@ -70,10 +89,9 @@ impl<T: Transport> AcceptMessageFromMain<T> {
 		let sender = log.sender;
 		let recipient = log.recipient;
 
-		info!(
-			"{:?} - step 1/4 - fetch message using message_id",
-			main_tx_hash
-		);
+		let journal = journal.relay(RelayKind::MainToSideSign, main_tx_hash, log_index);
+		journal.message_id(log.message_id);
+
 		let future = main.relayed_message_by_id(log.message_id);
 		let state = State::AwaitMessage(future);
 
@@ -83,6 +101,7 @@ impl<T: Transport> AcceptMessageFromMain<T> {
 			sender,
 			recipient,
 			side,
+			journal,
 		}
 	}
 }
@@ -92,6 +111,16 @@ impl<T: Transport> Future for AcceptMessageFromMain<T> {
 	type Error = error::Error;
 
 	fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+		let result = self.poll_state();
+		if let Err(ref err) = result {
+			self.journal.failed(err);
+		}
+		result
+	}
+}
+
+impl<T: Transport> AcceptMessageFromMain<T> {
+	fn poll_state(&mut self) -> Poll<Option<H256>, error::Error> {
 		loop {
 			let next_state = match self.state {
 				State::AwaitMessage(ref mut future) => {
@@ -99,10 +128,6 @@ impl<T: Transport> Future for AcceptMessageFromMain<T> {
 						.poll()
 						.chain_err(|| "AcceptMessageFromMain: failed to fetch the message"));
 
-					info!(
-						"{:?} - 2/4 - checking if the message is already signed",
-						self.main_tx_hash
-					);
 					State::AwaitAlreadyAccepted {
 						message: message.clone(),
 						future: self.side.is_message_accepted_from_main(
@@ -121,11 +146,10 @@ impl<T: Transport> Future for AcceptMessageFromMain<T> {
 						"AcceptMessageFromMain: failed to check if already accepted"
 					}));
 					if has_already_accepted {
-						info!("{:?} - DONE - already accepted", self.main_tx_hash);
+						self.journal.skipped();
 						return Ok(Async::Ready(None));
 					}
 
-					info!("{:?} - 3/4 - accepting the message", self.main_tx_hash);
 					State::AwaitTxSent(self.side.accept_message_from_main(
 						self.main_tx_hash,
 						message.clone(),
@@ -135,12 +159,18 @@ impl<T: Transport> Future for AcceptMessageFromMain<T> {
 				}
 				State::AwaitTxSent(ref mut future) => {
 					let main_tx_hash = self.main_tx_hash;
-					let side_tx_hash = try_ready!(future.poll().chain_err(|| format!(
-						"AcceptMessageFromMain: checking whether {} was relayed failed",
-						main_tx_hash
-					)));
-					info!("{:?} - DONE - accepted", self.main_tx_hash);
-					return Ok(Async::Ready(Some(side_tx_hash)));
+					let result = future.poll().chain_err(|| {
+						format!(
+							"AcceptMessageFromMain: checking whether {} was relayed failed",
+							main_tx_hash
+						)
+					});
+					if let Some(hash) = future.transaction_hash() {
+						self.journal.submitted(hash);
+					}
+					let receipt = try_ready!(result);
+					self.journal.mined(receipt.transaction_hash);
+					return Ok(Async::Ready(Some(receipt.transaction_hash)));
 				}
 			};
 			self.state = next_state;
@@ -153,6 +183,10 @@ mod tests {
 	use super::*;
 	use contracts;
 	use ethabi;
+	use gas_limit::GasLimit;
+	use gas_price::GasPriceOracle;
+	use journal::RelayStatus;
+	use nonce_manager::NonceManager;
 	use rustc_hex::ToHex;
 	use tokio_core::reactor::Core;
 	use web3::types::{Bytes, Log};
@@ -187,7 +221,7 @@ mod tests {
 			block_hash: None,
 			block_number: None,
 			transaction_index: None,
-			log_index: None,
+			log_index: Some(0.into()),
 			transaction_log_index: None,
 			log_type: None,
 			removed: None,
@@ -238,25 +272,58 @@ mod tests {
 					"to": format!("0x{:x}", side_contract_address),
 				}, "latest"]),
 				res => json!(format!("0x{}", ethabi::encode(&[ethabi::Token::Bool(false)]).to_hex::<String>()));
+			"eth_getTransactionCount" =>
+				req => json!([format!("0x{:x}", authority_address), "pending"]),
+				res => json!("0x0");
 			"eth_sendTransaction" =>
 				req => json!([{
 					"data": format!("0x{}", accept_message_call_data.to_hex::<String>()),
 					"from": "0x0000000000000000000000000000000000000001",
 					"gas": "0xfd",
 					"gasPrice": "0xa0",
+					"nonce": "0x0",
 					"to": format!("0x{:x}", side_contract_address),
 				}]),
 				res => json!(format!("0x{}", tx_hash));
+			"eth_blockNumber" =>
+				req => json!([]),
+				res => json!("0x1011");
+			"eth_getTransactionReceipt" =>
+				req => json!([format!("0x{}", tx_hash)]),
+				res => json!({
+					"blockHash": "0xe0bdcf35b14a292d2998308d9b3fdea93a8c3d9c0b6c824c633fb9b15f9c3919",
+					"blockNumber": "0x1011",
+					"contractAddress": null,
+					"cumulativeGasUsed": "0x1c1999",
+					"gasUsed": "0xcdb5d",
+					"logs": [],
+					"logsBloom": format!("0x{}", "0".repeat(512)),
+					"root": null,
+					"status": "0x1",
+					"transactionHash": format!("0x{}", tx_hash),
+					"transactionIndex": "0x4"
+				});
 		);
 
 		let main_contract = MainContract {
 			transport: main_transport.clone(),
 			contract_address: main_contract_address,
 			authority_address,
-			submit_collected_signatures_gas: 0.into(),
+			submit_collected_signatures_gas: GasLimit::Fixed(0.into()),
+			submit_collected_signatures_gas_price: GasPriceOracle::fixed(0.into()),
 			request_timeout: ::std::time::Duration::from_millis(0),
 			logs_poll_interval: ::std::time::Duration::from_millis(0),
 			required_log_confirmations: 0,
+			signer: None,
+			nonce_manager: NonceManager::new(
+				main_transport.clone(),
+				authority_address,
+				::std::time::Duration::from_millis(0),
+			),
+			resubmit_after: ::std::time::Duration::from_millis(0),
+			gas_price_bump_percent: 0,
+			max_log_block_range: None,
+			new_heads: None,
 		};
 
 		let side_contract = SideContract {
@@ -267,18 +334,37 @@ mod tests {
 			request_timeout: ::std::time::Duration::from_millis(0),
 			logs_poll_interval: ::std::time::Duration::from_millis(0),
 			required_log_confirmations: 0,
-			sign_main_to_side_gas: 0xfd.into(),
-			sign_main_to_side_gas_price: 0xa0.into(),
-			sign_side_to_main_gas: 0.into(),
-			sign_side_to_main_gas_price: 0.into(),
+			sign_main_to_side_gas: GasLimit::Fixed(0xfd.into()),
+			sign_main_to_side_gas_price: GasPriceOracle::fixed(0xa0.into()),
+			sign_side_to_main_gas: GasLimit::Fixed(0.into()),
+			sign_side_to_main_gas_price: GasPriceOracle::fixed(0.into()),
+			signer: None,
+			nonce_manager: NonceManager::new(
+				side_transport.clone(),
+				authority_address,
+				::std::time::Duration::from_millis(0),
+			),
+			resubmit_after: ::std::time::Duration::from_millis(0),
+			gas_price_bump_percent: 0,
+			max_log_block_range: None,
+			new_heads: None,
 		};
 
-		let future = AcceptMessageFromMain::new(&raw_log, side_contract, main_contract);
+		let journal = Journal::in_memory();
+		let future = AcceptMessageFromMain::new(&raw_log, side_contract, main_contract, &journal);
 
 		let mut event_loop = Core::new().unwrap();
 		let result = event_loop.run(future).unwrap();
 		assert_eq!(result, Some(tx_hash.parse().unwrap()));
 
+		let entries = journal.entries();
+		assert_eq!(entries.len(), 1);
+		assert_eq!(entries[0].kind, RelayKind::MainToSideSign);
+		assert_eq!(entries[0].tx_hash, log_tx_hash);
+		assert_eq!(entries[0].message_id, Some(log.message_id));
+		assert_eq!(entries[0].status, RelayStatus::Mined);
+		assert_eq!(entries[0].relay_tx_hash, Some(tx_hash.parse().unwrap()));
+
 		assert_eq!(
 			side_transport.actual_requests(),
 			side_transport.expected_requests()
@@ -319,7 +405,7 @@ mod tests {
 			block_hash: None,
 			block_number: None,
 			transaction_index: None,
-			log_index: None,
+			log_index: Some(0.into()),
 			transaction_log_index: None,
 			log_type: None,
 			removed: None,
@@ -368,10 +454,21 @@ mod tests {
 			transport: main_transport.clone(),
 			contract_address: main_contract_address,
 			authority_address,
-			submit_collected_signatures_gas: 0.into(),
+			submit_collected_signatures_gas: GasLimit::Fixed(0.into()),
+			submit_collected_signatures_gas_price: GasPriceOracle::fixed(0.into()),
 			request_timeout: ::std::time::Duration::from_millis(0),
 			logs_poll_interval: ::std::time::Duration::from_millis(0),
 			required_log_confirmations: 0,
+			signer: None,
+			nonce_manager: NonceManager::new(
+				main_transport.clone(),
+				authority_address,
+				::std::time::Duration::from_millis(0),
+			),
+			resubmit_after: ::std::time::Duration::from_millis(0),
+			gas_price_bump_percent: 0,
+			max_log_block_range: None,
+			new_heads: None,
 		};
 
 		let side_contract = SideContract {
@@ -382,17 +479,29 @@ mod tests {
 			request_timeout: ::std::time::Duration::from_millis(0),
 			logs_poll_interval: ::std::time::Duration::from_millis(0),
 			required_log_confirmations: 0,
-			sign_main_to_side_gas: 0xfd.into(),
-			sign_main_to_side_gas_price: 0xa0.into(),
-			sign_side_to_main_gas: 0.into(),
-			sign_side_to_main_gas_price: 0.into(),
+			sign_main_to_side_gas: GasLimit::Fixed(0xfd.into()),
+			sign_main_to_side_gas_price: GasPriceOracle::fixed(0xa0.into()),
+			sign_side_to_main_gas: GasLimit::Fixed(0.into()),
+			sign_side_to_main_gas_price: GasPriceOracle::fixed(0.into()),
+			signer: None,
+			nonce_manager: NonceManager::new(
+				side_transport.clone(),
+				authority_address,
+				::std::time::Duration::from_millis(0),
+			),
+			resubmit_after: ::std::time::Duration::from_millis(0),
+			gas_price_bump_percent: 0,
+			max_log_block_range: None,
+			new_heads: None,
 		};
 
-		let future = AcceptMessageFromMain::new(&raw_log, side_contract, main_contract);
+		let journal = Journal::in_memory();
+		let future = AcceptMessageFromMain::new(&raw_log, side_contract, main_contract, &journal);
 
 		let mut event_loop = Core::new().unwrap();
 		let result = event_loop.run(future).unwrap();
 		assert_eq!(result, None);
+		assert_eq!(journal.entries()[0].status, RelayStatus::Skipped);
 
 		assert_eq!(
 			side_transport.actual_requests(),
@@ -17,18 +17,30 @@
 use error::{self, ResultExt};
 use futures::future::FromErr;
 use futures::{Async, Future, Poll, Stream};
+use std::cmp;
 use std::time::Duration;
-use tokio_timer::{Interval, Timeout, Timer};
+use tokio_timer::{Interval, Sleep, Timeout, Timer};
 use web3;
-use web3::api::Namespace;
+use web3::api::{EthSubscribe, Namespace, SubscribeFuture, SubscriptionStream};
 use web3::helpers::CallFuture;
-use web3::types::U64;
+use web3::types::{BlockHeader, U64};
 use web3::Transport;
+use ws_transport::WsTransport;
+
+/// a subscription without a new head for this many poll intervals is considered stalled
+const NEW_HEADS_STALL_POLL_INTERVALS: u32 = 5;
 
 /// Block Number Stream state.
 enum State<T: Transport> {
 	AwaitInterval,
 	AwaitBlockNumber(Timeout<FromErr<CallFuture<U64, T::Out>, error::Error>>),
+	/// the generation of the connection is kept to reconnect it only once
+	AwaitSubscription(
+		Timeout<FromErr<SubscribeFuture<WsTransport, BlockHeader>, error::Error>>,
+		u64,
+	),
+	/// the sleep completes once the subscription stalled
+	AwaitNewHead(SubscriptionStream<WsTransport, BlockHeader>, u64, Sleep),
 }
 
 pub struct BlockNumberStreamOptions<T> {
@@ -37,13 +49,20 @@ pub struct BlockNumberStreamOptions<T> {
 	pub confirmations: u32,
 	pub transport: T,
 	pub after: u64,
+	/// if set new blocks come from a `newHeads` subscription instead of polling
+	pub new_heads: Option<WsTransport>,
 }
 
 /// `Stream` that repeatedly polls `eth_blockNumber` and yields new block numbers.
+/// if `new_heads` is set it subscribes to new blocks instead. whenever the subscription
+/// fails or stalls it reconnects and polls once before subscribing again.
 pub struct BlockNumberStream<T: Transport> {
 	request_timeout: Duration,
 	confirmations: u32,
 	transport: T,
+	new_heads: Option<WsTransport>,
+	/// time without a new head after which the subscription is replaced
+	new_heads_stall_timeout: Duration,
 	last_checked_block: u64,
 	timer: Timer,
 	poll_interval: Interval,
@@ -54,14 +73,68 @@ impl<T: Transport> BlockNumberStream<T> {
 	pub fn new(options: BlockNumberStreamOptions<T>) -> Self {
 		let timer = Timer::default();
 
-		BlockNumberStream {
+		let mut stream = BlockNumberStream {
 			request_timeout: options.request_timeout,
 			confirmations: options.confirmations,
 			poll_interval: timer.interval(options.poll_interval),
 			transport: options.transport,
+			new_heads: options.new_heads,
+			new_heads_stall_timeout: cmp::max(
+				options.poll_interval * NEW_HEADS_STALL_POLL_INTERVALS,
+				options.request_timeout,
+			),
 			last_checked_block: options.after,
 			timer,
 			state: State::AwaitInterval,
+		};
+		stream.state = stream.next_polling_state();
+		stream
+	}
+
+	/// subscribes if possible. polls otherwise
+	fn next_polling_state(&self) -> State<T> {
+		match self.new_heads {
+			Some(ref new_heads) => {
+				info!("BlockNumberStream: subscribing to new heads");
+				let generation = new_heads.generation();
+				let future = EthSubscribe::new(new_heads.clone()).subscribe_new_heads();
+				State::AwaitSubscription(
+					self.timer.timeout(future.from_err(), self.request_timeout),
+					generation,
+				)
+			}
+			None => State::AwaitInterval,
+		}
+	}
+
+	/// falls back to polling after the subscription on connection `generation` failed.
+	/// the connection is shared with other streams. it is only replaced if none of them did already
+	fn subscription_failed(&self, reason: &str, generation: u64) -> State<T> {
+		warn!(
+			"BlockNumberStream: new heads subscription {}. polling instead",
+			reason
+		);
+		if let Some(ref new_heads) = self.new_heads {
+			if let Err(err) = new_heads.reconnect(generation) {
+				warn!("BlockNumberStream: reconnecting failed: {}", err);
+			}
+		}
+		State::AwaitInterval
+	}
+
+	/// returns the new last confirmed block if `last_block` confirmed new blocks
+	fn confirm(&mut self, last_block: u64) -> Option<u64> {
+		// subtraction that saturates at zero
+		let last_confirmed_block = last_block.saturating_sub(self.confirmations as u64);
+
+		if self.last_checked_block < last_confirmed_block {
+			self.last_checked_block = last_confirmed_block;
+			Some(last_confirmed_block)
+		} else {
+			info!(
+				"BlockNumberStream: no blocks confirmed since we last checked. waiting some more"
+			);
+			None
 		}
 	}
 }
@@ -95,17 +168,49 @@ impl<T: Transport> Stream for BlockNumberStream<T> {
 						"BlockNumberStream: fetched last block number {}",
 						last_block
 					);
-					// subtraction that saturates at zero
-					let last_confirmed_block = last_block.saturating_sub(self.confirmations as u64);
-
-					if self.last_checked_block < last_confirmed_block {
-						self.last_checked_block = last_confirmed_block;
-						(State::AwaitInterval, Some(last_confirmed_block))
-					} else {
-						info!("BlockNumberStream: no blocks confirmed since we last checked. waiting some more");
-						(State::AwaitInterval, None)
-					}
+					(self.next_polling_state(), self.confirm(last_block))
 				}
+				State::AwaitSubscription(ref mut future, generation) => match future.poll() {
+					Ok(Async::NotReady) => return Ok(Async::NotReady),
+					Ok(Async::Ready(stream)) => {
+						info!("BlockNumberStream: subscribed to new heads");
+						let stall = self.timer.sleep(self.new_heads_stall_timeout);
+						(State::AwaitNewHead(stream, generation, stall), None)
+					}
+					Err(err) => (
+						self.subscription_failed(&format!("failed: {}", err), generation),
+						None,
+					),
+				},
+				State::AwaitNewHead(ref mut stream, generation, ref mut stall) => match stream
+					.poll()
+				{
+					Ok(Async::NotReady) => {
+						// a connection can stay open without delivering new heads
+						try_ready!(stall
+							.poll()
+							.chain_err(|| "BlockNumberStream: new heads stall timer failed"));
+						(self.subscription_failed("stalled", generation), None)
+					}
+					Ok(Async::Ready(Some(header))) => {
+						let last_block = match header.number {
+							Some(number) => number.as_u64(),
+							// pending block
+							None => continue,
+						};
+						*stall = self.timer.sleep(self.new_heads_stall_timeout);
+						info!("BlockNumberStream: received new head {}", last_block);
+						if let Some(last_confirmed_block) = self.confirm(last_block) {
+							return Ok(Async::Ready(Some(last_confirmed_block)));
+						}
+						continue;
+					}
+					Ok(Async::Ready(None)) => (self.subscription_failed("ended", generation), None),
+					Err(err) => (
+						self.subscription_failed(&format!("failed: {:?}", err), generation),
+						None,
+					),
+				},
 			};
 
 			self.state = next_state;
@@ -145,6 +250,7 @@ mod tests {
 			confirmations: 12,
 			transport: transport.clone(),
 			after: 3,
+			new_heads: None,
 		});
 
 		let mut event_loop = Core::new().unwrap();
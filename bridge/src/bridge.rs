@@ -19,8 +19,10 @@ use web3::Transport;
 use accept_message_from_main;
 use database::State;
 use error::{self, ResultExt};
+use journal::{Journal, RelayKind};
 use log_stream::LogStream;
 use main_contract::MainContract;
+use metrics::Metrics;
 use relay_stream::RelayStream;
 use side_contract::SideContract;
 use side_to_main_sign;
@@ -33,7 +35,8 @@ use side_to_main_signatures;
 /// if polled polls all relay streams which causes them fetch
 /// all pending relays and relay them
 /// updates the database with results returned from relay streams.
-/// yields new state that should be persisted
+/// yields new state that should be persisted.
+/// endless unless created with `until_caught_up`
 pub struct Bridge<T: Transport> {
 	accept_message_from_main:
 		RelayStream<LogStream<T>, accept_message_from_main::LogToAcceptMessageFromMain<T>>,
@@ -41,6 +44,9 @@ pub struct Bridge<T: Transport> {
 	side_to_main_signatures:
 		RelayStream<LogStream<T>, side_to_main_signatures::LogToSideToMainSignatures<T>>,
 	state: State,
+	/// confirmed blocks on main and side after which the stream ends
+	catch_up_to: Option<(u64, u64)>,
+	metrics: Option<Metrics>,
 }
 
 impl<T: Transport> Bridge<T> {
@@ -48,12 +54,14 @@ impl<T: Transport> Bridge<T> {
 		initial_state: State,
 		main_contract: MainContract<T>,
 		side_contract: SideContract<T>,
+		journal: Journal,
 	) -> Self {
 		let accept_message_from_main = RelayStream::new(
 			main_contract.main_to_side_log_stream(initial_state.last_main_to_side_sign_at_block),
 			accept_message_from_main::LogToAcceptMessageFromMain {
 				main: main_contract.clone(),
 				side: side_contract.clone(),
+				journal: journal.clone(),
 			},
 		);
 
@@ -62,6 +70,7 @@ impl<T: Transport> Bridge<T> {
 				.side_to_main_sign_log_stream(initial_state.last_side_to_main_sign_at_block),
 			side_to_main_sign::LogToSideToMainSign {
 				side: side_contract.clone(),
+				journal: journal.clone(),
 			},
 		);
 
@@ -73,6 +82,7 @@ impl<T: Transport> Bridge<T> {
 			side_to_main_signatures::LogToSideToMainSignatures {
 				main: main_contract.clone(),
 				side: side_contract.clone(),
+				journal,
 			},
 		);
 
@@ -81,6 +91,59 @@ impl<T: Transport> Bridge<T> {
 			side_to_main_sign,
 			side_to_main_signatures,
 			state: initial_state,
+			catch_up_to: None,
+			metrics: None,
+		}
+	}
+
+	/// records the progress of the relays in `metrics`
+	pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+		self.metrics = Some(metrics);
+		self
+	}
+
+	fn update_metrics(&self) {
+		if let Some(ref metrics) = self.metrics {
+			metrics.relay_progress(
+				RelayKind::MainToSideSign,
+				self.state.last_main_to_side_sign_at_block,
+				self.accept_message_from_main.relays_in_flight(),
+			);
+			metrics.relay_progress(
+				RelayKind::SideToMainSign,
+				self.state.last_side_to_main_sign_at_block,
+				self.side_to_main_sign.relays_in_flight(),
+			);
+			metrics.relay_progress(
+				RelayKind::SideToMainSignatures,
+				self.state.last_side_to_main_signatures_at_block,
+				self.side_to_main_signatures.relays_in_flight(),
+			);
+		}
+	}
+
+	/// ends the stream once all relays have checked the blocks up to `main_block`
+	/// on main and `side_block` on side and all their relay futures have completed
+	pub fn until_caught_up(mut self, main_block: u64, side_block: u64) -> Self {
+		self.catch_up_to = Some((main_block, side_block));
+		self
+	}
+
+	/// the number of messages skipped so far because their relay failed
+	pub fn skipped_messages(&self) -> usize {
+		self.accept_message_from_main.skipped_messages()
+			+ self.side_to_main_sign.skipped_messages()
+			+ self.side_to_main_signatures.skipped_messages()
+	}
+
+	fn is_caught_up(&self) -> bool {
+		match self.catch_up_to {
+			Some((main_block, side_block)) => {
+				self.state.last_main_to_side_sign_at_block >= main_block
+					&& self.state.last_side_to_main_sign_at_block >= side_block
+					&& self.state.last_side_to_main_signatures_at_block >= side_block
+			}
+			None => false,
 		}
 	}
 }
@@ -91,6 +154,17 @@ impl<T: Transport> Stream for Bridge<T> {
 
 	fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
 		loop {
+			if self.is_caught_up() {
+				let (main_block, side_block) = self
+					.catch_up_to
+					.expect("only a bridge with targets can catch up; qed");
+				info!(
+					"caught up with block {} on main and block {} on side",
+					main_block, side_block
+				);
+				return Ok(Async::Ready(None));
+			}
+
 			let maybe_main_to_side_sign = try_maybe_stream!(self
 				.accept_message_from_main
 				.poll()
@@ -131,6 +205,8 @@ impl<T: Transport> Stream for Bridge<T> {
 				has_state_changed = true;
 			}
 
+			self.update_metrics();
+
 			if has_state_changed {
 				return Ok(Async::Ready(Some(self.state.clone())));
 			} else {
@@ -19,9 +19,12 @@
 use error::{Error, ResultExt};
 use ethereum_types::U256;
 use rustc_hex::FromHex;
+use std::cmp;
+use std::fmt;
 use std::fs;
 use std::io::Read;
-use std::path::Path;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 use toml;
 use web3::types::{Address, Bytes};
@@ -31,6 +34,21 @@ const DEFAULT_TIMEOUT: u64 = 5;
 
 const DEFAULT_CONFIRMATIONS: u32 = 12;
 
+const DEFAULT_HEALTH_CHECK_INTERVAL: u64 = 30;
+
+const DEFAULT_RESUBMIT_AFTER: u64 = 120;
+const DEFAULT_GAS_PRICE_BUMP_PERCENT: u32 = 20;
+
+const DEFAULT_GAS_PRICE_MULTIPLIER: f64 = 1.0;
+const DEFAULT_GAS_PRICE_BLOCKS: u64 = 20;
+const DEFAULT_GAS_PRICE_PERCENTILE: u8 = 60;
+
+const DEFAULT_GAS_MARGIN_PERCENT: u32 = 20;
+
+const DEFAULT_METRICS_POLL_INTERVAL: u64 = 15;
+const DEFAULT_READY_MAX_UNREACHABLE: u64 = 60;
+const DEFAULT_READY_MAX_LAG: u64 = 100;
+
 /// Application config.
 #[derive(Debug, PartialEq, Clone)]
 pub struct Config {
@@ -42,6 +60,10 @@ pub struct Config {
 	pub estimated_gas_cost_of_withdraw: U256,
 	pub max_total_main_contract_balance: U256,
 	pub max_single_deposit_value: U256,
+	/// sign locally with this key instead of relying on unlocked accounts
+	pub keystore: Option<KeystoreConfig>,
+	/// serve prometheus metrics if set
+	pub metrics: Option<MetricsConfig>,
 }
 
 impl Config {
@@ -58,6 +80,29 @@ impl Config {
 	}
 
 	fn from_load_struct(config: load::Config) -> Result<Config, Error> {
+		if config.keystore.is_some()
+			&& (config.main.chain_id.is_none() || config.side.chain_id.is_none())
+		{
+			bail!("`main.chain_id` and `side.chain_id` are required when `keystore` is set");
+		}
+
+		if config.keystore.is_none() {
+			if let Some(ref transactions) = config.transactions {
+				let relays = [
+					&transactions.deposit_relay,
+					&transactions.withdraw_confirm,
+					&transactions.withdraw_relay,
+				];
+				if relays
+					.iter()
+					.filter_map(|tx| tx.as_ref())
+					.any(|tx| tx.max_fee_per_gas.is_some())
+				{
+					bail!("`keystore` is required when `max_fee_per_gas` is set for relay transactions");
+				}
+			}
+		}
+
 		let result = Config {
 			address: config.address,
 			main: NodeConfig::from_load_struct(config.main)?,
@@ -66,13 +111,31 @@ impl Config {
 				accounts: config.authorities.accounts,
 				required_signatures: config.authorities.required_signatures,
 			},
-			txs: config
-				.transactions
-				.map(Transactions::from_load_struct)
-				.unwrap_or_default(),
+			txs: match config.transactions {
+				Some(transactions) => Transactions::from_load_struct(transactions)?,
+				None => Transactions::default(),
+			},
 			estimated_gas_cost_of_withdraw: config.estimated_gas_cost_of_withdraw,
 			max_total_main_contract_balance: config.max_total_main_contract_balance,
 			max_single_deposit_value: config.max_single_deposit_value,
+			keystore: config.keystore.map(|keystore| KeystoreConfig {
+				path: keystore.path,
+				password: keystore.password,
+			}),
+			metrics: config.metrics.map(|metrics| MetricsConfig {
+				address: metrics.address,
+				poll_interval: Duration::from_secs(
+					metrics
+						.poll_interval
+						.unwrap_or(DEFAULT_METRICS_POLL_INTERVAL),
+				),
+				ready_max_unreachable: Duration::from_secs(
+					metrics
+						.ready_max_unreachable
+						.unwrap_or(DEFAULT_READY_MAX_UNREACHABLE),
+				),
+				ready_max_lag: metrics.ready_max_lag.unwrap_or(DEFAULT_READY_MAX_LAG),
+			}),
 		};
 
 		Ok(result)
@@ -82,10 +145,27 @@ impl Config {
 #[derive(Debug, PartialEq, Clone)]
 pub struct NodeConfig {
 	pub contract: ContractConfig,
-	pub http: String,
+	/// endpoints of the node in priority order. never empty.
+	/// requests fail over to the next endpoint if one stops responding
+	pub endpoints: Vec<NodeEndpoint>,
+	/// websocket url of the same node. if set new blocks are
+	/// received through a subscription instead of polling `endpoints`
+	pub ws: Option<String>,
 	pub request_timeout: Duration,
 	pub poll_interval: Duration,
 	pub required_confirmations: u32,
+	/// EIP-155 chain id used when signing transactions locally
+	pub chain_id: Option<u64>,
+	/// relay transactions still pending after this long get re-submitted
+	/// with a bumped gas price
+	pub resubmit_after: Duration,
+	/// gas price increase in percent for every re-submission
+	pub gas_price_bump_percent: u32,
+	/// maximum number of blocks requested in one `eth_getLogs` call.
+	/// `None` requests all new blocks at once
+	pub max_log_block_range: Option<u64>,
+	/// how often the first endpoint is checked while a fallback is in use
+	pub health_check_interval: Duration,
 }
 
 impl NodeConfig {
@@ -104,16 +184,67 @@ impl NodeConfig {
 					Bytes(read.from_hex()?)
 				},
 			},
-			http: node.http,
+			endpoints: {
+				let mut endpoints = vec![NodeEndpoint::from_load_struct(node.http, node.ipc)?];
+				for fallback in node.fallback.unwrap_or_default() {
+					endpoints.push(NodeEndpoint::from_load_struct(fallback.http, fallback.ipc)?);
+				}
+				endpoints
+			},
+			ws: node.ws,
 			request_timeout: Duration::from_secs(node.request_timeout.unwrap_or(DEFAULT_TIMEOUT)),
 			poll_interval: Duration::from_secs(node.poll_interval.unwrap_or(DEFAULT_POLL_INTERVAL)),
 			required_confirmations: node.required_confirmations.unwrap_or(DEFAULT_CONFIRMATIONS),
+			chain_id: node.chain_id,
+			resubmit_after: Duration::from_secs(
+				node.resubmit_after.unwrap_or(DEFAULT_RESUBMIT_AFTER),
+			),
+			gas_price_bump_percent: node
+				.gas_price_bump_percent
+				.unwrap_or(DEFAULT_GAS_PRICE_BUMP_PERCENT),
+			max_log_block_range: node.max_log_block_range,
+			health_check_interval: Duration::from_secs(
+				node.health_check_interval
+					.unwrap_or(DEFAULT_HEALTH_CHECK_INTERVAL),
+			),
 		};
 
+		if result.max_log_block_range == Some(0) {
+			bail!("`max_log_block_range` must be at least 1");
+		}
+
 		Ok(result)
 	}
 }
 
+/// how the bridge talks to a node
+#[derive(Debug, PartialEq, Clone)]
+pub enum NodeEndpoint {
+	/// url of the http json rpc server
+	Http(String),
+	/// path of the ipc socket. for nodes on the same host
+	Ipc(PathBuf),
+}
+
+impl NodeEndpoint {
+	fn from_load_struct(http: Option<String>, ipc: Option<PathBuf>) -> Result<Self, Error> {
+		match (http, ipc) {
+			(Some(http), None) => Ok(NodeEndpoint::Http(http)),
+			(None, Some(ipc)) => Ok(NodeEndpoint::Ipc(ipc)),
+			_ => bail!("exactly one of `http` and `ipc` must be set"),
+		}
+	}
+}
+
+impl fmt::Display for NodeEndpoint {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			NodeEndpoint::Http(ref url) => write!(f, "{}", url),
+			NodeEndpoint::Ipc(ref path) => write!(f, "{}", path.display()),
+		}
+	}
+}
+
 #[derive(Debug, PartialEq, Default, Clone)]
 pub struct Transactions {
 	pub main_deploy: TransactionConfig,
@@ -124,44 +255,159 @@ pub struct Transactions {
 }
 
 impl Transactions {
-	fn from_load_struct(cfg: load::Transactions) -> Self {
-		Transactions {
+	fn from_load_struct(cfg: load::Transactions) -> Result<Self, Error> {
+		let result = Transactions {
 			main_deploy: cfg
 				.main_deploy
 				.map(TransactionConfig::from_load_struct)
+				.transpose()
+				.chain_err(|| "Invalid `transactions.main_deploy`")?
 				.unwrap_or_default(),
 			side_deploy: cfg
 				.side_deploy
 				.map(TransactionConfig::from_load_struct)
+				.transpose()
+				.chain_err(|| "Invalid `transactions.side_deploy`")?
 				.unwrap_or_default(),
 			deposit_relay: cfg
 				.deposit_relay
 				.map(TransactionConfig::from_load_struct)
+				.transpose()
+				.chain_err(|| "Invalid `transactions.deposit_relay`")?
 				.unwrap_or_default(),
 			withdraw_confirm: cfg
 				.withdraw_confirm
 				.map(TransactionConfig::from_load_struct)
+				.transpose()
+				.chain_err(|| "Invalid `transactions.withdraw_confirm`")?
 				.unwrap_or_default(),
 			withdraw_relay: cfg
 				.withdraw_relay
 				.map(TransactionConfig::from_load_struct)
+				.transpose()
+				.chain_err(|| "Invalid `transactions.withdraw_relay`")?
 				.unwrap_or_default(),
-		}
+		};
+
+		Ok(result)
 	}
 }
 
 #[derive(Debug, PartialEq, Default, Clone)]
 pub struct TransactionConfig {
 	pub gas: U256,
+	/// used by `GasPriceStrategy::Fixed`
 	pub gas_price: U256,
+	/// only used for relay transactions. deployments always use `gas_price`
+	pub gas_price_strategy: GasPriceStrategy,
+	/// floor for gas prices from the `gas_price_strategy`
+	pub min_gas_price: U256,
+	/// ceiling for gas prices from the `gas_price_strategy` and re-submissions.
+	/// also caps bumps of `max_fee_per_gas`.
+	/// defaults to `gas_price` for `GasPriceStrategy::Fixed` which disables re-submission
+	pub max_gas_price: U256,
+	/// EIP-1559 fees. used instead of the `gas_price_strategy` on chains with a base fee.
+	/// require a keystore since nodes can't sign type 2 transactions for us
+	pub max_fee_per_gas: Option<U256>,
+	pub max_priority_fee_per_gas: Option<U256>,
+	/// only used for relay transactions. estimate the gas limit with `eth_estimateGas`
+	/// and add `gas_margin_percent`. `gas` becomes the upper limit
+	pub estimate_gas: bool,
+	pub gas_margin_percent: u32,
 }
 
 impl TransactionConfig {
-	fn from_load_struct(cfg: load::TransactionConfig) -> Self {
-		TransactionConfig {
-			gas: cfg.gas,
-			gas_price: cfg.gas_price,
+	fn from_load_struct(cfg: load::TransactionConfig) -> Result<Self, Error> {
+		let gas_price_strategy = match cfg.gas_price_oracle {
+			None | Some(load::GasPriceOracle::Fixed) => GasPriceStrategy::Fixed,
+			Some(load::GasPriceOracle::Node) => GasPriceStrategy::Node {
+				multiplier: cfg
+					.gas_price_multiplier
+					.unwrap_or(DEFAULT_GAS_PRICE_MULTIPLIER),
+			},
+			Some(load::GasPriceOracle::Percentile) => GasPriceStrategy::Percentile {
+				blocks: cfg.gas_price_blocks.unwrap_or(DEFAULT_GAS_PRICE_BLOCKS),
+				percentile: cfg
+					.gas_price_percentile
+					.unwrap_or(DEFAULT_GAS_PRICE_PERCENTILE),
+			},
+		};
+
+		// a missing `gas_price` must not silently become 0
+		let gas_price = match (cfg.gas_price, &gas_price_strategy) {
+			(Some(gas_price), _) => gas_price,
+			(None, GasPriceStrategy::Fixed) => {
+				bail!("`gas_price` is required unless `gas_price_oracle` is set")
+			}
+			(None, _) => U256::zero(),
+		};
+
+		let max_gas_price = match gas_price_strategy {
+			GasPriceStrategy::Fixed => cmp::max(
+				cmp::max(gas_price, cfg.max_fee_per_gas.unwrap_or_default()),
+				cfg.max_gas_price,
+			),
+			_ if cfg.max_gas_price.is_zero() => {
+				bail!("`max_gas_price` is required when `gas_price_oracle` is set")
+			}
+			_ => cfg.max_gas_price,
+		};
+
+		match gas_price_strategy {
+			GasPriceStrategy::Node { multiplier } if multiplier <= 0.0 => {
+				bail!("`gas_price_multiplier` must be positive")
+			}
+			GasPriceStrategy::Percentile { blocks, .. } if blocks == 0 => {
+				bail!("`gas_price_blocks` must be at least 1")
+			}
+			GasPriceStrategy::Percentile { percentile, .. } if percentile > 100 => {
+				bail!("`gas_price_percentile` must be at most 100")
+			}
+			_ => {}
+		}
+
+		if cfg.min_gas_price > max_gas_price {
+			bail!("`min_gas_price` must not exceed `max_gas_price`");
+		}
+
+		match (cfg.max_fee_per_gas, cfg.max_priority_fee_per_gas) {
+			(Some(max_fee_per_gas), Some(max_priority_fee_per_gas)) => {
+				if max_priority_fee_per_gas > max_fee_per_gas {
+					bail!("`max_priority_fee_per_gas` must not exceed `max_fee_per_gas`");
+				}
+			}
+			(None, None) => {}
+			_ => bail!("`max_fee_per_gas` and `max_priority_fee_per_gas` must be set together"),
 		}
+
+		Ok(TransactionConfig {
+			gas: cfg.gas,
+			gas_price,
+			gas_price_strategy,
+			min_gas_price: cfg.min_gas_price,
+			max_gas_price,
+			max_fee_per_gas: cfg.max_fee_per_gas,
+			max_priority_fee_per_gas: cfg.max_priority_fee_per_gas,
+			estimate_gas: cfg.estimate_gas,
+			gas_margin_percent: cfg.gas_margin_percent.unwrap_or(DEFAULT_GAS_MARGIN_PERCENT),
+		})
+	}
+}
+
+/// how the gas price of a relay transaction is determined
+#[derive(Debug, PartialEq, Clone)]
+pub enum GasPriceStrategy {
+	/// always `gas_price`
+	Fixed,
+	/// `eth_gasPrice` of the node times `multiplier`
+	Node { multiplier: f64 },
+	/// `percentile` of the gas prices of all transactions in the last `blocks` blocks
+	Percentile { blocks: u64, percentile: u8 },
+}
+
+impl Default for GasPriceStrategy {
+	fn default() -> Self {
+		GasPriceStrategy::Fixed
 	}
 }
 
@@ -170,6 +416,26 @@ pub struct ContractConfig {
 	pub bin: Bytes,
 }
 
+/// encrypted json keystore file containing the authority key
+/// and a file containing the password to decrypt it
+#[derive(Debug, PartialEq, Clone)]
+pub struct KeystoreConfig {
+	pub path: PathBuf,
+	pub password: PathBuf,
+}
+
+/// address to serve metrics at and how often chain heads and
+/// authority balances are polled for them
+#[derive(Debug, PartialEq, Clone)]
+pub struct MetricsConfig {
+	pub address: SocketAddr,
+	pub poll_interval: Duration,
+	/// `/ready` fails once the node of a chain has not responded for this long
+	pub ready_max_unreachable: Duration,
+	/// `/ready` fails once a relay lags more blocks behind the confirmed head of its chain
+	pub ready_max_lag: u64,
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct Authorities {
 	pub accounts: Vec<Address>,
@@ -181,7 +447,8 @@ pub struct Authorities {
 /// in application.
 mod load {
 	use ethereum_types::U256;
-	use helpers::deserialize_u256;
+	use helpers::{deserialize_opt_u256, deserialize_u256};
+	use std::net::SocketAddr;
 	use std::path::PathBuf;
 	use web3::types::Address;
 
@@ -199,16 +466,33 @@ mod load {
 		pub max_total_main_contract_balance: U256,
 		#[serde(deserialize_with = "deserialize_u256")]
 		pub max_single_deposit_value: U256,
+		pub keystore: Option<KeystoreConfig>,
+		pub metrics: Option<MetricsConfig>,
 	}
 
 	#[derive(Deserialize)]
 	#[serde(deny_unknown_fields)]
 	pub struct NodeConfig {
 		pub contract: ContractConfig,
-		pub http: String,
+		pub http: Option<String>,
+		pub ipc: Option<PathBuf>,
+		pub fallback: Option<Vec<NodeEndpoint>>,
+		pub ws: Option<String>,
 		pub request_timeout: Option<u64>,
 		pub poll_interval: Option<u64>,
 		pub required_confirmations: Option<u32>,
+		pub chain_id: Option<u64>,
+		pub resubmit_after: Option<u64>,
+		pub gas_price_bump_percent: Option<u32>,
+		pub max_log_block_range: Option<u64>,
+		pub health_check_interval: Option<u64>,
+	}
+
+	#[derive(Deserialize)]
+	#[serde(deny_unknown_fields)]
+	pub struct NodeEndpoint {
+		pub http: Option<String>,
+		pub ipc: Option<PathBuf>,
 	}
 
 	#[derive(Deserialize)]
@@ -226,8 +510,31 @@ mod load {
 	pub struct TransactionConfig {
 		#[serde(deserialize_with = "deserialize_u256")]
 		pub gas: U256,
-		#[serde(deserialize_with = "deserialize_u256")]
-		pub gas_price: U256,
+		#[serde(default, deserialize_with = "deserialize_opt_u256")]
+		pub gas_price: Option<U256>,
+		pub gas_price_oracle: Option<GasPriceOracle>,
+		pub gas_price_multiplier: Option<f64>,
+		pub gas_price_blocks: Option<u64>,
+		pub gas_price_percentile: Option<u8>,
+		#[serde(default, deserialize_with = "deserialize_u256")]
+		pub min_gas_price: U256,
+		#[serde(default, deserialize_with = "deserialize_u256")]
+		pub max_gas_price: U256,
+		#[serde(default, deserialize_with = "deserialize_opt_u256")]
+		pub max_fee_per_gas: Option<U256>,
+		#[serde(default, deserialize_with = "deserialize_opt_u256")]
+		pub max_priority_fee_per_gas: Option<U256>,
+		#[serde(default)]
+		pub estimate_gas: bool,
+		pub gas_margin_percent: Option<u32>,
+	}
+
+	#[derive(Deserialize)]
+	#[serde(rename_all = "snake_case")]
+	pub enum GasPriceOracle {
+		Fixed,
+		Node,
+		Percentile,
 	}
 
 	#[derive(Deserialize)]
@@ -236,6 +543,22 @@ mod load {
 		pub bin: PathBuf,
 	}
 
+	#[derive(Deserialize)]
+	#[serde(deny_unknown_fields)]
+	pub struct KeystoreConfig {
+		pub path: PathBuf,
+		pub password: PathBuf,
+	}
+
+	#[derive(Deserialize)]
+	#[serde(deny_unknown_fields)]
+	pub struct MetricsConfig {
+		pub address: SocketAddr,
+		pub poll_interval: Option<u64>,
+		pub ready_max_unreachable: Option<u64>,
+		pub ready_max_lag: Option<u64>,
+	}
+
 	#[derive(Deserialize)]
 	#[serde(deny_unknown_fields)]
 	pub struct Authorities {
@@ -246,7 +569,10 @@ mod load {
 
 #[cfg(test)]
 mod tests {
-	use super::{Authorities, Config, ContractConfig, NodeConfig, TransactionConfig, Transactions};
+	use super::{
+		Authorities, Config, ContractConfig, GasPriceStrategy, KeystoreConfig, MetricsConfig,
+		NodeConfig, NodeEndpoint, TransactionConfig, Transactions,
+	};
 	use ethereum_types::U256;
 	use rustc_hex::FromHex;
 	use std::time::Duration;
@@ -261,8 +587,12 @@ max_single_deposit_value = "1000000000000000000"
 
 [main]
 http = "http://localhost:8545"
+ws = "ws://localhost:8546"
 poll_interval = 2
 required_confirmations = 100
+resubmit_after = 60
+gas_price_bump_percent = 10
+max_log_block_range = 1000
 
 [main.contract]
 bin = "../compiled_contracts/Main.bin"
@@ -283,13 +613,17 @@ required_signatures = 2
 
 [transactions]
 main_deploy = { gas = "20", gas_price = "0" }
+withdraw_relay = { gas = "20", gas_price = "1000", max_gas_price = "5000" }
+deposit_relay = { gas = "30", gas_price_oracle = "node", gas_price_multiplier = 1.5, min_gas_price = "100", max_gas_price = "9000" }
+withdraw_confirm = { gas = "40", gas_price_oracle = "percentile", gas_price_percentile = 80, max_gas_price = "9000", estimate_gas = true, gas_margin_percent = 30 }
 "#;
 
 		let mut expected = Config {
 			address: "1B68Cb0B50181FC4006Ce572cF346e596E51818b".parse().unwrap(),
 			txs: Transactions::default(),
 			main: NodeConfig {
-				http: "http://localhost:8545".into(),
+				endpoints: vec![NodeEndpoint::Http("http://localhost:8545".into())],
+				ws: Some("ws://localhost:8546".into()),
 				contract: ContractConfig {
 					bin: include_str!("../../compiled_contracts/Main.bin")
 						.from_hex::<Vec<u8>>()
@@ -299,6 +633,11 @@ main_deploy = { gas = "20", gas_price = "0" }
 				poll_interval: Duration::from_secs(2),
 				request_timeout: Duration::from_secs(5),
 				required_confirmations: 100,
+				chain_id: None,
+				resubmit_after: Duration::from_secs(60),
+				gas_price_bump_percent: 10,
+				max_log_block_range: Some(1000),
+				health_check_interval: Duration::from_secs(30),
 			},
 			side: NodeConfig {
 				contract: ContractConfig {
@@ -307,10 +646,16 @@ main_deploy = { gas = "20", gas_price = "0" }
 						.unwrap()
 						.into(),
 				},
-				http: "http://localhost:8546".into(),
+				endpoints: vec![NodeEndpoint::Http("http://localhost:8546".into())],
+				ws: None,
 				poll_interval: Duration::from_secs(1),
 				request_timeout: Duration::from_secs(5),
 				required_confirmations: 12,
+				chain_id: None,
+				resubmit_after: Duration::from_secs(120),
+				gas_price_bump_percent: 20,
+				max_log_block_range: None,
+				health_check_interval: Duration::from_secs(30),
 			},
 			authorities: Authorities {
 				accounts: vec![
@@ -323,11 +668,56 @@ main_deploy = { gas = "20", gas_price = "0" }
 			estimated_gas_cost_of_withdraw: U256::from_dec_str("100000").unwrap(),
 			max_total_main_contract_balance: U256::from_dec_str("10000000000000000000").unwrap(),
 			max_single_deposit_value: U256::from_dec_str("1000000000000000000").unwrap(),
+			keystore: None,
+			metrics: None,
 		};
 
 		expected.txs.main_deploy = TransactionConfig {
 			gas: 20.into(),
 			gas_price: 0.into(),
+			gas_price_strategy: GasPriceStrategy::Fixed,
+			min_gas_price: 0.into(),
+			max_gas_price: 0.into(),
+			max_fee_per_gas: None,
+			max_priority_fee_per_gas: None,
+			estimate_gas: false,
+			gas_margin_percent: 20,
+		};
+		expected.txs.withdraw_relay = TransactionConfig {
+			gas: 20.into(),
+			gas_price: 1000.into(),
+			gas_price_strategy: GasPriceStrategy::Fixed,
+			min_gas_price: 0.into(),
+			max_gas_price: 5000.into(),
+			max_fee_per_gas: None,
+			max_priority_fee_per_gas: None,
+			estimate_gas: false,
+			gas_margin_percent: 20,
+		};
+		expected.txs.deposit_relay = TransactionConfig {
+			gas: 30.into(),
+			gas_price: 0.into(),
+			gas_price_strategy: GasPriceStrategy::Node { multiplier: 1.5 },
+			min_gas_price: 100.into(),
+			max_gas_price: 9000.into(),
+			max_fee_per_gas: None,
+			max_priority_fee_per_gas: None,
+			estimate_gas: false,
+			gas_margin_percent: 20,
+		};
+		expected.txs.withdraw_confirm = TransactionConfig {
+			gas: 40.into(),
+			gas_price: 0.into(),
+			gas_price_strategy: GasPriceStrategy::Percentile {
+				blocks: 20,
+				percentile: 80,
+			},
+			min_gas_price: 0.into(),
+			max_gas_price: 9000.into(),
+			max_fee_per_gas: None,
+			max_priority_fee_per_gas: None,
+			estimate_gas: true,
+			gas_margin_percent: 30,
 		};
 
 		let config = Config::load_from_str(toml).unwrap();
@@ -366,7 +756,8 @@ required_signatures = 2
 			address: "0000000000000000000000000000000000000001".parse().unwrap(),
 			txs: Transactions::default(),
 			main: NodeConfig {
-				http: "".into(),
+				endpoints: vec![NodeEndpoint::Http("".into())],
+				ws: None,
 				contract: ContractConfig {
 					bin: include_str!("../../compiled_contracts/Main.bin")
 						.from_hex::<Vec<u8>>()
@@ -376,9 +767,15 @@ required_signatures = 2
 				poll_interval: Duration::from_secs(1),
 				request_timeout: Duration::from_secs(5),
 				required_confirmations: 12,
+				chain_id: None,
+				resubmit_after: Duration::from_secs(120),
+				gas_price_bump_percent: 20,
+				max_log_block_range: None,
+				health_check_interval: Duration::from_secs(30),
 			},
 			side: NodeConfig {
-				http: "".into(),
+				endpoints: vec![NodeEndpoint::Http("".into())],
+				ws: None,
 				contract: ContractConfig {
 					bin: include_str!("../../compiled_contracts/Side.bin")
 						.from_hex::<Vec<u8>>()
@@ -388,6 +785,11 @@ required_signatures = 2
 				poll_interval: Duration::from_secs(1),
 				request_timeout: Duration::from_secs(5),
 				required_confirmations: 12,
+				chain_id: None,
+				resubmit_after: Duration::from_secs(120),
+				gas_price_bump_percent: 20,
+				max_log_block_range: None,
+				health_check_interval: Duration::from_secs(30),
 			},
 			authorities: Authorities {
 				accounts: vec![
@@ -400,9 +802,337 @@ required_signatures = 2
 			estimated_gas_cost_of_withdraw: U256::from_dec_str("200000000").unwrap(),
 			max_total_main_contract_balance: U256::from_dec_str("10000000000000000000").unwrap(),
 			max_single_deposit_value: U256::from_dec_str("1000000000000000000").unwrap(),
+			keystore: None,
+			metrics: None,
 		};
 
 		let config = Config::load_from_str(toml).unwrap();
 		assert_eq!(expected, config);
 	}
+
+	#[test]
+	fn load_ipc_setup_from_str() {
+		let toml = r#"
+address = "0x0000000000000000000000000000000000000001"
+estimated_gas_cost_of_withdraw = "200000000"
+max_total_main_contract_balance = "10000000000000000000"
+max_single_deposit_value = "1000000000000000000"
+
+[main]
+ipc = "/home/parity/.local/share/io.parity.ethereum/jsonrpc.ipc"
+
+[main.contract]
+bin = "../compiled_contracts/Main.bin"
+
+[side]
+http = "http://localhost:8546"
+
+[side.contract]
+bin = "../compiled_contracts/Side.bin"
+
+[authorities]
+accounts = [
+	"0x0000000000000000000000000000000000000001",
+]
+required_signatures = 1
+"#;
+
+		let config = Config::load_from_str(toml).unwrap();
+		assert_eq!(
+			config.main.endpoints,
+			vec![NodeEndpoint::Ipc(
+				"/home/parity/.local/share/io.parity.ethereum/jsonrpc.ipc".into()
+			)]
+		);
+		assert_eq!(
+			config.side.endpoints,
+			vec![NodeEndpoint::Http("http://localhost:8546".into())]
+		);
+
+		let both = toml.replace(
+			"[side]\n",
+			"[side]\nipc = \"/home/parity/.local/share/io.parity.ethereum/jsonrpc.ipc\"\n",
+		);
+		assert!(Config::load_from_str(&both).is_err());
+	}
+
+	#[test]
+	fn load_fallback_setup_from_str() {
+		let toml = r#"
+address = "0x0000000000000000000000000000000000000001"
+estimated_gas_cost_of_withdraw = "200000000"
+max_total_main_contract_balance = "10000000000000000000"
+max_single_deposit_value = "1000000000000000000"
+
+[main]
+http = "http://localhost:8545"
+fallback = [
+	{ http = "https://mainnet.example.com" },
+	{ ipc = "/home/parity/.local/share/io.parity.ethereum/jsonrpc.ipc" },
+]
+health_check_interval = 60
+
+[main.contract]
+bin = "../compiled_contracts/Main.bin"
+
+[side]
+http = "http://localhost:8546"
+
+[side.contract]
+bin = "../compiled_contracts/Side.bin"
+
+[authorities]
+accounts = [
+	"0x0000000000000000000000000000000000000001",
+]
+required_signatures = 1
+"#;
+
+		let config = Config::load_from_str(toml).unwrap();
+		assert_eq!(
+			config.main.endpoints,
+			vec![
+				NodeEndpoint::Http("http://localhost:8545".into()),
+				NodeEndpoint::Http("https://mainnet.example.com".into()),
+				NodeEndpoint::Ipc(
+					"/home/parity/.local/share/io.parity.ethereum/jsonrpc.ipc".into()
+				),
+			]
+		);
+		assert_eq!(config.main.health_check_interval, Duration::from_secs(60));
+		assert_eq!(
+			config.side.endpoints,
+			vec![NodeEndpoint::Http("http://localhost:8546".into())]
+		);
+
+		let empty_fallback = toml.replace("{ http = \"https://mainnet.example.com\" },", "{},");
+		assert!(Config::load_from_str(&empty_fallback).is_err());
+	}
+
+	#[test]
+	fn load_keystore_setup_from_str() {
+		let toml = r#"
+address = "0x0000000000000000000000000000000000000001"
+estimated_gas_cost_of_withdraw = "200000000"
+max_total_main_contract_balance = "10000000000000000000"
+max_single_deposit_value = "1000000000000000000"
+
+[main]
+http = ""
+chain_id = 1
+
+[main.contract]
+bin = "../compiled_contracts/Main.bin"
+
+[side]
+http = ""
+chain_id = 77
+
+[side.contract]
+bin = "../compiled_contracts/Side.bin"
+
+[authorities]
+accounts = [
+	"0x0000000000000000000000000000000000000001",
+]
+required_signatures = 1
+
+[keystore]
+path = "keys/authority.json"
+password = "password.txt"
+"#;
+
+		let config = Config::load_from_str(toml).unwrap();
+		assert_eq!(config.main.chain_id, Some(1));
+		assert_eq!(config.side.chain_id, Some(77));
+		assert_eq!(
+			config.keystore,
+			Some(KeystoreConfig {
+				path: "keys/authority.json".into(),
+				password: "password.txt".into(),
+			})
+		);
+	}
+
+	#[test]
+	fn load_keystore_setup_without_chain_id_fails() {
+		let toml = r#"
+address = "0x0000000000000000000000000000000000000001"
+estimated_gas_cost_of_withdraw = "200000000"
+max_total_main_contract_balance = "10000000000000000000"
+max_single_deposit_value = "1000000000000000000"
+
+[main]
+http = ""
+
+[main.contract]
+bin = "../compiled_contracts/Main.bin"
+
+[side]
+http = ""
+
+[side.contract]
+bin = "../compiled_contracts/Side.bin"
+
+[authorities]
+accounts = [
+	"0x0000000000000000000000000000000000000001",
+]
+required_signatures = 1
+
+[keystore]
+path = "keys/authority.json"
+password = "password.txt"
+"#;
+
+		assert!(Config::load_from_str(toml).is_err());
+	}
+
+	#[test]
+	fn load_gas_price_oracle_without_max_gas_price_fails() {
+		let toml = r#"
+address = "0x0000000000000000000000000000000000000001"
+estimated_gas_cost_of_withdraw = "200000000"
+max_total_main_contract_balance = "10000000000000000000"
+max_single_deposit_value = "1000000000000000000"
+
+[main]
+http = ""
+
+[main.contract]
+bin = "../compiled_contracts/Main.bin"
+
+[side]
+http = ""
+
+[side.contract]
+bin = "../compiled_contracts/Side.bin"
+
+[authorities]
+accounts = [
+	"0x0000000000000000000000000000000000000001",
+]
+required_signatures = 1
+
+[transactions]
+withdraw_relay = { gas = "20", gas_price_oracle = "node" }
+"#;
+
+		assert!(Config::load_from_str(toml).is_err());
+
+		// the fixed gas price must be set explicitly. `"0"` is fine for free transactions
+		let without_gas_price = toml.replace(", gas_price_oracle = \"node\"", "");
+		assert!(Config::load_from_str(&without_gas_price).is_err());
+		let free = toml.replace(", gas_price_oracle = \"node\"", ", gas_price = \"0\"");
+		assert!(Config::load_from_str(&free).is_ok());
+	}
+
+	#[test]
+	fn load_eip1559_fees_from_str() {
+		let toml = r#"
+address = "0x0000000000000000000000000000000000000001"
+estimated_gas_cost_of_withdraw = "200000000"
+max_total_main_contract_balance = "10000000000000000000"
+max_single_deposit_value = "1000000000000000000"
+
+[main]
+http = ""
+chain_id = 1
+
+[main.contract]
+bin = "../compiled_contracts/Main.bin"
+
+[side]
+http = ""
+chain_id = 77
+
+[side.contract]
+bin = "../compiled_contracts/Side.bin"
+
+[authorities]
+accounts = [
+	"0x0000000000000000000000000000000000000001",
+]
+required_signatures = 1
+
+[keystore]
+path = "keys/authority.json"
+password = "password.txt"
+
+[transactions]
+withdraw_relay = { gas = "20", gas_price = "1000", max_fee_per_gas = "3000", max_priority_fee_per_gas = "100" }
+"#;
+
+		let config = Config::load_from_str(toml).unwrap();
+		assert_eq!(config.txs.withdraw_relay.max_fee_per_gas, Some(3000.into()));
+		assert_eq!(
+			config.txs.withdraw_relay.max_priority_fee_per_gas,
+			Some(100.into())
+		);
+		assert_eq!(config.txs.withdraw_relay.max_gas_price, 3000.into());
+
+		// relay transactions with EIP-1559 fees need a keystore
+		let without_keystore = toml.replace(
+			"[keystore]\npath = \"keys/authority.json\"\npassword = \"password.txt\"\n",
+			"",
+		);
+		assert!(Config::load_from_str(&without_keystore).is_err());
+
+		let without_priority_fee = toml.replace(", max_priority_fee_per_gas = \"100\"", "");
+		assert!(Config::load_from_str(&without_priority_fee).is_err());
+	}
+
+	#[test]
+	fn load_metrics_setup_from_str() {
+		let toml = r#"
+address = "0x0000000000000000000000000000000000000001"
+estimated_gas_cost_of_withdraw = "200000000"
+max_total_main_contract_balance = "10000000000000000000"
+max_single_deposit_value = "1000000000000000000"
+
+[main]
+http = ""
+
+[main.contract]
+bin = "../compiled_contracts/Main.bin"
+
+[side]
+http = ""
+
+[side.contract]
+bin = "../compiled_contracts/Side.bin"
+
+[authorities]
+accounts = [
+	"0x0000000000000000000000000000000000000001",
+]
+required_signatures = 1
+
+[metrics]
+address = "127.0.0.1:9100"
+"#;
+
+		let config = Config::load_from_str(toml).unwrap();
+		assert_eq!(
+			config.metrics,
+			Some(MetricsConfig {
+				address: "127.0.0.1:9100".parse().unwrap(),
+				poll_interval: Duration::from_secs(15),
+				ready_max_unreachable: Duration::from_secs(60),
+				ready_max_lag: 100,
+			})
+		);
+
+		let with_options = toml.replace(
+			"address = \"127.0.0.1:9100\"\n",
+			"address = \"127.0.0.1:9100\"\npoll_interval = 5\nready_max_unreachable = 30\nready_max_lag = 20\n",
+		);
+		let metrics = Config::load_from_str(&with_options)
+			.unwrap()
+			.metrics
+			.unwrap();
+		assert_eq!(metrics.poll_interval, Duration::from_secs(5));
+		assert_eq!(metrics.ready_max_unreachable, Duration::from_secs(30));
+		assert_eq!(metrics.ready_max_lag, 20);
+	}
 }
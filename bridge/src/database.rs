@@ -14,18 +14,69 @@
 // You should have received a copy of the GNU General Public License
 // along with Parity-Bridge.  If not, see <http://www.gnu.org/licenses/>.
 
-//! concerning reading/writing `State` from/to toml file
+//! concerning reading/writing `State` from/to toml file or an embedded database
 
 use error::{Error, ErrorKind, ResultExt};
+use fs2::{self, FileExt};
+use journal::Journal;
+use sled;
 use std::io::{Read, Write};
 /// the state of a bridge node process and ways to persist it
 use std::path::{Path, PathBuf};
-use std::{fmt, fs, io, str};
+use std::{env, fmt, fs, io, process, str};
 use toml;
 use web3::types::{Address, TransactionReceipt};
 
+/// version of the database format written by this version of the bridge.
+/// to be incremented together with a new entry in `MIGRATIONS`
+/// whenever `State` changes
+pub const DATABASE_VERSION: u32 = 1;
+
+/// upgrades a database in the version of its index in `MIGRATIONS` to the next version
+type Migration = fn(&mut toml::value::Table) -> Result<(), Error>;
+
+const MIGRATIONS: [Migration; DATABASE_VERSION as usize] = [migrate_home_foreign_to_main_side];
+
+/// version 0 is every database without a `version`.
+/// these used `home` and `foreign` for what is now `main` and `side`
+fn migrate_home_foreign_to_main_side(table: &mut toml::value::Table) -> Result<(), Error> {
+	let renames = [
+		("home_contract_address", "main_contract_address"),
+		("foreign_contract_address", "side_contract_address"),
+		("home_deploy", "main_deployed_at_block"),
+		("foreign_deploy", "side_deployed_at_block"),
+		("checked_deposit_relay", "last_main_to_side_sign_at_block"),
+		(
+			"checked_withdraw_relay",
+			"last_side_to_main_signatures_at_block",
+		),
+		(
+			"checked_withdraw_confirm",
+			"last_side_to_main_sign_at_block",
+		),
+	];
+	for &(old, new) in renames.iter() {
+		if let Some(value) = table.remove(old) {
+			if table.contains_key(new) {
+				bail!("database contains both `{}` and `{}`", old, new);
+			}
+			table.insert(new.to_owned(), value);
+		}
+	}
+	Ok(())
+}
+
+/// `State` as it is persisted
+#[derive(Serialize)]
+struct VersionedState<'a> {
+	version: u32,
+	#[serde(flatten)]
+	state: &'a State,
+}
+
 /// bridge process state
 #[derive(Debug, PartialEq, Deserialize, Serialize, Default, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct State {
 	/// Address of home contract.
 	pub main_contract_address: Address,
@@ -79,16 +130,52 @@ impl State {
 impl State {
 	/// write state to a `std::io::write`
 	pub fn write<W: Write>(&self, mut write: W) -> Result<(), Error> {
-		let serialized = toml::to_string(self).expect("serialization can't fail. q.e.d.");
-		write.write_all(serialized.as_bytes())?;
+		write.write_all(self.to_toml().as_bytes())?;
 		write.flush()?;
 		Ok(())
 	}
+
+	/// serializes the state together with the current `DATABASE_VERSION`
+	pub fn to_toml(&self) -> String {
+		toml::to_string(&VersionedState {
+			version: DATABASE_VERSION,
+			state: self,
+		})
+		.expect("serialization can't fail; qed")
+	}
+
+	/// parses a state written by this or an older version of the bridge.
+	/// older versions are migrated. newer versions are refused
+	pub fn from_toml(toml: &str) -> Result<Self, Error> {
+		let mut table: toml::value::Table = toml::from_str(toml)?;
+		let version = match table.remove("version") {
+			None => 0,
+			Some(toml::Value::Integer(version)) if version >= 0 => version as u64,
+			Some(version) => bail!("invalid database version {}", version),
+		};
+		if version > u64::from(DATABASE_VERSION) {
+			bail!(
+				"database version {} is newer than version {} supported by this bridge. please upgrade the bridge",
+				version,
+				DATABASE_VERSION
+			);
+		}
+		for migration in &MIGRATIONS[version as usize..] {
+			migration(&mut table)?;
+		}
+		if version < u64::from(DATABASE_VERSION) {
+			info!(
+				"migrated database from version {} to {}",
+				version, DATABASE_VERSION
+			);
+		}
+		toml::Value::Table(table).try_into().map_err(Error::from)
+	}
 }
 
 impl fmt::Display for State {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-		f.write_str(&toml::to_string(self).expect("serialization can't fail; qed"))
+		f.write_str(&self.to_toml())
 	}
 }
 
@@ -99,30 +186,149 @@ pub trait Database {
 	fn write(&mut self, state: &State) -> Result<(), Error>;
 }
 
-/// `State` stored in a TOML file
+/// `path` with `suffix` appended to its file name
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+	let mut path = path.as_os_str().to_owned();
+	path.push(suffix);
+	path.into()
+}
+
+/// name of this host for `DatabaseLock`
+fn hostname() -> String {
+	fs::read_to_string("/proc/sys/kernel/hostname")
+		.or_else(|_| fs::read_to_string("/etc/hostname"))
+		.map(|hostname| hostname.trim().to_owned())
+		.or_else(|_| env::var("HOSTNAME"))
+		.or_else(|_| env::var("COMPUTERNAME"))
+		.unwrap_or_else(|_| "unknown".into())
+}
+
+/// exclusive advisory lock on a database (`<database>.lock`) that prevents
+/// two bridge processes from using the same database.
+/// the lock file names the process holding the lock.
+/// released when dropped or when the process exits.
+pub struct DatabaseLock {
+	_file: fs::File,
+}
+
+impl DatabaseLock {
+	/// fails if another process holds the lock for `database`
+	pub fn acquire(database: &Path) -> Result<Self, Error> {
+		let path = with_suffix(database, ".lock");
+		let mut file = fs::OpenOptions::new()
+			.read(true)
+			.write(true)
+			.create(true)
+			.open(&path)
+			.chain_err(|| format!("Cannot open lock file {:?}", path))?;
+
+		if let Err(err) = file.try_lock_exclusive() {
+			if err.raw_os_error() != fs2::lock_contended_error().raw_os_error() {
+				return Err(err).chain_err(|| format!("Cannot lock {:?}", path));
+			}
+			let mut holder = String::new();
+			let _ = file.read_to_string(&mut holder);
+			bail!(
+				"database {:?} is in use by another bridge process: {} (lock file {:?})",
+				database,
+				holder.trim(),
+				path
+			);
+		}
+
+		file.set_len(0)?;
+		write!(file, "pid {} on host {}", process::id(), hostname())?;
+		file.sync_all()?;
+		Ok(Self { _file: file })
+	}
+}
+
+/// `State` stored in a TOML file.
+/// writes go to a temporary file that is synced and then renamed over the
+/// database file so a crash never leaves a partially written database.
+/// the previous state is kept as a backup (`<filepath>.bak`) which is
+/// used on startup if the database file is missing or corrupted.
 pub struct TomlFileDatabase {
 	filepath: PathBuf,
 	state: State,
+	_lock: DatabaseLock,
 }
 
 impl TomlFileDatabase {
 	/// create `TomlFileDatabase` backed by file at `filepath`
 	pub fn from_path<P: AsRef<Path>>(filepath: P) -> Result<Self, Error> {
-		let mut file = match fs::File::open(&filepath) {
+		let filepath = filepath.as_ref();
+		let lock = DatabaseLock::acquire(filepath)?;
+		let state = Self::read_state(filepath)?;
+		Ok(Self {
+			filepath: filepath.to_path_buf(),
+			state,
+			_lock: lock,
+		})
+	}
+
+	/// reads the state at `filepath` (or its backup) without locking the database.
+	/// used to inspect the database of a running bridge
+	pub fn read_state<P: AsRef<Path>>(filepath: P) -> Result<State, Error> {
+		let filepath = filepath.as_ref();
+		let backup_path = with_suffix(filepath, ".bak");
+		match Self::read_state_file(filepath) {
+			Ok(state) => Ok(state),
+			Err(err) => {
+				if !backup_path.exists() {
+					return Err(err);
+				}
+				warn!(
+					"Cannot read database {:?}: {}. falling back to backup {:?}",
+					filepath, err, backup_path
+				);
+				Self::read_state_file(&backup_path)
+					.chain_err(|| format!("Cannot read backup database {:?}", backup_path))
+			}
+		}
+	}
+
+	fn read_state_file(filepath: &Path) -> Result<State, Error> {
+		let mut file = match fs::File::open(filepath) {
 			Ok(file) => file,
 			Err(ref err) if err.kind() == io::ErrorKind::NotFound => {
-				return Err(ErrorKind::MissingFile(format!("{:?}", filepath.as_ref())).into())
+				return Err(ErrorKind::MissingFile(format!("{:?}", filepath)).into())
 			}
 			Err(err) => return Err(err).chain_err(|| "Cannot open database"),
 		};
 
 		let mut buffer = String::new();
 		file.read_to_string(&mut buffer)?;
-		let state: State = toml::from_str(&buffer).chain_err(|| "Cannot parse database")?;
-		Ok(Self {
-			filepath: filepath.as_ref().to_path_buf(),
-			state,
-		})
+		State::from_toml(&buffer).chain_err(|| "Cannot parse database")
+	}
+
+	/// replaces the database file with one containing `self.state`
+	fn persist(&self) -> Result<(), Error> {
+		let tmp_path = with_suffix(&self.filepath, ".tmp");
+		let backup_path = with_suffix(&self.filepath, ".bak");
+
+		let mut file =
+			fs::File::create(&tmp_path).chain_err(|| format!("Cannot create {:?}", tmp_path))?;
+		self.state.write(&mut file)?;
+		file.sync_all()?;
+
+		if self.filepath.exists() {
+			fs::rename(&self.filepath, &backup_path)
+				.chain_err(|| format!("Cannot back up database to {:?}", backup_path))?;
+		}
+		fs::rename(&tmp_path, &self.filepath)
+			.chain_err(|| format!("Cannot replace database {:?}", self.filepath))?;
+
+		// make the renames durable
+		if let Some(dir) = self.filepath.parent() {
+			let dir = if dir.as_os_str().is_empty() {
+				Path::new(".")
+			} else {
+				dir
+			};
+			fs::File::open(dir)?.sync_all()?;
+		}
+		Ok(())
 	}
 }
 
@@ -134,14 +340,296 @@ impl Database for TomlFileDatabase {
 	fn write(&mut self, state: &State) -> Result<(), Error> {
 		if self.state != *state {
 			self.state = state.clone();
+			self.persist()?;
+		}
+		Ok(())
+	}
+}
+
+/// key of the `State` in a `SledDatabase`. stored as in a `TomlFileDatabase`
+const SLED_STATE_KEY: &[u8] = b"state";
+/// name of the tree holding the `Journal` in a `SledDatabase`
+const SLED_JOURNAL_TREE: &[u8] = b"journal";
+
+/// `State` and `Journal` stored in an embedded sled database.
+/// unlike `TomlFileDatabase` a write doesn't rewrite the whole file.
+/// sled lets only one process open a database so every written state is
+/// copied to `<path>.state` as well where it can be read without opening it
+pub struct SledDatabase {
+	path: PathBuf,
+	db: sled::Db,
+	state: State,
+	_lock: DatabaseLock,
+}
+
+impl SledDatabase {
+	/// open the sled database at `path` which must contain a `State`
+	pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+		if !path.as_ref().exists() {
+			return Err(ErrorKind::MissingFile(format!("{:?}", path.as_ref())).into());
+		}
+		let lock = DatabaseLock::acquire(path.as_ref())?;
+		let db = sled::open(&path).chain_err(|| "Cannot open database")?;
+		let state = match db.get(SLED_STATE_KEY)? {
+			Some(bytes) => str::from_utf8(&bytes)
+				.map_err(|err| Error::from(err.to_string()))
+				.and_then(State::from_toml)
+				.chain_err(|| "Cannot parse database")?,
+			None => bail!("database {:?} contains no state", path.as_ref()),
+		};
+		let database = Self {
+			path: path.as_ref().to_path_buf(),
+			db,
+			state,
+			_lock: lock,
+		};
+		// databases written by earlier versions have no copy yet
+		database.copy_state();
+		Ok(database)
+	}
+
+	/// reads the state of the sled database at `path` from its copy without
+	/// opening the database. used to inspect the database of a running bridge
+	pub fn read_state<P: AsRef<Path>>(path: P) -> Result<State, Error> {
+		TomlFileDatabase::read_state_file(&with_suffix(path.as_ref(), ".state"))
+			.chain_err(|| format!("Cannot read state of database {:?}", path.as_ref()))
+	}
+
+	/// create a sled database at `path` that contains `state`.
+	/// fails if there already is a database containing a state
+	pub fn create<P: AsRef<Path>>(path: P, state: &State) -> Result<Self, Error> {
+		let lock = DatabaseLock::acquire(path.as_ref())?;
+		let db = sled::open(&path).chain_err(|| "Cannot create database")?;
+		if db.contains_key(SLED_STATE_KEY)? {
+			bail!("database {:?} already contains a state", path.as_ref());
+		}
+		let mut database = Self {
+			path: path.as_ref().to_path_buf(),
+			db,
+			state: state.clone(),
+			_lock: lock,
+		};
+		database.persist()?;
+		Ok(database)
+	}
+
+	/// the relay journal stored in this database
+	pub fn journal(&self) -> Result<Journal, Error> {
+		Journal::open_tree(self.db.open_tree(SLED_JOURNAL_TREE)?)
+	}
+
+	fn persist(&mut self) -> Result<(), Error> {
+		self.db
+			.insert(SLED_STATE_KEY, self.state.to_toml().into_bytes())?;
+		self.db.flush()?;
+		self.copy_state();
+		Ok(())
+	}
+
+	/// replaces `<path>.state` with the current state.
+	/// the copy is only read by `read_state`. failing to write it must not stop relays
+	fn copy_state(&self) {
+		let copy_path = with_suffix(&self.path, ".state");
+		let tmp_path = with_suffix(&copy_path, ".tmp");
+		let result = fs::File::create(&tmp_path)
+			.map_err(Error::from)
+			.and_then(|mut file| {
+				self.state.write(&mut file)?;
+				file.sync_all()?;
+				Ok(())
+			})
+			.and_then(|_| fs::rename(&tmp_path, &copy_path).map_err(Error::from));
+		if let Err(err) = result {
+			warn!("Cannot write state copy {:?}: {}", copy_path, err);
+		}
+	}
+}
 
-			let file = fs::OpenOptions::new()
-				.write(true)
-				.create(true)
-				.open(&self.filepath)?;
+impl Database for SledDatabase {
+	fn read(&self) -> State {
+		self.state.clone()
+	}
 
-			self.state.write(file)?;
+	fn write(&mut self, state: &State) -> Result<(), Error> {
+		if self.state != *state {
+			self.state = state.clone();
+			self.persist()?;
 		}
 		Ok(())
 	}
 }
+
+/// creates a `SledDatabase` at `path` from the `TomlFileDatabase` at `toml_path`
+/// and the entries of `journal`
+pub fn import_toml_database<P: AsRef<Path>, Q: AsRef<Path>>(
+	toml_path: P,
+	journal: &Journal,
+	path: Q,
+) -> Result<SledDatabase, Error> {
+	let toml = TomlFileDatabase::from_path(toml_path)?;
+	let database = SledDatabase::create(path, &toml.read())?;
+	database.journal()?.import(journal.entries())?;
+	Ok(database)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use journal::{RelayKind, RelayStatus};
+	use tempfile::TempDir;
+	use web3::types::H256;
+
+	fn state() -> State {
+		State {
+			main_contract_address: "0000000000000000000000000000000000000001".parse().unwrap(),
+			side_contract_address: "0000000000000000000000000000000000000002".parse().unwrap(),
+			main_deployed_at_block: 10,
+			side_deployed_at_block: 20,
+			last_main_to_side_sign_at_block: 11,
+			last_side_to_main_signatures_at_block: 21,
+			last_side_to_main_sign_at_block: 22,
+		}
+	}
+
+	#[test]
+	fn test_state_toml_roundtrip() {
+		let toml = state().to_toml();
+		assert!(toml.starts_with(&format!("version = {}\n", DATABASE_VERSION)));
+		assert_eq!(State::from_toml(&toml).unwrap(), state());
+	}
+
+	#[test]
+	fn test_state_from_toml_migrates_home_foreign_database() {
+		let toml = r#"
+home_contract_address = "0x0000000000000000000000000000000000000001"
+foreign_contract_address = "0x0000000000000000000000000000000000000002"
+home_deploy = 10
+foreign_deploy = 20
+checked_deposit_relay = 11
+checked_withdraw_relay = 21
+checked_withdraw_confirm = 22
+"#;
+		assert_eq!(State::from_toml(toml).unwrap(), state());
+	}
+
+	#[test]
+	fn test_state_from_toml_refuses_newer_version() {
+		let toml = state().to_toml().replace(
+			&format!("version = {}", DATABASE_VERSION),
+			&format!("version = {}", DATABASE_VERSION + 1),
+		);
+		let message = State::from_toml(&toml).unwrap_err().to_string();
+		assert!(message.contains("please upgrade the bridge"));
+	}
+
+	#[test]
+	fn test_state_from_toml_refuses_unknown_fields() {
+		let toml = format!("{}checked_deposit_relay = 11\n", state().to_toml());
+		assert!(State::from_toml(&toml).is_err());
+	}
+
+	#[test]
+	fn test_toml_database_write_replaces_file_and_keeps_backup() {
+		let dir = TempDir::new().unwrap();
+		let path = dir.path().join("bridge.db");
+		let mut initial = state();
+		initial.last_main_to_side_sign_at_block = 1_000_000_000;
+		initial.write(fs::File::create(&path).unwrap()).unwrap();
+
+		TomlFileDatabase::from_path(&path)
+			.unwrap()
+			// serializes shorter than the initial state
+			.write(&state())
+			.unwrap();
+
+		assert_eq!(TomlFileDatabase::from_path(&path).unwrap().read(), state());
+		assert_eq!(
+			TomlFileDatabase::read_state_file(&dir.path().join("bridge.db.bak")).unwrap(),
+			initial
+		);
+		assert!(!dir.path().join("bridge.db.tmp").exists());
+	}
+
+	#[test]
+	fn test_toml_database_falls_back_to_backup() {
+		let dir = TempDir::new().unwrap();
+		let path = dir.path().join("bridge.db");
+		state().write(fs::File::create(&path).unwrap()).unwrap();
+
+		let mut next = state();
+		next.last_side_to_main_sign_at_block = 23;
+		TomlFileDatabase::from_path(&path)
+			.unwrap()
+			.write(&next)
+			.unwrap();
+
+		// crash halfway through writing the database file
+		fs::write(&path, "main_contract_address = \"0x00").unwrap();
+		assert_eq!(TomlFileDatabase::from_path(&path).unwrap().read(), state());
+
+		// crash between the renames
+		fs::remove_file(&path).unwrap();
+		assert_eq!(TomlFileDatabase::from_path(&path).unwrap().read(), state());
+
+		fs::remove_file(dir.path().join("bridge.db.bak")).unwrap();
+		match *TomlFileDatabase::from_path(&path).err().unwrap().kind() {
+			ErrorKind::MissingFile(_) => {}
+			ref kind => panic!("expected a missing file error, got {:?}", kind),
+		}
+	}
+
+	#[test]
+	fn test_database_lock_is_exclusive() {
+		let dir = TempDir::new().unwrap();
+		let path = dir.path().join("bridge.db");
+		state().write(fs::File::create(&path).unwrap()).unwrap();
+
+		let database = TomlFileDatabase::from_path(&path).unwrap();
+		let err = TomlFileDatabase::from_path(&path).err().unwrap();
+		let message = err.to_string();
+		assert!(message.contains("in use by another bridge process"));
+		assert!(message.contains(&format!("pid {}", process::id())));
+
+		drop(database);
+		assert!(TomlFileDatabase::from_path(&path).is_ok());
+	}
+
+	#[test]
+	fn test_import_toml_database_into_sled() {
+		let dir = TempDir::new().unwrap();
+		let toml_path = dir.path().join("bridge.db");
+		state()
+			.write(fs::File::create(&toml_path).unwrap())
+			.unwrap();
+
+		let journal = Journal::in_memory();
+		let tx_hash = H256::from_low_u64_be(1);
+		journal
+			.relay(RelayKind::MainToSideSign, tx_hash, 0)
+			.mined(H256::from_low_u64_be(2));
+
+		let sled_path = dir.path().join("bridge.sled");
+		{
+			let mut database = import_toml_database(&toml_path, &journal, &sled_path).unwrap();
+			assert_eq!(database.read(), state());
+
+			let mut next = state();
+			next.last_main_to_side_sign_at_block = 12;
+			database.write(&next).unwrap();
+			// readable while the database is in use
+			assert_eq!(SledDatabase::read_state(&sled_path).unwrap(), next);
+		}
+
+		{
+			let database = SledDatabase::from_path(&sled_path).unwrap();
+			assert_eq!(database.read().last_main_to_side_sign_at_block, 12);
+			let entries = database.journal().unwrap().entries();
+			assert_eq!(entries.len(), 1);
+			assert_eq!(entries[0].tx_hash, tx_hash);
+			assert_eq!(entries[0].status, RelayStatus::Mined);
+		}
+
+		// importing again must not overwrite the existing state
+		assert!(import_toml_database(&toml_path, &journal, &sled_path).is_err());
+	}
+}
@@ -16,24 +16,70 @@
 
 //! concerning deployment of the bridge contracts
 
-use config::Config;
+use config::{Config, NodeConfig, TransactionConfig};
 use contracts;
 use error::{self, ResultExt};
 use futures::{Future, Poll};
+use gas_limit::GasLimit;
+use gas_price::GasPriceOracle;
+use helpers::AsyncTransaction;
+use nonce_manager::NonceManager;
 use rustc_hex::ToHex;
-use send_tx_with_receipt::{SendTransactionWithReceipt, SendTransactionWithReceiptOptions};
+use send_tx_with_receipt::{TrackedTransaction, TrackedTransactionOptions};
 use std::fs;
 use std::fs::File;
 use std::io::Write;
 use std::path::Path;
-use web3::types::{TransactionReceipt, TransactionRequest};
+use web3::types::{Address, TransactionReceipt, TransactionRequest};
 use web3::Transport;
 
+/// transaction from `address` that creates a contract with `data` on the node
+/// of `node`. signed by the node and tracked until it has the required confirmations
+fn deploy_transaction<T: Transport + Clone>(
+	transport: &T,
+	address: Address,
+	node: &NodeConfig,
+	tx_config: &TransactionConfig,
+	data: Vec<u8>,
+) -> TrackedTransaction<T> {
+	let request = TransactionRequest {
+		from: address,
+		to: None,
+		gas: None,
+		gas_price: None,
+		value: None,
+		data: Some(data.into()),
+		nonce: None,
+		condition: None,
+	};
+	// deployments always use `gas_price`
+	let gas_price = GasPriceOracle::fixed(tx_config.gas_price);
+	let transaction = AsyncTransaction::new(
+		transport,
+		None,
+		&NonceManager::new(transport.clone(), address, node.request_timeout),
+		GasLimit::Fixed(tx_config.gas).gas(transport, &request, node.request_timeout),
+		gas_price.fees(transport, node.request_timeout),
+		request,
+		node.request_timeout,
+	);
+	TrackedTransaction::new(TrackedTransactionOptions {
+		transport: transport.clone(),
+		transaction,
+		request_timeout: node.request_timeout,
+		poll_interval: node.poll_interval,
+		confirmations: node.required_confirmations,
+		resubmit_after: node.resubmit_after,
+		gas_price_bump_percent: node.gas_price_bump_percent,
+		max_gas_price: gas_price.max_gas_price(),
+	})
+}
+
 pub enum DeployState<T: Transport + Clone> {
 	NotDeployed,
 	Deploying {
 		data: Vec<u8>,
-		future: SendTransactionWithReceipt<T>,
+		future: TrackedTransaction<T>,
 	},
 	Deployed {
 		contract: DeployedContract,
@@ -71,25 +117,13 @@ impl<T: Transport + Clone> Future for DeployMain<T> {
 						self.config.authorities.accounts.clone(),
 					);
 
-					let tx_request = TransactionRequest {
-						from: self.config.address,
-						to: None,
-						gas: Some(self.config.txs.main_deploy.gas.into()),
-						gas_price: Some(self.config.txs.main_deploy.gas_price.into()),
-						value: None,
-						data: Some(data.clone().into()),
-						nonce: None,
-						condition: None,
-					};
-
-					let future =
-						SendTransactionWithReceipt::new(SendTransactionWithReceiptOptions {
-							transport: self.main_transport.clone(),
-							request_timeout: self.config.main.request_timeout,
-							poll_interval: self.config.main.poll_interval,
-							confirmations: self.config.main.required_confirmations,
-							transaction: tx_request,
-						});
+					let future = deploy_transaction(
+						&self.main_transport,
+						self.config.address,
+						&self.config.main,
+						&self.config.txs.main_deploy,
+						data.clone(),
+					);
 
 					info!("sending MainBridge contract deployment transaction and waiting for {} confirmations...", self.config.main.required_confirmations);
 
@@ -156,25 +190,13 @@ impl<T: Transport + Clone> Future for DeploySide<T> {
 						self.config.authorities.accounts.clone(),
 					);
 
-					let tx_request = TransactionRequest {
-						from: self.config.address,
-						to: None,
-						gas: Some(self.config.txs.side_deploy.gas.into()),
-						gas_price: Some(self.config.txs.side_deploy.gas_price.into()),
-						value: None,
-						data: Some(data.clone().into()),
-						nonce: None,
-						condition: None,
-					};
-
-					let future =
-						SendTransactionWithReceipt::new(SendTransactionWithReceiptOptions {
-							transport: self.side_transport.clone(),
-							request_timeout: self.config.side.request_timeout,
-							poll_interval: self.config.side.poll_interval,
-							confirmations: self.config.side.required_confirmations,
-							transaction: tx_request,
-						});
+					let future = deploy_transaction(
+						&self.side_transport,
+						self.config.address,
+						&self.config.side,
+						&self.config.txs.side_deploy,
+						data.clone(),
+					);
 
 					info!("sending SideBridge contract deployment transaction and waiting for {} confirmations...", self.config.side.required_confirmations);
 
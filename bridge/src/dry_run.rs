@@ -0,0 +1,168 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+// This file is part of Parity-Bridge.
+
+// Parity-Bridge is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity-Bridge is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity-Bridge.  If not, see <http://www.gnu.org/licenses/>.
+
+//! dry run of the bridge (`parity-bridge --dry-run`).
+//! a `NodeTransport` in dry run mode hands every request to `DryRun` first.
+//! requests that would send a transaction are logged and answered with the
+//! hash of the transaction instead. the receipt of that hash says the
+//! transaction is mined so relays complete as if it had been sent.
+//! messages the node would sign with the authority key are answered with a
+//! dummy signature so no signature of the authority is created.
+//! all other requests (including the checks of the relay futures) reach the node.
+
+use serde_json;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use tiny_keccak::{self, Hasher};
+use web3::helpers;
+use web3::rpc;
+use web3::types::{Bytes, H2048, H256};
+
+/// length of a signature as returned by `eth_sign`
+const SIGNATURE_LENGTH: usize = 65;
+
+fn keccak256(bytes: &[u8]) -> H256 {
+	let mut output = [0u8; 32];
+	let mut keccak = tiny_keccak::Keccak::v256();
+	keccak.update(bytes);
+	keccak.finalize(&mut output);
+	H256::from_slice(&output)
+}
+
+/// receipt of a transaction mined in block 0 which is always confirmed
+fn receipt(transaction_hash: H256) -> rpc::Value {
+	json!({
+		"blockHash": null,
+		"blockNumber": "0x0",
+		"contractAddress": null,
+		"cumulativeGasUsed": "0x0",
+		"gasUsed": "0x0",
+		"logs": [],
+		"logsBloom": H2048::zero(),
+		"root": null,
+		"status": "0x1",
+		"transactionHash": transaction_hash,
+		"transactionIndex": "0x0"
+	})
+}
+
+#[derive(Debug, Default)]
+pub struct DryRun {
+	/// hashes of the transactions that were not sent
+	transaction_hashes: RefCell<HashSet<H256>>,
+}
+
+impl DryRun {
+	/// the response to `request` if it must not reach the node
+	pub fn intercept(&self, request: &rpc::Call) -> Option<rpc::Value> {
+		let call = match *request {
+			rpc::Call::MethodCall(ref call) => call,
+			_ => return None,
+		};
+		let params = match call.params {
+			rpc::Params::Array(ref params) => params,
+			_ => return None,
+		};
+		match (call.method.as_str(), params.get(0)) {
+			("eth_sendRawTransaction", Some(raw_transaction)) => {
+				let bytes: Bytes = serde_json::from_value(raw_transaction.clone()).ok()?;
+				let hash = keccak256(&bytes.0);
+				info!(
+					"dry run: not sending raw transaction {:?}: {}",
+					hash, raw_transaction
+				);
+				Some(self.not_sent(hash))
+			}
+			("eth_sendTransaction", Some(transaction)) => {
+				// the node would sign the transaction. the hash only identifies it here
+				let hash = keccak256(transaction.to_string().as_bytes());
+				info!(
+					"dry run: not sending transaction {:?}: {}",
+					hash, transaction
+				);
+				Some(self.not_sent(hash))
+			}
+			("eth_sign", Some(address)) => {
+				info!(
+					"dry run: not signing message {} with {}",
+					params
+						.get(1)
+						.map(|data| data.to_string())
+						.unwrap_or_default(),
+					address
+				);
+				Some(helpers::serialize(&Bytes(vec![0; SIGNATURE_LENGTH])))
+			}
+			("eth_getTransactionReceipt", Some(hash)) => {
+				let hash: H256 = serde_json::from_value(hash.clone()).ok()?;
+				if self.transaction_hashes.borrow().contains(&hash) {
+					Some(receipt(hash))
+				} else {
+					None
+				}
+			}
+			_ => None,
+		}
+	}
+
+	fn not_sent(&self, hash: H256) -> rpc::Value {
+		self.transaction_hashes.borrow_mut().insert(hash);
+		helpers::serialize(&hash)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use web3::helpers::build_request;
+
+	#[test]
+	fn test_dry_run_intercepts_transactions_and_their_receipts() {
+		let dry_run = DryRun::default();
+		let hash = keccak256(&[0x12, 0x34]);
+
+		let send = build_request(1, "eth_sendRawTransaction", vec![json!("0x1234")]);
+		assert_eq!(dry_run.intercept(&send), Some(helpers::serialize(&hash)));
+
+		let receipt_request = build_request(
+			2,
+			"eth_getTransactionReceipt",
+			vec![helpers::serialize(&hash)],
+		);
+		assert_eq!(dry_run.intercept(&receipt_request), Some(receipt(hash)));
+
+		// receipts of other transactions and checks come from the node
+		let other_receipt_request = build_request(
+			3,
+			"eth_getTransactionReceipt",
+			vec![helpers::serialize(&H256::zero())],
+		);
+		assert_eq!(dry_run.intercept(&other_receipt_request), None);
+		let call = build_request(4, "eth_call", vec![json!({}), json!("latest")]);
+		assert_eq!(dry_run.intercept(&call), None);
+
+		let sign = build_request(
+			5,
+			"eth_sign",
+			vec![
+				json!("0x006b5dda44dc2606f07ad86c9190fb54fd905f6d"),
+				json!("0x1234"),
+			],
+		);
+		let signature: Bytes = serde_json::from_value(dry_run.intercept(&sign).unwrap()).unwrap();
+		assert_eq!(signature.0, vec![0; 65]);
+	}
+}
@@ -18,7 +18,8 @@
 
 use std::io;
 use tokio_timer::{TimeoutError, TimerError};
-use {ethabi, rustc_hex, toml, web3};
+use web3::types::H256;
+use {ethabi, prometheus, rustc_hex, sled, toml, web3};
 
 error_chain! {
 	types {
@@ -31,6 +32,8 @@ error_chain! {
 		Ethabi(ethabi::Error);
 		Timer(TimerError);
 		Hex(rustc_hex::FromHexError);
+		Sled(sled::Error);
+		Prometheus(prometheus::Error);
 	}
 
 	errors {
@@ -44,6 +47,20 @@ error_chain! {
 			description("File not found"),
 			display("File {} not found", filename),
 		}
+		Signing(reason: String) {
+			description("signing failed"),
+			display("signing failed: {}", reason),
+		}
+		// usually means that the transaction would revert
+		GasEstimation(reason: String) {
+			description("gas estimation failed"),
+			display("gas estimation failed: {}", reason),
+		}
+		// the relay transaction got mined but its execution failed
+		TransactionReverted(hash: H256) {
+			description("transaction reverted"),
+			display("transaction {:?} reverted", hash),
+		}
 		// workaround for lack of web3:Error Display and Error implementations
 		Web3(err: web3::Error) {
 			description("web3 error"),
@@ -79,3 +96,64 @@ impl From<web3::Error> for Error {
 		ErrorKind::Web3(err).into()
 	}
 }
+
+impl Error {
+	/// whether the operation that failed with this error may succeed if
+	/// it is tried again later. true for timeouts, unreachable nodes and
+	/// nonces that were taken in the meantime. false for everything that
+	/// indicates a misconfiguration like a wrong contract or a bad key.
+	pub fn is_transient(&self) -> bool {
+		let mut next = Some(self);
+		while let Some(err) = next {
+			match *err.kind() {
+				ErrorKind::TimedOut | ErrorKind::Timer(_) | ErrorKind::Io(_) => return true,
+				ErrorKind::Web3(ref err) => return is_transient_web3_error(err),
+				// context added through `chain_err`. the cause decides
+				ErrorKind::Msg(_) => {}
+				_ => return false,
+			}
+			next = err.cause_error();
+		}
+		false
+	}
+
+	/// whether the relay of a single message failed because its transaction
+	/// would revert or did revert. other messages are not affected so the
+	/// message is skipped rather than stopping the bridge.
+	pub fn is_message_failure(&self) -> bool {
+		let mut next = Some(self);
+		while let Some(err) = next {
+			match *err.kind() {
+				ErrorKind::GasEstimation(_) | ErrorKind::TransactionReverted(_) => return true,
+				// context added through `chain_err`. the cause decides
+				ErrorKind::Msg(_) => {}
+				_ => return false,
+			}
+			next = err.cause_error();
+		}
+		false
+	}
+
+	/// the `Error` this one was chained to with `chain_err`
+	fn cause_error(&self) -> Option<&Error> {
+		self.1
+			.next_error
+			.as_ref()
+			.and_then(|cause| cause.downcast_ref::<Error>())
+	}
+}
+
+fn is_transient_web3_error(err: &web3::Error) -> bool {
+	match *err {
+		web3::Error::Unreachable | web3::Error::Transport(_) | web3::Error::Io(_) => true,
+		web3::Error::Rpc(ref err) => {
+			let message = err.message.to_lowercase();
+			// another transaction got the nonce first. the `NonceManager`
+			// resynchronises so the next attempt uses a fresh one
+			message.contains("nonce too low")
+				|| message.contains("transaction with the same hash was already imported")
+				|| message.contains("replacement transaction underpriced")
+		}
+		_ => false,
+	}
+}
@@ -0,0 +1,213 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+// This file is part of Parity-Bridge.
+
+// Parity-Bridge is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity-Bridge is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity-Bridge.  If not, see <http://www.gnu.org/licenses/>.
+
+//! determines the gas limit of relay transactions.
+//! either the configured value or, if `estimate_gas` is set,
+//! the result of `eth_estimateGas` plus a safety margin.
+
+use config::TransactionConfig;
+use error::{self, ErrorKind};
+use futures::future::FromErr;
+use futures::{Async, Future, Poll};
+use std::time::Duration;
+use tokio_timer::{Timeout, Timer};
+use web3;
+use web3::api::Namespace;
+use web3::helpers::CallFuture;
+use web3::types::{CallRequest, TransactionRequest, U256};
+use web3::Transport;
+
+/// gas limit of a transaction type
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum GasLimit {
+	/// always the given value
+	Fixed(U256),
+	/// `eth_estimateGas` plus `margin_percent`.
+	/// transactions that would need more than `max` are not sent
+	Estimate { margin_percent: u32, max: U256 },
+}
+
+impl GasLimit {
+	/// `gas` is the fixed gas limit or, if `config.estimate_gas` is set, the cap
+	pub fn new(gas: U256, config: &TransactionConfig) -> Self {
+		if config.estimate_gas {
+			GasLimit::Estimate {
+				margin_percent: config.gas_margin_percent,
+				max: gas,
+			}
+		} else {
+			GasLimit::Fixed(gas)
+		}
+	}
+
+	/// returns a `Future` that resolves with the gas limit to use for `request`
+	pub fn gas<T: Transport>(
+		&self,
+		transport: &T,
+		request: &TransactionRequest,
+		request_timeout: Duration,
+	) -> TransactionGas<T> {
+		let state = match *self {
+			GasLimit::Fixed(gas) => TransactionGasState::Fixed(gas),
+			GasLimit::Estimate { .. } => {
+				let call_request = CallRequest {
+					from: Some(request.from),
+					to: request
+						.to
+						.expect("relay transactions are sent to a contract; qed"),
+					gas: None,
+					gas_price: None,
+					value: request.value,
+					data: request.data.clone(),
+				};
+				let future = web3::api::Eth::new(transport)
+					.estimate_gas(call_request, None)
+					.from_err();
+				TransactionGasState::AwaitEstimate(
+					Timer::default().timeout(future, request_timeout),
+				)
+			}
+		};
+		TransactionGas {
+			limit: *self,
+			state,
+		}
+	}
+}
+
+enum TransactionGasState<T: Transport> {
+	Fixed(U256),
+	AwaitEstimate(Timeout<FromErr<CallFuture<U256, T::Out>, error::Error>>),
+}
+
+/// `Future` returned by `GasLimit::gas`
+pub struct TransactionGas<T: Transport> {
+	limit: GasLimit,
+	state: TransactionGasState<T>,
+}
+
+impl<T: Transport> Future for TransactionGas<T> {
+	type Item = U256;
+	type Error = error::Error;
+
+	fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+		let estimate = match self.state {
+			TransactionGasState::Fixed(gas) => return Ok(Async::Ready(gas)),
+			TransactionGasState::AwaitEstimate(ref mut future) => match future.poll() {
+				Ok(Async::NotReady) => return Ok(Async::NotReady),
+				Ok(Async::Ready(estimate)) => estimate,
+				Err(err) => match *err.kind() {
+					// a timeout says nothing about the transaction
+					ErrorKind::TimedOut => return Err(err),
+					// the node fails the estimate if the transaction would revert
+					_ => {
+						return Err(ErrorKind::GasEstimation(format!(
+							"eth_estimateGas failed: {}",
+							err
+						))
+						.into())
+					}
+				},
+			},
+		};
+
+		let (margin_percent, max) = match self.limit {
+			GasLimit::Estimate {
+				margin_percent,
+				max,
+			} => (margin_percent, max),
+			GasLimit::Fixed(_) => unreachable!("only estimates are awaited; qed"),
+		};
+		let gas = estimate + estimate * U256::from(margin_percent) / U256::from(100);
+		if gas > max {
+			return Err(ErrorKind::GasEstimation(format!(
+				"estimated gas {} (with {}% margin) exceeds the maximum of {}",
+				gas, margin_percent, max
+			))
+			.into());
+		}
+		Ok(Async::Ready(gas))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use tokio_core::reactor::Core;
+	use web3::types::Bytes;
+
+	fn request() -> TransactionRequest {
+		TransactionRequest {
+			from: "0000000000000000000000000000000000000001".parse().unwrap(),
+			to: Some("0000000000000000000000000000000000000002".parse().unwrap()),
+			gas: None,
+			gas_price: None,
+			value: None,
+			data: Some(Bytes(vec![0x12, 0x34])),
+			nonce: None,
+			condition: None,
+		}
+	}
+
+	#[test]
+	fn test_gas_limit_estimate_with_margin() {
+		let transport = mock_transport!(
+			"eth_estimateGas" =>
+				req => json!([{
+					"data": "0x1234",
+					"from": "0x0000000000000000000000000000000000000001",
+					"to": "0x0000000000000000000000000000000000000002"
+				}]),
+				res => json!("0x2710");
+		);
+
+		let limit = GasLimit::Estimate {
+			margin_percent: 20,
+			max: 20000.into(),
+		};
+		let future = limit.gas(&transport, &request(), Duration::from_secs(1));
+
+		let mut event_loop = Core::new().unwrap();
+		assert_eq!(event_loop.run(future).unwrap(), 12000.into());
+		assert_eq!(transport.actual_requests(), transport.expected_requests());
+	}
+
+	#[test]
+	fn test_gas_limit_estimate_above_max_fails() {
+		let transport = mock_transport!(
+			"eth_estimateGas" =>
+				req => json!([{
+					"data": "0x1234",
+					"from": "0x0000000000000000000000000000000000000001",
+					"to": "0x0000000000000000000000000000000000000002"
+				}]),
+				res => json!("0x2710");
+		);
+
+		let limit = GasLimit::Estimate {
+			margin_percent: 20,
+			max: 11000.into(),
+		};
+		let future = limit.gas(&transport, &request(), Duration::from_secs(1));
+
+		let mut event_loop = Core::new().unwrap();
+		match *event_loop.run(future).unwrap_err().kind() {
+			ErrorKind::GasEstimation(_) => {}
+			ref kind => panic!("expected a gas estimation error, got {:?}", kind),
+		}
+		assert_eq!(transport.actual_requests(), transport.expected_requests());
+	}
+}
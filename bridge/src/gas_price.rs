@@ -0,0 +1,477 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+// This file is part of Parity-Bridge.
+
+// Parity-Bridge is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity-Bridge is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity-Bridge.  If not, see <http://www.gnu.org/licenses/>.
+
+//! determines the fees of relay transactions according to their `TransactionConfig`.
+//! EIP-1559 fees are used on chains whose latest block has a base fee.
+//! the `GasPriceStrategy` is used otherwise.
+
+use config::{GasPriceStrategy, TransactionConfig};
+use error::{self, ResultExt};
+use futures::future::{join_all, FromErr, JoinAll};
+use futures::{Async, Future, Poll};
+use signer::Fees;
+use std::cmp;
+use std::time::Duration;
+use tokio_timer::{Timeout, Timer};
+use web3;
+use web3::api::Namespace;
+use web3::helpers::{self, CallFuture};
+use web3::types::{Block, BlockId, BlockNumber, Transaction, U256, U64};
+use web3::Transport;
+
+/// the part of a block that tells whether the chain has a base fee.
+/// the `Block` of web3 doesn't know about `baseFeePerGas`
+#[derive(Deserialize)]
+struct BaseFee {
+	#[serde(rename = "baseFeePerGas")]
+	base_fee_per_gas: Option<U256>,
+}
+
+/// fee settings of a transaction type: EIP-1559 fees and the gas price
+/// strategy for chains without a base fee together with its floor and ceiling
+#[derive(Debug, PartialEq, Clone)]
+pub struct GasPriceOracle {
+	eip1559_fees: Option<Fees>,
+	strategy: GasPriceStrategy,
+	gas_price: U256,
+	min_gas_price: U256,
+	max_gas_price: U256,
+}
+
+impl GasPriceOracle {
+	pub fn new(config: &TransactionConfig) -> Self {
+		let eip1559_fees = match (config.max_fee_per_gas, config.max_priority_fee_per_gas) {
+			(Some(max_fee_per_gas), Some(max_priority_fee_per_gas)) => Some(Fees::Eip1559 {
+				max_fee_per_gas,
+				max_priority_fee_per_gas,
+			}),
+			_ => None,
+		};
+		Self {
+			eip1559_fees,
+			strategy: config.gas_price_strategy.clone(),
+			gas_price: config.gas_price,
+			min_gas_price: config.min_gas_price,
+			max_gas_price: config.max_gas_price,
+		}
+	}
+
+	/// always uses `gas_price`
+	pub fn fixed(gas_price: U256) -> Self {
+		Self {
+			eip1559_fees: None,
+			strategy: GasPriceStrategy::Fixed,
+			gas_price,
+			min_gas_price: gas_price,
+			max_gas_price: gas_price,
+		}
+	}
+
+	/// ceiling for the gas price and the max fee per gas of re-submissions
+	pub fn max_gas_price(&self) -> U256 {
+		self.max_gas_price
+	}
+
+	/// returns a `Future` that resolves with the fees to use for a
+	/// transaction sent now
+	pub fn fees<T: Transport>(
+		&self,
+		transport: &T,
+		request_timeout: Duration,
+	) -> TransactionFees<T> {
+		let timer = Timer::default();
+		let state = if self.eip1559_fees.is_some() {
+			let future = CallFuture::new(transport.execute(
+				"eth_getBlockByNumber",
+				vec![
+					helpers::serialize(&BlockNumber::Latest),
+					helpers::serialize(&false),
+				],
+			));
+			FeesState::AwaitLatestBlock(timer.timeout(future.from_err(), request_timeout))
+		} else {
+			self.gas_price_state(transport, &timer, request_timeout)
+		};
+		TransactionFees {
+			oracle: self.clone(),
+			transport: transport.clone(),
+			request_timeout,
+			timer,
+			state,
+		}
+	}
+
+	fn gas_price_state<T: Transport>(
+		&self,
+		transport: &T,
+		timer: &Timer,
+		request_timeout: Duration,
+	) -> FeesState<T> {
+		match self.strategy {
+			GasPriceStrategy::Fixed => FeesState::Fixed(self.gas_price),
+			GasPriceStrategy::Node { .. } => {
+				let future = web3::api::Eth::new(transport).gas_price();
+				FeesState::AwaitNodeGasPrice(timer.timeout(future.from_err(), request_timeout))
+			}
+			GasPriceStrategy::Percentile { .. } => {
+				let future = web3::api::Eth::new(transport).block_number();
+				FeesState::AwaitBlockNumber(timer.timeout(future.from_err(), request_timeout))
+			}
+		}
+	}
+
+	fn clamp(&self, gas_price: U256) -> U256 {
+		cmp::max(self.min_gas_price, cmp::min(gas_price, self.max_gas_price))
+	}
+}
+
+enum FeesState<T: Transport> {
+	AwaitLatestBlock(Timeout<FromErr<CallFuture<Option<BaseFee>, T::Out>, error::Error>>),
+	Fixed(U256),
+	AwaitNodeGasPrice(Timeout<FromErr<CallFuture<U256, T::Out>, error::Error>>),
+	AwaitBlockNumber(Timeout<FromErr<CallFuture<U64, T::Out>, error::Error>>),
+	AwaitBlocks(
+		JoinAll<
+			Vec<Timeout<FromErr<CallFuture<Option<Block<Transaction>>, T::Out>, error::Error>>>,
+		>,
+	),
+}
+
+/// `Future` returned by `GasPriceOracle::fees`
+pub struct TransactionFees<T: Transport> {
+	oracle: GasPriceOracle,
+	transport: T,
+	request_timeout: Duration,
+	timer: Timer,
+	state: FeesState<T>,
+}
+
+impl<T: Transport> Future for TransactionFees<T> {
+	type Item = Fees;
+	type Error = error::Error;
+
+	fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+		loop {
+			let next_state = match self.state {
+				FeesState::AwaitLatestBlock(ref mut future) => {
+					let latest_block = try_ready!(future
+						.poll()
+						.chain_err(|| "TransactionFees: fetching latest block failed"));
+					match latest_block.and_then(|block| block.base_fee_per_gas) {
+						Some(base_fee_per_gas) => {
+							let fees = self.oracle.eip1559_fees.expect(
+								"latest block is only fetched if EIP-1559 fees are set; qed",
+							);
+							info!(
+								"TransactionFees: base fee per gas is {}. using {:?}",
+								base_fee_per_gas, fees
+							);
+							return Ok(Async::Ready(fees));
+						}
+						None => {
+							info!("TransactionFees: chain has no base fee. using gas price");
+							self.oracle.gas_price_state(
+								&self.transport,
+								&self.timer,
+								self.request_timeout,
+							)
+						}
+					}
+				}
+				FeesState::Fixed(gas_price) => {
+					return Ok(Async::Ready(Fees::Legacy { gas_price }));
+				}
+				FeesState::AwaitNodeGasPrice(ref mut future) => {
+					let node_gas_price = try_ready!(future
+						.poll()
+						.chain_err(|| "TransactionFees: fetching gas price of node failed"));
+					let multiplier = match self.oracle.strategy {
+						GasPriceStrategy::Node { multiplier } => multiplier,
+						_ => unreachable!(
+							"only the node strategy fetches the gas price of the node; qed"
+						),
+					};
+					// multiplier with a precision of two decimals
+					let gas_price = node_gas_price
+						* U256::from((multiplier * 100.0).round() as u64)
+						/ U256::from(100);
+					let gas_price = self.oracle.clamp(gas_price);
+					info!(
+						"TransactionFees: gas price of node is {}. using {}",
+						node_gas_price, gas_price
+					);
+					return Ok(Async::Ready(Fees::Legacy { gas_price }));
+				}
+				FeesState::AwaitBlockNumber(ref mut future) => {
+					let last_block = try_ready!(future
+						.poll()
+						.chain_err(|| "TransactionFees: fetching last block number failed"))
+					.as_u64();
+					let blocks = match self.oracle.strategy {
+						GasPriceStrategy::Percentile { blocks, .. } => blocks,
+						_ => unreachable!("only the percentile strategy fetches blocks; qed"),
+					};
+					let eth = web3::api::Eth::new(&self.transport);
+					let futures = (last_block.saturating_sub(blocks - 1)..=last_block)
+						.map(|number| {
+							self.timer.timeout(
+								eth.block_with_txs(BlockId::Number(BlockNumber::Number(
+									number.into(),
+								)))
+								.from_err(),
+								self.request_timeout,
+							)
+						})
+						.collect::<Vec<_>>();
+					FeesState::AwaitBlocks(join_all(futures))
+				}
+				FeesState::AwaitBlocks(ref mut future) => {
+					let blocks = try_ready!(future
+						.poll()
+						.chain_err(|| "TransactionFees: fetching recent blocks failed"));
+					let percentile = match self.oracle.strategy {
+						GasPriceStrategy::Percentile { percentile, .. } => percentile,
+						_ => unreachable!("only the percentile strategy fetches blocks; qed"),
+					};
+					let mut gas_prices = blocks
+						.into_iter()
+						.flatten()
+						.flat_map(|block| block.transactions)
+						.map(|transaction| transaction.gas_price)
+						.collect::<Vec<_>>();
+					gas_prices.sort();
+
+					let gas_price = if gas_prices.is_empty() {
+						info!("TransactionFees: no transactions in recent blocks. using floor");
+						self.oracle.min_gas_price
+					} else {
+						let index = (gas_prices.len() - 1) * percentile as usize / 100;
+						self.oracle.clamp(gas_prices[index])
+					};
+					info!(
+						"TransactionFees: {}th percentile of {} recent gas prices. using {}",
+						percentile,
+						gas_prices.len(),
+						gas_price
+					);
+					return Ok(Async::Ready(Fees::Legacy { gas_price }));
+				}
+			};
+
+			self.state = next_state;
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use serde_json;
+	use tokio_core::reactor::Core;
+
+	fn oracle(strategy: GasPriceStrategy) -> GasPriceOracle {
+		GasPriceOracle::new(&TransactionConfig {
+			gas: 0.into(),
+			gas_price: 0.into(),
+			gas_price_strategy: strategy,
+			min_gas_price: 1000.into(),
+			max_gas_price: 5000.into(),
+			max_fee_per_gas: None,
+			max_priority_fee_per_gas: None,
+			estimate_gas: false,
+			gas_margin_percent: 0,
+		})
+	}
+
+	fn eip1559_oracle() -> GasPriceOracle {
+		GasPriceOracle::new(&TransactionConfig {
+			gas: 0.into(),
+			gas_price: 0.into(),
+			gas_price_strategy: GasPriceStrategy::Node { multiplier: 1.0 },
+			min_gas_price: 1000.into(),
+			max_gas_price: 5000.into(),
+			max_fee_per_gas: Some(4000.into()),
+			max_priority_fee_per_gas: Some(200.into()),
+			estimate_gas: false,
+			gas_margin_percent: 0,
+		})
+	}
+
+	fn block_with_gas_prices(number: u64, gas_prices: &[u64]) -> serde_json::Value {
+		let transactions = gas_prices
+			.iter()
+			.map(|gas_price| {
+				json!({
+					"hash": "0x0000000000000000000000000000000000000000000000000000000000000001",
+					"nonce": "0x0",
+					"blockHash": "0x0000000000000000000000000000000000000000000000000000000000000002",
+					"blockNumber": format!("0x{:x}", number),
+					"transactionIndex": "0x0",
+					"from": "0x0000000000000000000000000000000000000001",
+					"to": "0x0000000000000000000000000000000000000002",
+					"value": "0x0",
+					"gasPrice": format!("0x{:x}", gas_price),
+					"gas": "0x5208",
+					"input": "0x",
+				})
+			})
+			.collect::<Vec<_>>();
+		json!({
+			"hash": "0x0000000000000000000000000000000000000000000000000000000000000002",
+			"parentHash": "0x0000000000000000000000000000000000000000000000000000000000000003",
+			"sha3Uncles": "0x0000000000000000000000000000000000000000000000000000000000000004",
+			"miner": "0x0000000000000000000000000000000000000001",
+			"stateRoot": "0x0000000000000000000000000000000000000000000000000000000000000005",
+			"transactionsRoot": "0x0000000000000000000000000000000000000000000000000000000000000006",
+			"receiptsRoot": "0x0000000000000000000000000000000000000000000000000000000000000007",
+			"number": format!("0x{:x}", number),
+			"gasUsed": "0x0",
+			"gasLimit": "0x0",
+			"extraData": "0x",
+			"logsBloom": format!("0x{}", "0".repeat(512)),
+			"timestamp": "0x0",
+			"difficulty": "0x0",
+			"totalDifficulty": "0x0",
+			"sealFields": [],
+			"uncles": [],
+			"transactions": transactions,
+			"size": "0x0",
+		})
+	}
+
+	#[test]
+	fn test_gas_price_node_with_multiplier() {
+		let transport = mock_transport!(
+			"eth_gasPrice" =>
+				req => json!([]),
+				res => json!(format!("0x{:x}", 2000));
+		);
+		let future = oracle(GasPriceStrategy::Node { multiplier: 1.25 })
+			.fees(&transport, Duration::from_secs(1));
+
+		let mut event_loop = Core::new().unwrap();
+		assert_eq!(
+			event_loop.run(future).unwrap(),
+			Fees::Legacy {
+				gas_price: 2500.into()
+			}
+		);
+		assert_eq!(transport.actual_requests(), transport.expected_requests());
+	}
+
+	#[test]
+	fn test_gas_price_node_is_clamped() {
+		let transport = mock_transport!(
+			"eth_gasPrice" =>
+				req => json!([]),
+				res => json!(format!("0x{:x}", 10));
+			"eth_gasPrice" =>
+				req => json!([]),
+				res => json!(format!("0x{:x}", 100000));
+		);
+		let oracle = oracle(GasPriceStrategy::Node { multiplier: 1.0 });
+
+		let mut event_loop = Core::new().unwrap();
+		assert_eq!(
+			event_loop
+				.run(oracle.fees(&transport, Duration::from_secs(1)))
+				.unwrap(),
+			Fees::Legacy {
+				gas_price: 1000.into()
+			}
+		);
+		assert_eq!(
+			event_loop
+				.run(oracle.fees(&transport, Duration::from_secs(1)))
+				.unwrap(),
+			Fees::Legacy {
+				gas_price: 5000.into()
+			}
+		);
+		assert_eq!(transport.actual_requests(), transport.expected_requests());
+	}
+
+	#[test]
+	fn test_gas_price_percentile_of_recent_blocks() {
+		let transport = mock_transport!(
+			"eth_blockNumber" =>
+				req => json!([]),
+				res => json!("0x11");
+			"eth_getBlockByNumber" =>
+				req => json!(["0x10", true]),
+				res => block_with_gas_prices(0x10, &[4000, 1500]);
+			"eth_getBlockByNumber" =>
+				req => json!(["0x11", true]),
+				res => block_with_gas_prices(0x11, &[3000, 2000, 9000]);
+		);
+		let future = oracle(GasPriceStrategy::Percentile {
+			blocks: 2,
+			percentile: 50,
+		})
+		.fees(&transport, Duration::from_secs(1));
+
+		let mut event_loop = Core::new().unwrap();
+		assert_eq!(
+			event_loop.run(future).unwrap(),
+			Fees::Legacy {
+				gas_price: 3000.into()
+			}
+		);
+		assert_eq!(transport.actual_requests(), transport.expected_requests());
+	}
+
+	#[test]
+	fn test_fees_eip1559_on_chain_with_base_fee() {
+		let transport = mock_transport!(
+			"eth_getBlockByNumber" =>
+				req => json!(["latest", false]),
+				res => json!({ "number": "0x11", "baseFeePerGas": "0x7" });
+		);
+		let future = eip1559_oracle().fees(&transport, Duration::from_secs(1));
+
+		let mut event_loop = Core::new().unwrap();
+		assert_eq!(
+			event_loop.run(future).unwrap(),
+			Fees::Eip1559 {
+				max_fee_per_gas: 4000.into(),
+				max_priority_fee_per_gas: 200.into(),
+			}
+		);
+		assert_eq!(transport.actual_requests(), transport.expected_requests());
+	}
+
+	#[test]
+	fn test_fees_legacy_on_chain_without_base_fee() {
+		let transport = mock_transport!(
+			"eth_getBlockByNumber" =>
+				req => json!(["latest", false]),
+				res => json!({ "number": "0x11" });
+			"eth_gasPrice" =>
+				req => json!([]),
+				res => json!(format!("0x{:x}", 2000));
+		);
+		let future = eip1559_oracle().fees(&transport, Duration::from_secs(1));
+
+		let mut event_loop = Core::new().unwrap();
+		assert_eq!(
+			event_loop.run(future).unwrap(),
+			Fees::Legacy {
+				gas_price: 2000.into()
+			}
+		);
+		assert_eq!(transport.actual_requests(), transport.expected_requests());
+	}
+}
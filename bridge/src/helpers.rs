@@ -20,8 +20,12 @@ use error::{self, ResultExt};
 use ethabi::{self, FunctionOutputDecoder, RawLog};
 use futures::future::FromErr;
 use futures::{Async, Future, Poll, Stream};
+use gas_limit::TransactionGas;
+use gas_price::TransactionFees;
+use nonce_manager::{NextNonce, NonceManager};
 use serde::de::Error;
 use serde::{Deserialize, Deserializer, Serializer};
+use signer::{Fees, Signer, UnsignedTransaction};
 use std::time::Duration;
 use tokio_timer::{Timeout, Timer};
 use web3::api::Namespace;
@@ -95,35 +99,128 @@ impl<T: Transport, F: FunctionOutputDecoder> Future for AsyncCall<T, F> {
 	}
 }
 
+enum AsyncTransactionState<T: Transport> {
+	AwaitGas(TransactionGas<T>),
+	AwaitFees(TransactionFees<T>),
+	AwaitNonce(NextNonce<T>),
+	AwaitSent {
+		future: Timeout<FromErr<CallFuture<H256, T::Out>, error::Error>>,
+		nonce: U256,
+	},
+}
+
+/// use `AsyncTransaction::new(transport, signer, nonce_manager, gas, fees, request, timeout)`
+/// to get a `Future` that resolves with the hash of the sent transaction.
+/// the gas limit is taken from `gas`, the fees from `fees` and the nonce from `nonce_manager`.
+/// if `signer` is `Some` the transaction is signed locally and submitted via
+/// `eth_sendRawTransaction`. otherwise `eth_sendTransaction` is used which
+/// requires `request.from` to be unlocked on the node and doesn't support
+/// EIP-1559 fees.
 pub struct AsyncTransaction<T: Transport> {
-	future: Timeout<FromErr<CallFuture<H256, T::Out>, error::Error>>,
+	transport: T,
+	signer: Option<Signer>,
+	nonce_manager: NonceManager<T>,
+	request: TransactionRequest,
+	/// taken once the gas limit is known
+	fees_future: Option<TransactionFees<T>>,
+	fees: Option<Fees>,
+	/// set if sending failed without the node rejecting the transaction
+	may_be_sent: bool,
+	timeout: Duration,
+	state: AsyncTransactionState<T>,
 }
 
 impl<T: Transport> AsyncTransaction<T> {
 	pub fn new(
 		transport: &T,
-		contract_address: Address,
-		authority_address: Address,
-		gas: U256,
-		gas_price: U256,
+		signer: Option<&Signer>,
+		nonce_manager: &NonceManager<T>,
+		gas: TransactionGas<T>,
+		fees: TransactionFees<T>,
+		request: TransactionRequest,
 		timeout: Duration,
-		payload: Vec<u8>,
 	) -> Self {
-		let request = TransactionRequest {
-			from: authority_address,
-			to: Some(contract_address),
-			gas: Some(gas),
-			gas_price: Some(gas_price),
-			value: None,
-			data: Some(Bytes(payload)),
-			nonce: None,
-			condition: None,
-		};
-		let inner_future = web3::api::Eth::new(transport)
-			.send_transaction(request)
-			.from_err();
-		let future = Timer::default().timeout(inner_future, timeout);
-		Self { future }
+		Self {
+			transport: transport.clone(),
+			signer: signer.cloned(),
+			nonce_manager: nonce_manager.clone(),
+			request,
+			fees_future: Some(fees),
+			fees: None,
+			may_be_sent: false,
+			timeout,
+			state: AsyncTransactionState::AwaitGas(gas),
+		}
+	}
+
+	/// the fees of the transaction. `None` until they have been determined
+	pub fn fees(&self) -> Option<Fees> {
+		self.fees
+	}
+
+	/// whether the transaction may have been broadcast although sending it failed.
+	/// it keeps its nonce and can only be sent again through `resend`
+	pub fn may_be_sent(&self) -> bool {
+		self.may_be_sent
+	}
+
+	/// gives the nonce back to the nonce manager once no submission with it
+	/// can have been accepted. does nothing before a nonce was taken
+	pub fn release_nonce(&self) {
+		if let Some(nonce) = self.request.nonce {
+			self.nonce_manager.failed(nonce);
+		}
+	}
+
+	/// sends the already sent transaction again with the same nonce but `fees`.
+	/// the nonce manager is not involved as the nonce is already used.
+	pub fn resend(
+		&self,
+		fees: Fees,
+	) -> Result<Timeout<FromErr<CallFuture<H256, T::Out>, error::Error>>, error::Error> {
+		Ok(Timer::default().timeout(self.send(&self.request, fees)?.from_err(), self.timeout))
+	}
+
+	fn send(
+		&self,
+		request: &TransactionRequest,
+		fees: Fees,
+	) -> Result<CallFuture<H256, T::Out>, error::Error> {
+		let eth = web3::api::Eth::new(&self.transport);
+		Ok(match (self.signer.as_ref(), fees) {
+			(Some(signer), fees) => {
+				let transaction = UnsignedTransaction {
+					nonce: request.nonce.expect("nonce is set before sending; qed"),
+					fees,
+					gas: request.gas.unwrap_or_default(),
+					to: request.to,
+					value: request.value.unwrap_or_default(),
+					data: request
+						.data
+						.as_ref()
+						.map(|data| data.0.clone())
+						.unwrap_or_default(),
+				};
+				eth.send_raw_transaction(signer.sign_transaction(&transaction)?)
+			}
+			(None, Fees::Legacy { gas_price }) => {
+				let mut request = request.clone();
+				request.gas_price = Some(gas_price);
+				eth.send_transaction(request)
+			}
+			(None, Fees::Eip1559 { .. }) => {
+				bail!("EIP-1559 transactions can only be sent with a keystore")
+			}
+		})
+	}
+}
+
+/// whether the node answered that it does not accept the transaction.
+/// other errors (timeouts, connection errors) leave that uncertain
+pub fn is_rejection(err: &error::Error) -> bool {
+	match *err.kind() {
+		error::ErrorKind::Web3(web3::Error::Rpc(_)) => true,
+		_ => false,
 	}
 }
 
@@ -131,7 +228,61 @@ impl<T: Transport> Future for AsyncTransaction<T> {
 	type Item = H256;
 	type Error = error::Error;
 	fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-		self.future.poll().map_err(|x| x.into())
+		loop {
+			let next_state = match self.state {
+				// gas and fees are determined first so a failure doesn't leave a nonce gap
+				AsyncTransactionState::AwaitGas(ref mut future) => {
+					// not chained so `ErrorKind::GasEstimation` stays visible to callers
+					let gas = try_ready!(future.poll());
+					self.request.gas = Some(gas);
+					AsyncTransactionState::AwaitFees(
+						self.fees_future
+							.take()
+							.expect("fees future is only taken once; qed"),
+					)
+				}
+				AsyncTransactionState::AwaitFees(ref mut future) => {
+					let fees = try_ready!(future
+						.poll()
+						.chain_err(|| "AsyncTransaction: fetching fees failed"));
+					self.fees = Some(fees);
+					AsyncTransactionState::AwaitNonce(self.nonce_manager.next())
+				}
+				AsyncTransactionState::AwaitNonce(ref mut future) => {
+					let nonce = try_ready!(future
+						.poll()
+						.chain_err(|| "AsyncTransaction: fetching nonce failed"));
+					self.request.nonce = Some(nonce);
+					let fees = self.fees.expect("fees are set before the nonce; qed");
+					let future = match self.send(&self.request, fees) {
+						Ok(future) => future,
+						Err(err) => {
+							self.nonce_manager.failed(nonce);
+							return Err(err);
+						}
+					};
+					let future = Timer::default().timeout(future.from_err(), self.timeout);
+					AsyncTransactionState::AwaitSent { future, nonce }
+				}
+				AsyncTransactionState::AwaitSent {
+					ref mut future,
+					nonce,
+				} => {
+					let result = future.poll();
+					if let Err(ref err) = result {
+						if is_rejection(err) {
+							self.nonce_manager.failed(nonce);
+						} else {
+							// the transaction may have been broadcast already
+							self.nonce_manager.resync(nonce);
+							self.may_be_sent = true;
+						}
+					}
+					return result;
+				}
+			};
+			self.state = next_state;
+		}
 	}
 }
 
@@ -148,6 +299,15 @@ where
 	U256::from_dec_str(s).map_err(|_| D::Error::custom("failed to parse U256 from dec str"))
 }
 
+/// `deserialize_u256` for optional config options.
+/// use together with `#[serde(default)]`.
+pub fn deserialize_opt_u256<'de, D>(deserializer: D) -> Result<Option<U256>, D::Error>
+where
+	D: Deserializer<'de>,
+{
+	deserialize_u256(deserializer).map(Some)
+}
+
 pub fn serialize_u256<S>(value: &U256, serializer: S) -> Result<S::Ok, S::Error>
 where
 	S: Serializer,
@@ -0,0 +1,487 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+// This file is part of Parity-Bridge.
+
+// Parity-Bridge is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity-Bridge is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity-Bridge.  If not, see <http://www.gnu.org/licenses/>.
+
+//! journal of every relay the bridge attempted.
+//!
+//! unlike `database::State` which only remembers how far logs were checked
+//! the journal records for each relay its progress, the hash of the relay
+//! transaction and when it happened. that way operators can tell what
+//! happened to a message even if the process crashed mid-relay.
+//!
+//! stored either as a file of json lines, where every change appends the
+//! complete updated entry so the last line for a relay is its current state,
+//! or in a tree of a `SledDatabase` which keeps the current state of each relay.
+
+use error::{self, ResultExt};
+use log_format::{self, LogFormat};
+use metrics::Metrics;
+use serde_json;
+use sled;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use web3::types::{Log, H256};
+
+/// the relay operations a bridge node performs
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RelayKind {
+	/// `AcceptMessageFromMain`
+	MainToSideSign,
+	/// `SideToMainSign`
+	SideToMainSign,
+	/// `SideToMainSignatures`
+	SideToMainSignatures,
+}
+
+impl RelayKind {
+	/// the name used in the journal, logs and metrics
+	pub fn name(self) -> &'static str {
+		match self {
+			RelayKind::MainToSideSign => "main_to_side_sign",
+			RelayKind::SideToMainSign => "side_to_main_sign",
+			RelayKind::SideToMainSignatures => "side_to_main_signatures",
+		}
+	}
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RelayStatus {
+	/// the log was picked up
+	Seen,
+	/// the message was signed by this authority
+	Signed,
+	/// the relay transaction was sent but is not yet mined
+	Submitted,
+	/// the relay transaction was mined
+	Mined,
+	/// nothing to do. the message was relayed already
+	Skipped,
+	/// the last attempt failed. see `error`
+	Failed,
+}
+
+impl RelayStatus {
+	/// the name used in the journal, logs and metrics
+	pub fn name(self) -> &'static str {
+		match self {
+			RelayStatus::Seen => "seen",
+			RelayStatus::Signed => "signed",
+			RelayStatus::Submitted => "submitted",
+			RelayStatus::Mined => "mined",
+			RelayStatus::Skipped => "skipped",
+			RelayStatus::Failed => "failed",
+		}
+	}
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct RelayEntry {
+	pub kind: RelayKind,
+	/// hash of the transaction that emitted the log that caused the relay
+	pub tx_hash: H256,
+	/// index of that log in its block. tells apart the messages of a transaction
+	pub log_index: u64,
+	pub message_id: Option<H256>,
+	pub status: RelayStatus,
+	/// hash of the (last submitted) relay transaction
+	pub relay_tx_hash: Option<H256>,
+	pub error: Option<String>,
+	/// unix timestamp in seconds
+	pub first_seen: u64,
+	/// unix timestamp in seconds
+	pub updated: u64,
+}
+
+fn now() -> u64 {
+	SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map(|duration| duration.as_secs())
+		.unwrap_or(0)
+}
+
+/// identifies the relay of a single log
+type RelayKey = (RelayKind, H256, u64);
+
+fn relay_key(entry: &RelayEntry) -> RelayKey {
+	(entry.kind, entry.tx_hash, entry.log_index)
+}
+
+/// key of an entry in a sled tree
+fn tree_key(entry: &RelayEntry) -> Vec<u8> {
+	let mut key = vec![entry.kind as u8];
+	key.extend_from_slice(entry.tx_hash.as_bytes());
+	key.extend_from_slice(&entry.log_index.to_be_bytes());
+	key
+}
+
+/// where changed entries are written to
+enum Storage {
+	File(fs::File),
+	Tree(sled::Tree),
+}
+
+impl Storage {
+	fn write(&mut self, entry: &RelayEntry) -> Result<(), error::Error> {
+		let json = serde_json::to_string(entry).expect("serialization can't fail; qed");
+		match *self {
+			Storage::File(ref mut file) => {
+				writeln!(file, "{}", json)?;
+				file.flush()?;
+			}
+			Storage::Tree(ref tree) => {
+				tree.insert(tree_key(entry), json.into_bytes())?;
+				tree.flush()?;
+			}
+		}
+		Ok(())
+	}
+}
+
+#[derive(Default)]
+struct Inner {
+	storage: Option<Storage>,
+	entries: BTreeMap<RelayKey, RelayEntry>,
+	/// counts the relay steps if set
+	metrics: Option<Metrics>,
+	/// format every change is logged in
+	log_format: LogFormat,
+}
+
+/// `clone`d versions share the same entries and storage
+#[derive(Clone, Default)]
+pub struct Journal {
+	inner: Rc<RefCell<Inner>>,
+}
+
+impl Journal {
+	/// loads the journal from the file at `path` and appends to it from then on.
+	/// the file is created if it doesn't exist
+	pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, error::Error> {
+		let path = path.as_ref();
+		let journal = Self::read(path)?;
+		let file = fs::OpenOptions::new()
+			.append(true)
+			.create(true)
+			.open(path)
+			.chain_err(|| format!("Cannot open journal {:?}", path))?;
+		journal.inner.borrow_mut().storage = Some(Storage::File(file));
+		Ok(journal)
+	}
+
+	/// loads the journal from `tree` and writes changes to it from then on
+	pub fn open_tree(tree: sled::Tree) -> Result<Self, error::Error> {
+		let journal = Self::in_memory();
+		for item in tree.iter() {
+			let (_, value) = item?;
+			let entry: RelayEntry =
+				serde_json::from_slice(&value).chain_err(|| "Cannot parse journal entry")?;
+			journal
+				.inner
+				.borrow_mut()
+				.entries
+				.insert(relay_key(&entry), entry);
+		}
+		journal.inner.borrow_mut().storage = Some(Storage::Tree(tree));
+		Ok(journal)
+	}
+
+	/// loads the journal from the file at `path` without writing to it
+	pub fn read<P: AsRef<Path>>(path: P) -> Result<Self, error::Error> {
+		let path = path.as_ref();
+		let journal = Self::in_memory();
+		let file = match fs::File::open(path) {
+			Ok(file) => file,
+			Err(ref err) if err.kind() == ::std::io::ErrorKind::NotFound => return Ok(journal),
+			Err(err) => return Err(err).chain_err(|| format!("Cannot open journal {:?}", path)),
+		};
+		for line in BufReader::new(file).lines() {
+			let line = line?;
+			if line.trim().is_empty() {
+				continue;
+			}
+			let entry: RelayEntry = serde_json::from_str(&line)
+				.chain_err(|| format!("Cannot parse journal {:?}", path))?;
+			journal
+				.inner
+				.borrow_mut()
+				.entries
+				.insert(relay_key(&entry), entry);
+		}
+		Ok(journal)
+	}
+
+	/// journal that is not persisted
+	pub fn in_memory() -> Self {
+		Self::default()
+	}
+
+	/// counts every relay step in `metrics` from now on
+	pub fn with_metrics(self, metrics: Metrics) -> Self {
+		self.inner.borrow_mut().metrics = Some(metrics);
+		self
+	}
+
+	/// logs every change of an entry in `log_format` from now on
+	pub fn with_log_format(self, log_format: LogFormat) -> Self {
+		self.inner.borrow_mut().log_format = log_format;
+		self
+	}
+
+	/// all entries ordered by relay kind, transaction hash and log index
+	pub fn entries(&self) -> Vec<RelayEntry> {
+		self.inner.borrow().entries.values().cloned().collect()
+	}
+
+	/// entries of relays caused by the transaction `tx_hash`
+	/// or whose relay transaction is `tx_hash`
+	pub fn find(&self, tx_hash: &H256) -> Vec<RelayEntry> {
+		self.inner
+			.borrow()
+			.entries
+			.values()
+			.filter(|entry| {
+				entry.tx_hash == *tx_hash || entry.relay_tx_hash.as_ref() == Some(tx_hash)
+			})
+			.cloned()
+			.collect()
+	}
+
+	/// entries of relays of the message with `message_id`
+	pub fn find_by_message_id(&self, message_id: &H256) -> Vec<RelayEntry> {
+		self.inner
+			.borrow()
+			.entries
+			.values()
+			.filter(|entry| entry.message_id.as_ref() == Some(message_id))
+			.cloned()
+			.collect()
+	}
+
+	/// adds `entries` from another journal. entries for the same relay are replaced
+	pub fn import(&self, entries: Vec<RelayEntry>) -> Result<(), error::Error> {
+		let mut inner = self.inner.borrow_mut();
+		for entry in entries {
+			if let Some(storage) = inner.storage.as_mut() {
+				storage.write(&entry)?;
+			}
+			inner.entries.insert(relay_key(&entry), entry);
+		}
+		Ok(())
+	}
+
+	/// handle to record the progress of the relay of `kind` caused by the log
+	/// at `log_index` emitted by `tx_hash`
+	pub fn relay(&self, kind: RelayKind, tx_hash: H256, log_index: u64) -> RelayJournal {
+		RelayJournal {
+			journal: self.clone(),
+			key: (kind, tx_hash, log_index),
+		}
+	}
+
+	/// handle to record the progress of the relay of `kind` caused by the mined `log`
+	pub fn relay_of_log(&self, kind: RelayKind, log: &Log) -> RelayJournal {
+		let tx_hash = log
+			.transaction_hash
+			.expect("`log` must be mined and contain `transaction_hash`. q.e.d.");
+		let log_index = log
+			.log_index
+			.expect("`log` must be mined and contain `log_index`. q.e.d.")
+			.as_u64();
+		self.relay(kind, tx_hash, log_index)
+	}
+
+	fn count(&self, kind: RelayKind, status: RelayStatus) {
+		if let Some(ref metrics) = self.inner.borrow().metrics {
+			metrics.relay_step(kind, status);
+		}
+	}
+
+	fn update<F: FnOnce(&mut RelayEntry)>(&self, key: RelayKey, update: F) {
+		let mut inner = self.inner.borrow_mut();
+		let timestamp = now();
+		let (kind, tx_hash, log_index) = key;
+		let is_new = !inner.entries.contains_key(&key);
+		let entry = {
+			let entry = inner.entries.entry(key).or_insert_with(|| RelayEntry {
+				kind,
+				tx_hash,
+				log_index,
+				message_id: None,
+				status: RelayStatus::Seen,
+				relay_tx_hash: None,
+				error: None,
+				first_seen: timestamp,
+				updated: 0,
+			});
+			let before = entry.clone();
+			update(entry);
+			if !is_new && *entry == before {
+				return;
+			}
+			entry.updated = timestamp;
+			entry.clone()
+		};
+
+		log_format::log_relay_step(&entry, inner.log_format);
+
+		if let Some(storage) = inner.storage.as_mut() {
+			// the journal is informational. failing to write it must not stop relays
+			if let Err(err) = storage.write(&entry) {
+				error!("Journal: cannot write entry {:?}: {}", entry, err);
+			}
+		}
+	}
+}
+
+/// records the progress of a single relay in the `Journal`
+#[derive(Clone)]
+pub struct RelayJournal {
+	journal: Journal,
+	key: RelayKey,
+}
+
+impl RelayJournal {
+	pub fn seen(&self) {
+		self.journal.count(self.key.0, RelayStatus::Seen);
+		self.journal.update(self.key, |_| {})
+	}
+
+	pub fn message_id(&self, message_id: H256) {
+		self.journal
+			.update(self.key, |entry| entry.message_id = Some(message_id))
+	}
+
+	pub fn signed(&self) {
+		self.status(RelayStatus::Signed, None)
+	}
+
+	pub fn submitted(&self, relay_tx_hash: H256) {
+		self.status(RelayStatus::Submitted, Some(relay_tx_hash))
+	}
+
+	pub fn mined(&self, relay_tx_hash: H256) {
+		self.status(RelayStatus::Mined, Some(relay_tx_hash))
+	}
+
+	pub fn skipped(&self) {
+		self.journal.count(self.key.0, RelayStatus::Skipped);
+		self.journal.update(self.key, |entry| {
+			// a restarted bridge finds its own mined relays done already
+			if entry.status != RelayStatus::Mined {
+				entry.status = RelayStatus::Skipped;
+				entry.error = None;
+			}
+		})
+	}
+
+	pub fn failed(&self, err: &error::Error) {
+		let message = err
+			.iter()
+			.map(|e| e.to_string())
+			.collect::<Vec<_>>()
+			.join(": ");
+		self.journal.count(self.key.0, RelayStatus::Failed);
+		self.journal.update(self.key, |entry| {
+			entry.status = RelayStatus::Failed;
+			entry.error = Some(message);
+		})
+	}
+
+	fn status(&self, status: RelayStatus, relay_tx_hash: Option<H256>) {
+		self.journal.count(self.key.0, status);
+		self.journal.update(self.key, |entry| {
+			entry.status = status;
+			entry.error = None;
+			if relay_tx_hash.is_some() {
+				entry.relay_tx_hash = relay_tx_hash;
+			}
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::io::Read;
+	use tempfile::NamedTempFile;
+
+	#[test]
+	fn test_journal_persists_last_state_of_relay() {
+		let file = NamedTempFile::new().unwrap();
+		let tx_hash = H256::from_low_u64_be(1);
+		let relay_tx_hash = H256::from_low_u64_be(2);
+
+		let journal = Journal::open(file.path()).unwrap();
+		let relay = journal.relay(RelayKind::SideToMainSign, tx_hash, 0);
+		relay.seen();
+		relay.message_id(H256::from_low_u64_be(3));
+		relay.signed();
+		relay.submitted(relay_tx_hash);
+		// unchanged entries are not written again
+		relay.submitted(relay_tx_hash);
+		relay.mined(relay_tx_hash);
+
+		let mut contents = String::new();
+		fs::File::open(file.path())
+			.unwrap()
+			.read_to_string(&mut contents)
+			.unwrap();
+		assert_eq!(contents.lines().count(), 5);
+
+		let journal = Journal::read(file.path()).unwrap();
+		let entries = journal.find(&relay_tx_hash);
+		assert_eq!(entries.len(), 1);
+		assert_eq!(entries[0].kind, RelayKind::SideToMainSign);
+		assert_eq!(entries[0].tx_hash, tx_hash);
+		assert_eq!(entries[0].message_id, Some(H256::from_low_u64_be(3)));
+		assert_eq!(entries[0].status, RelayStatus::Mined);
+		assert_eq!(entries[0].relay_tx_hash, Some(relay_tx_hash));
+		assert_eq!(
+			journal.find_by_message_id(&H256::from_low_u64_be(3)),
+			entries
+		);
+	}
+
+	#[test]
+	fn test_journal_keeps_relays_of_logs_of_same_transaction_apart() {
+		let tx_hash = H256::from_low_u64_be(1);
+		let journal = Journal::in_memory();
+		journal
+			.relay(RelayKind::MainToSideSign, tx_hash, 0)
+			.message_id(H256::from_low_u64_be(2));
+		journal
+			.relay(RelayKind::MainToSideSign, tx_hash, 1)
+			.message_id(H256::from_low_u64_be(3));
+		journal
+			.relay(RelayKind::MainToSideSign, tx_hash, 0)
+			.mined(H256::from_low_u64_be(4));
+
+		let entries = journal.find(&tx_hash);
+		assert_eq!(entries.len(), 2);
+		assert_eq!(entries[0].log_index, 0);
+		assert_eq!(entries[0].message_id, Some(H256::from_low_u64_be(2)));
+		assert_eq!(entries[0].status, RelayStatus::Mined);
+		assert_eq!(entries[1].log_index, 1);
+		assert_eq!(entries[1].message_id, Some(H256::from_low_u64_be(3)));
+		assert_eq!(entries[1].status, RelayStatus::Seen);
+	}
+}
@@ -33,12 +33,21 @@
 //! and yields the numbers of those blocks for which all such created futures
 //! have completed. these block numbers are then persisted
 //! so the bridge doesn't have to check logs up to them again next time it's started.
+//! relay futures that fail with a transient error (`Error::is_transient`) are
+//! recreated and retried after a jittered exponential backoff.
+//! messages whose relay transaction would revert or reverted (`Error::is_message_failure`)
+//! are skipped. only other errors stop the bridge.
+//! the relay futures record their progress in the `Journal` (see `journal`).
 //!
 //! a `Bridge` instance is constructed as follows (how the parts fit together):
 //!
 //! - a tokio `event_loop` is created.
-//! - `main_transport` and `side_transport` which are web3 http transports
-//!   are created and each use an `event_loop` handle
+//! - `main_transport` and `side_transport` which are `NodeTransport`s (http or ipc
+//!   depending on the config) are created and each use an `event_loop` handle.
+//!   with `fallback` endpoints configured they fail over between them
+//! - if `ws` is configured for a node a `WsTransport` is created as well.
+//!   the log streams then learn about new blocks through a `newHeads` subscription
+//!   and only fall back to polling while it is down
 //! - the initial `state` is read from the database file
 //! - the `config` is read from the config file
 //! - `main_contract` (`side_contract`) which is for interaction with the main (side) bridge contract
@@ -53,9 +62,15 @@
 //! - each `RelayStream` polls all relay futures that are currently running as well as the log
 //! stream
 //! - if the log stream yields a log the relay stream creates the corresponding relay future
+//! - log streams remember the hashes of the blocks they checked. if blocks get reorganised
+//!   the log stream yields them as reverted and the relay stream drops their relay futures
+//!   and rewinds. the blocks are checked again on the new canonical chain
 //! - the relay future is responsible for the entire relay operation
 //! - currently relay futures check whether the specific relay has already happened,
 //!   ignore if it has and execute the corresponding transaction otherwise
+//! - relay transactions are tracked until they are mined with `required_confirmations`.
+//!   transactions that stay pending for too long are re-submitted with the same nonce
+//!   and a bumped gas price
 //! - relay futures should (currently don't) and easily could observe whether
 //!   the transaction succeeds, log it to help with troubleshooting and
 //!   retry if the condition can be recovered from
@@ -64,24 +79,31 @@
 extern crate error_chain;
 extern crate ethabi;
 extern crate ethereum_types;
+extern crate ethsign;
+extern crate fs2;
 #[macro_use]
 extern crate futures;
 #[macro_use]
 extern crate log;
 #[macro_use]
 extern crate pretty_assertions;
+extern crate prometheus;
 #[cfg(test)]
 #[macro_use]
 extern crate quickcheck;
+extern crate rand;
+extern crate rlp;
 extern crate rustc_hex;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 extern crate bridge_contracts as contracts;
-#[cfg_attr(test, macro_use)]
+#[macro_use]
 extern crate serde_json;
+extern crate sled;
 extern crate tiny_keccak;
 extern crate tokio_core;
+extern crate tokio_io;
 extern crate tokio_timer;
 extern crate toml;
 extern crate web3;
@@ -99,16 +121,31 @@ pub use bridge::Bridge;
 pub mod config;
 pub mod database;
 pub mod deploy;
+mod dry_run;
 pub mod error;
+mod gas_limit;
+pub use gas_limit::GasLimit;
+mod gas_price;
+pub use gas_price::GasPriceOracle;
 mod ordered_stream;
 pub use ordered_stream::OrderedStream;
 pub mod helpers;
+pub mod journal;
+pub mod log_format;
 mod main_contract;
 pub use main_contract::MainContract;
 mod accept_message_from_main;
+pub mod manual_relay;
+pub mod metrics;
 pub use accept_message_from_main::AcceptMessageFromMain;
+mod nonce_manager;
+pub use nonce_manager::NonceManager;
 mod relay_stream;
 pub use relay_stream::RelayStream;
+mod replay;
+pub use replay::replay;
+mod retry;
+pub use retry::{NewFuture, Retry};
 mod send_tx_with_receipt;
 mod side_contract;
 pub use side_contract::SideContract;
@@ -123,11 +160,24 @@ pub use log_stream::{LogStream, LogStreamOptions};
 mod signature;
 pub use signature::Signature;
 
+pub mod signer;
+
+mod status;
+pub use status::{ChainStatus, RelayProgress, Status};
+
+mod transport;
+pub use transport::NodeTransport;
+
+mod ws_transport;
+pub use ws_transport::WsTransport;
+
 mod message_to_main;
 pub use message_to_main::{MessageToMain, MESSAGE_LENGTH};
 
 #[cfg(test)]
 extern crate jsonrpc_core;
+#[cfg(test)]
+extern crate tempfile;
 
 #[cfg(test)]
 pub use test::MockTransport;
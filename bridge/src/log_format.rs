@@ -0,0 +1,189 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+// This file is part of Parity-Bridge.
+
+// Parity-Bridge is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity-Bridge is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity-Bridge.  If not, see <http://www.gnu.org/licenses/>.
+
+//! log output of the bridge (`parity-bridge --log-format <format>`).
+//! every step of a relay is logged by the `Journal` under `RELAY_TARGET`.
+//! with the `json` format every log record is a json object and the records
+//! of relay steps carry the fields of the step so the lifecycle of a transfer
+//! can be reconstructed from the source transaction hash and message id.
+
+use journal::{RelayEntry, RelayKind, RelayStatus};
+use log::{Level, Record};
+use serde_json;
+use web3::types::H256;
+
+/// target of the log records of relay steps
+pub const RELAY_TARGET: &str = "bridge::relay";
+
+#[derive(Debug, PartialEq, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+	Text,
+	Json,
+}
+
+impl Default for LogFormat {
+	fn default() -> Self {
+		LogFormat::Text
+	}
+}
+
+/// fields of a relay step
+#[derive(Serialize)]
+struct RelayStep<'a> {
+	relay: RelayKind,
+	step: RelayStatus,
+	/// chain the relay transaction is sent to
+	chain: &'static str,
+	source_tx_hash: H256,
+	message_id: Option<H256>,
+	destination_tx_hash: Option<H256>,
+	error: Option<&'a str>,
+}
+
+impl<'a> RelayStep<'a> {
+	fn new(entry: &'a RelayEntry) -> Self {
+		Self {
+			relay: entry.kind,
+			step: entry.status,
+			chain: match entry.kind {
+				RelayKind::MainToSideSign | RelayKind::SideToMainSign => "side",
+				RelayKind::SideToMainSignatures => "main",
+			},
+			source_tx_hash: entry.tx_hash,
+			message_id: entry.message_id,
+			destination_tx_hash: entry.relay_tx_hash,
+			error: entry.error.as_ref().map(String::as_str),
+		}
+	}
+}
+
+/// logs the current step of the relay `entry` in `format`
+pub fn log_relay_step(entry: &RelayEntry, format: LogFormat) {
+	let level = match entry.status {
+		RelayStatus::Failed => Level::Warn,
+		_ => Level::Info,
+	};
+	let step = RelayStep::new(entry);
+	match format {
+		LogFormat::Text => log!(
+			target: RELAY_TARGET,
+			level,
+			"{} {:?} - {} - message {} - {} tx {}{}",
+			step.relay.name(),
+			step.source_tx_hash,
+			step.step.name(),
+			step.message_id
+				.map(|id| format!("{:?}", id))
+				.unwrap_or_else(|| "unknown".into()),
+			step.chain,
+			step.destination_tx_hash
+				.map(|hash| format!("{:?}", hash))
+				.unwrap_or_else(|| "none".into()),
+			step.error
+				.map(|error| format!(" - {}", error))
+				.unwrap_or_default()
+		),
+		LogFormat::Json => log!(
+			target: RELAY_TARGET,
+			level,
+			"{}",
+			serde_json::to_string(&step).expect("`RelayStep` serializes; qed")
+		),
+	}
+}
+
+/// `record` logged at `timestamp` as a single line json object.
+/// the fields of relay steps are added to the object
+pub fn json_record(timestamp: &str, record: &Record) -> String {
+	let message = record.args().to_string();
+	let mut object = json!({
+		"timestamp": timestamp,
+		"level": record.level().to_string(),
+		"target": record.target(),
+	});
+	let step = if record.target() == RELAY_TARGET {
+		serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(&message).ok()
+	} else {
+		None
+	};
+	match step {
+		Some(step) => object
+			.as_object_mut()
+			.expect("`object` is a json object; qed")
+			.extend(step),
+		None => object["message"] = message.into(),
+	}
+	object.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn logged(target: &str, message: &str) -> serde_json::Value {
+		let line = json_record(
+			"2017-01-01T00:00:00Z",
+			&Record::builder()
+				.args(format_args!("{}", message))
+				.level(Level::Info)
+				.target(target)
+				.build(),
+		);
+		serde_json::from_str(&line).unwrap()
+	}
+
+	#[test]
+	fn test_json_record_of_relay_step() {
+		let entry = RelayEntry {
+			kind: RelayKind::SideToMainSignatures,
+			tx_hash: H256::from_low_u64_be(1),
+			log_index: 0,
+			message_id: Some(H256::from_low_u64_be(2)),
+			status: RelayStatus::Mined,
+			relay_tx_hash: Some(H256::from_low_u64_be(3)),
+			error: None,
+			first_seen: 0,
+			updated: 0,
+		};
+		let step = serde_json::to_string(&RelayStep::new(&entry)).unwrap();
+
+		assert_eq!(
+			logged(RELAY_TARGET, &step),
+			json!({
+				"timestamp": "2017-01-01T00:00:00Z",
+				"level": "INFO",
+				"target": RELAY_TARGET,
+				"relay": "side_to_main_signatures",
+				"step": "mined",
+				"chain": "main",
+				"source_tx_hash": H256::from_low_u64_be(1),
+				"message_id": H256::from_low_u64_be(2),
+				"destination_tx_hash": H256::from_low_u64_be(3),
+				"error": null,
+			})
+		);
+		assert_eq!(
+			logged("parity_bridge", "Started polling logs"),
+			json!({
+				"timestamp": "2017-01-01T00:00:00Z",
+				"level": "INFO",
+				"target": "parity_bridge",
+				"message": "Started polling logs",
+			})
+		);
+	}
+}
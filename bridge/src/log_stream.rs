@@ -19,13 +19,53 @@ use error::{self, ResultExt};
 use ethabi;
 use futures::future::FromErr;
 use futures::{Async, Future, Poll, Stream};
+use std::cmp;
+use std::collections::VecDeque;
 use std::time::Duration;
 use tokio_timer::{Timeout, Timer};
 use web3;
 use web3::api::Namespace;
-use web3::helpers::CallFuture;
-use web3::types::{Address, FilterBuilder, Log, H256};
+use web3::helpers::{self, CallFuture};
+use web3::types::{Address, BlockNumber, FilterBuilder, Log, H256};
 use web3::Transport;
+use ws_transport::WsTransport;
+
+/// how many hashes of checked blocks are remembered.
+/// limits the depth of reorgs `LogStream` can recover from
+const MAX_TRACKED_BLOCK_HASHES: usize = 128;
+
+/// error messages of nodes and providers rejecting an `eth_getLogs` request
+/// that covers too many blocks or would return too many logs.
+/// specific enough that unrelated rpc errors still surface
+const BLOCK_RANGE_TOO_LARGE_MESSAGES: &[&str] = &[
+	// geth, infura
+	"query returned more than",
+	"block range is too wide",
+	"exceed maximum block range",
+	"block range too large",
+	// alchemy
+	"log response size exceeded",
+];
+
+/// whether `err` is a node rejecting an `eth_getLogs` request because of its size
+fn is_block_range_too_large(err: &error::Error) -> bool {
+	match *err.kind() {
+		// web3 errors don't expose the rpc error in a uniform way. look at the message
+		error::ErrorKind::Web3(ref err) => {
+			let message = format!("{:?}", err).to_lowercase();
+			BLOCK_RANGE_TOO_LARGE_MESSAGES
+				.iter()
+				.any(|fragment| message.contains(fragment))
+		}
+		_ => false,
+	}
+}
+
+/// the part of a block `LogStream` is interested in
+#[derive(Deserialize)]
+struct BlockHash {
+	hash: Option<H256>,
+}
 
 fn ethabi_topic_to_web3(topic: &ethabi::Topic<ethabi::Hash>) -> Option<Vec<H256>> {
 	match topic {
@@ -54,24 +94,50 @@ pub struct LogStreamOptions<T> {
 	pub transport: T,
 	pub contract_address: Address,
 	pub after: u64,
+	/// maximum number of blocks per `eth_getLogs` request.
+	/// `None` requests all new blocks at once
+	pub max_block_range: Option<u64>,
+	/// see `BlockNumberStreamOptions::new_heads`
+	pub new_heads: Option<WsTransport>,
 }
 
 /// Contains all logs matching `LogStream` filter in inclusive block range `[from, to]`.
+/// if `reverted` is set the blocks in `[from, to]` are no longer part of the canonical
+/// chain. `logs` is empty then and logs previously yielded for those blocks are invalid.
+/// the blocks are yielded again once they are on the new canonical chain.
 #[derive(Debug, PartialEq)]
 pub struct LogsInBlockRange {
 	pub from: u64,
 	pub to: u64,
 	pub logs: Vec<Log>,
+	pub reverted: bool,
 }
 
+type BlockHashFuture<T> =
+	Timeout<FromErr<CallFuture<Option<BlockHash>, <T as Transport>::Out>, error::Error>>;
+
 /// Log Stream state.
 enum State<T: Transport> {
 	/// Fetching best block number.
 	AwaitBlockNumber,
+	/// Fetching hash of an already checked block to see whether it is still canonical.
+	/// walks back the remembered hashes until one matches on a reorg.
+	AwaitCheckedBlockHash {
+		number: u64,
+		expected: H256,
+		future: BlockHashFuture<T>,
+	},
+	/// Fetching hash of new best block.
+	AwaitBlockHash {
+		from: u64,
+		to: u64,
+		future: BlockHashFuture<T>,
+	},
 	/// Fetching logs for new best block.
 	AwaitLogs {
 		from: u64,
 		to: u64,
+		hash: H256,
 		future: Timeout<FromErr<CallFuture<Vec<Log>, T::Out>, error::Error>>,
 	},
 }
@@ -79,11 +145,23 @@ enum State<T: Transport> {
 /// `Stream` that repeatedly polls logs matching `filter_builder` from `contract_address`
 /// with adjustable `poll_interval` and `request_timeout`.
 /// yields new logs that are `confirmations` blocks deep.
+/// detects reorgs deeper than that through the hashes of checked blocks
+/// and yields a `reverted` range for the blocks that were removed.
+/// catches up in ranges of at most `max_block_range` blocks. the range is halved
+/// whenever the node rejects a request for being too large.
+/// endless unless limited with `until`.
 pub struct LogStream<T: Transport> {
 	block_number_stream: BlockNumberStream<T>,
 	request_timeout: Duration,
 	transport: T,
 	last_checked_block: u64,
+	/// last confirmed block. yielded ranges end here once caught up
+	last_confirmed_block: u64,
+	max_block_range: Option<u64>,
+	/// last block to check. the stream ends once it is checked
+	until: Option<u64>,
+	/// hashes of the last blocks of yielded ranges. oldest first
+	block_hashes: VecDeque<(u64, H256)>,
 	timer: Timer,
 	state: State<T>,
 	filter_builder: FilterBuilder,
@@ -104,6 +182,7 @@ impl<T: Transport> LogStream<T> {
 			confirmations: options.confirmations,
 			transport: options.transport.clone(),
 			after: options.after,
+			new_heads: options.new_heads,
 		};
 
 		LogStream {
@@ -111,12 +190,48 @@ impl<T: Transport> LogStream<T> {
 			request_timeout: options.request_timeout,
 			transport: options.transport,
 			last_checked_block: options.after,
+			last_confirmed_block: options.after,
+			max_block_range: options.max_block_range,
+			until: None,
+			block_hashes: VecDeque::new(),
 			timer,
 			state: State::AwaitBlockNumber,
 			filter_builder,
 			topic,
 		}
 	}
+
+	/// ends the stream once the blocks up to `block` are checked
+	pub fn until(mut self, block: u64) -> Self {
+		self.until = Some(block);
+		self
+	}
+
+	fn block_hash(&self, number: u64) -> BlockHashFuture<T> {
+		let future = CallFuture::new(self.transport.execute(
+			"eth_getBlockByNumber",
+			vec![
+				helpers::serialize(&BlockNumber::Number(number.into())),
+				helpers::serialize(&false),
+			],
+		));
+		self.timer.timeout(future.from_err(), self.request_timeout)
+	}
+
+	/// the next range starting at `from`
+	fn new_range(&self, from: u64) -> State<T> {
+		let to = match self.max_block_range {
+			Some(max_block_range) => {
+				cmp::min(self.last_confirmed_block, from + max_block_range - 1)
+			}
+			None => self.last_confirmed_block,
+		};
+		State::AwaitBlockHash {
+			from,
+			to,
+			future: self.block_hash(to),
+		}
+	}
 }
 
 impl<T: Transport> Stream for LogStream<T> {
@@ -125,6 +240,12 @@ impl<T: Transport> Stream for LogStream<T> {
 
 	fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
 		loop {
+			if let Some(until) = self.until {
+				if self.last_checked_block >= until {
+					return Ok(Async::Ready(None));
+				}
+			}
+
 			let (next_state, value_to_yield) = match self.state {
 				State::AwaitBlockNumber => {
 					let last_block = try_stream!(self
@@ -132,24 +253,102 @@ impl<T: Transport> Stream for LogStream<T> {
 						.poll()
 						.chain_err(|| "LogStream: fetching of last confirmed block number failed"));
 					info!("LogStream: fetched confirmed block number {}", last_block);
+					self.last_confirmed_block = match self.until {
+						Some(until) => cmp::min(last_block, until),
+						None => last_block,
+					};
+
+					let next_state = match self.block_hashes.back() {
+						// make sure the chain we checked so far is still canonical
+						Some(&(number, expected)) => State::AwaitCheckedBlockHash {
+							number,
+							expected,
+							future: self.block_hash(number),
+						},
+						None => self.new_range(self.last_checked_block + 1),
+					};
+
+					(next_state, None)
+				}
+				State::AwaitCheckedBlockHash {
+					ref mut future,
+					number,
+					expected,
+				} => {
+					let block = try_ready!(future
+						.poll()
+						.chain_err(|| "LogStream: fetching checked block failed"));
+					let hash = block.and_then(|block| block.hash);
+
+					if hash == Some(expected) {
+						if number == self.last_checked_block {
+							(self.new_range(number + 1), None)
+						} else {
+							// found the common ancestor of the old and the new canonical chain
+							warn!(
+								"LogStream (topic: {:?}): blocks {} to {} were reorganised",
+								self.topic,
+								number + 1,
+								self.last_checked_block
+							);
+							let reverted = LogsInBlockRange {
+								from: number + 1,
+								to: self.last_checked_block,
+								logs: Vec::new(),
+								reverted: true,
+							};
+							self.last_checked_block = number;
+							(self.new_range(number + 1), Some(reverted))
+						}
+					} else {
+						info!(
+							"LogStream (topic: {:?}): block {} is no longer canonical",
+							self.topic, number
+						);
+						self.block_hashes.pop_back();
+						let (number, expected) = match self.block_hashes.back() {
+							Some(&block) => block,
+							None => bail!(
+								"LogStream: reorg of block {} is deeper than the {} remembered blocks",
+								number,
+								MAX_TRACKED_BLOCK_HASHES
+							),
+						};
+						let next_state = State::AwaitCheckedBlockHash {
+							number,
+							expected,
+							future: self.block_hash(number),
+						};
+						(next_state, None)
+					}
+				}
+				State::AwaitBlockHash {
+					ref mut future,
+					from,
+					to,
+				} => {
+					let block = try_ready!(future
+						.poll()
+						.chain_err(|| "LogStream: fetching new best block failed"));
+					let hash = match block.and_then(|block| block.hash) {
+						Some(hash) => hash,
+						None => bail!("LogStream: block {} not found", to),
+					};
 
-					let from = self.last_checked_block + 1;
 					let filter = self
 						.filter_builder
 						.clone()
 						.from_block(from.into())
-						.to_block(last_block.into())
+						.to_block(to.into())
 						.build();
 					let future = web3::api::Eth::new(&self.transport).logs(filter);
 
-					info!(
-						"LogStream: fetching logs in blocks {} to {}",
-						from, last_block
-					);
+					info!("LogStream: fetching logs in blocks {} to {}", from, to);
 
 					let next_state = State::AwaitLogs {
-						from: from,
-						to: last_block,
+						from,
+						to,
+						hash,
 						future: self.timer.timeout(future.from_err(), self.request_timeout),
 					};
 
@@ -159,10 +358,27 @@ impl<T: Transport> Stream for LogStream<T> {
 					ref mut future,
 					from,
 					to,
+					hash,
 				} => {
-					let logs = try_ready!(future
-						.poll()
-						.chain_err(|| "LogStream: polling web3 logs failed"));
+					let logs = match future.poll() {
+						Err(ref err) if to > from && is_block_range_too_large(err) => None,
+						result => Some(try_ready!(
+							result.chain_err(|| "LogStream: polling web3 logs failed")
+						)),
+					};
+					let logs = match logs {
+						Some(logs) => logs,
+						None => {
+							let max_block_range = (to - from + 1) / 2;
+							warn!(
+								"LogStream (topic: {:?}): node rejected logs from block {} to block {}. retrying with ranges of {} blocks",
+								self.topic, from, to, max_block_range
+							);
+							self.max_block_range = Some(max_block_range);
+							self.state = self.new_range(from);
+							continue;
+						}
+					};
 					info!(
 						"LogStream (topic: {:?}): fetched {} logs from block {} to block {}",
 						self.topic,
@@ -170,10 +386,29 @@ impl<T: Transport> Stream for LogStream<T> {
 						from,
 						to
 					);
-					let log_range_to_yield = LogsInBlockRange { from, to, logs };
+					let logs = logs
+						.into_iter()
+						.filter(|log| log.removed != Some(true))
+						.collect();
+					let log_range_to_yield = LogsInBlockRange {
+						from,
+						to,
+						logs,
+						reverted: false,
+					};
 
 					self.last_checked_block = to;
-					(State::AwaitBlockNumber, Some(log_range_to_yield))
+					self.block_hashes.push_back((to, hash));
+					if self.block_hashes.len() > MAX_TRACKED_BLOCK_HASHES {
+						self.block_hashes.pop_front();
+					}
+					let next_state = if to < self.last_confirmed_block {
+						// still catching up
+						self.new_range(to + 1)
+					} else {
+						State::AwaitBlockNumber
+					};
+					(next_state, Some(log_range_to_yield))
 				}
 			};
 
@@ -191,9 +426,15 @@ mod tests {
 	use super::*;
 	use contracts;
 	use rustc_hex::FromHex;
+	use serde_json;
 	use tokio_core::reactor::Core;
+	use web3::rpc;
 	use web3::types::{Bytes, Log};
 
+	fn block(hash: u64) -> serde_json::Value {
+		json!({ "hash": format!("0x{:064x}", hash) })
+	}
+
 	#[test]
 	fn test_log_stream_twice_no_logs() {
 		let deposit_topic = contracts::main::events::relay_message::filter().topic0;
@@ -202,6 +443,9 @@ mod tests {
 			"eth_blockNumber" =>
 				req => json!([]),
 				res => json!("0x1011");
+			"eth_getBlockByNumber" =>
+				req => json!(["0x1005", false]),
+				res => block(1);
 			"eth_getLogs" =>
 				req => json!([{
 					"address": "0x0000000000000000000000000000000000000001",
@@ -213,6 +457,12 @@ mod tests {
 			"eth_blockNumber" =>
 				req => json!([]),
 				res => json!("0x1012");
+			"eth_getBlockByNumber" =>
+				req => json!(["0x1005", false]),
+				res => block(1);
+			"eth_getBlockByNumber" =>
+				req => json!(["0x1006", false]),
+				res => block(2);
 			"eth_getLogs" =>
 				req => json!([{
 					"address": "0x0000000000000000000000000000000000000001",
@@ -231,6 +481,8 @@ mod tests {
 			contract_address: "0000000000000000000000000000000000000001".parse().unwrap(),
 			after: 3,
 			filter: contracts::main::events::relay_message::filter(),
+			max_block_range: None,
+			new_heads: None,
 		});
 
 		let mut event_loop = Core::new().unwrap();
@@ -243,11 +495,13 @@ mod tests {
 					from: 4,
 					to: 4101,
 					logs: vec![],
+					reverted: false,
 				},
 				LogsInBlockRange {
 					from: 4102,
 					to: 4102,
 					logs: vec![],
+					reverted: false,
 				},
 			]
 		);
@@ -262,6 +516,9 @@ mod tests {
 			"eth_blockNumber" =>
 				req => json!([]),
 				res => json!("0x1011");
+			"eth_getBlockByNumber" =>
+				req => json!(["0x1005", false]),
+				res => block(1);
 			"eth_getLogs" =>
 				req => json!([{
 					"address": "0x0000000000000000000000000000000000000001",
@@ -286,6 +543,8 @@ mod tests {
 			contract_address: "0000000000000000000000000000000000000001".parse().unwrap(),
 			after: 3,
 			filter: contracts::main::events::relay_message::filter(),
+			max_block_range: None,
+			new_heads: None,
 		});
 
 		let mut event_loop = Core::new().unwrap();
@@ -308,8 +567,269 @@ mod tests {
 						log_type: None,
 						removed: None,
 					}
-				] },
+				], reverted: false },
 			]);
 		assert_eq!(transport.actual_requests(), transport.expected_requests());
 	}
+
+	#[test]
+	fn test_log_stream_reverts_reorganised_blocks() {
+		let deposit_topic = contracts::main::events::relay_message::filter().topic0;
+
+		let transport = mock_transport!(
+			"eth_blockNumber" =>
+				req => json!([]),
+				res => json!("0x1011");
+			"eth_getBlockByNumber" =>
+				req => json!(["0x1005", false]),
+				res => block(1);
+			"eth_getLogs" =>
+				req => json!([{
+					"address": "0x0000000000000000000000000000000000000001",
+					"fromBlock": "0x4",
+					"toBlock": "0x1005",
+					"topics": [deposit_topic]
+				}]),
+				res => json!([]);
+			"eth_blockNumber" =>
+				req => json!([]),
+				res => json!("0x1012");
+			"eth_getBlockByNumber" =>
+				req => json!(["0x1005", false]),
+				res => block(1);
+			"eth_getBlockByNumber" =>
+				req => json!(["0x1006", false]),
+				res => block(2);
+			"eth_getLogs" =>
+				req => json!([{
+					"address": "0x0000000000000000000000000000000000000001",
+					"fromBlock": "0x1006",
+					"toBlock": "0x1006",
+					"topics": [deposit_topic]
+				}]),
+				res => json!([]);
+			"eth_blockNumber" =>
+				req => json!([]),
+				res => json!("0x1013");
+			// block 0x1006 was replaced
+			"eth_getBlockByNumber" =>
+				req => json!(["0x1006", false]),
+				res => block(3);
+			"eth_getBlockByNumber" =>
+				req => json!(["0x1005", false]),
+				res => block(1);
+			"eth_getBlockByNumber" =>
+				req => json!(["0x1007", false]),
+				res => block(4);
+			"eth_getLogs" =>
+				req => json!([{
+					"address": "0x0000000000000000000000000000000000000001",
+					"fromBlock": "0x1006",
+					"toBlock": "0x1007",
+					"topics": [deposit_topic]
+				}]),
+				res => json!([]);
+		);
+
+		let log_stream = LogStream::new(LogStreamOptions {
+			request_timeout: Duration::from_secs(1),
+			poll_interval: Duration::from_secs(0),
+			confirmations: 12,
+			transport: transport.clone(),
+			contract_address: "0000000000000000000000000000000000000001".parse().unwrap(),
+			after: 3,
+			filter: contracts::main::events::relay_message::filter(),
+			max_block_range: None,
+			new_heads: None,
+		});
+
+		let mut event_loop = Core::new().unwrap();
+		let log_ranges = event_loop.run(log_stream.take(4).collect()).unwrap();
+
+		assert_eq!(
+			log_ranges,
+			vec![
+				LogsInBlockRange {
+					from: 4,
+					to: 4101,
+					logs: vec![],
+					reverted: false,
+				},
+				LogsInBlockRange {
+					from: 4102,
+					to: 4102,
+					logs: vec![],
+					reverted: false,
+				},
+				LogsInBlockRange {
+					from: 4102,
+					to: 4102,
+					logs: vec![],
+					reverted: true,
+				},
+				LogsInBlockRange {
+					from: 4102,
+					to: 4103,
+					logs: vec![],
+					reverted: false,
+				},
+			]
+		);
+		assert_eq!(transport.actual_requests(), transport.expected_requests());
+	}
+
+	#[test]
+	fn test_log_stream_catches_up_in_block_ranges() {
+		let deposit_topic = contracts::main::events::relay_message::filter().topic0;
+
+		let transport = mock_transport!(
+			"eth_blockNumber" =>
+				req => json!([]),
+				res => json!("0x1011");
+			"eth_getBlockByNumber" =>
+				req => json!(["0x803", false]),
+				res => block(1);
+			"eth_getLogs" =>
+				req => json!([{
+					"address": "0x0000000000000000000000000000000000000001",
+					"fromBlock": "0x4",
+					"toBlock": "0x803",
+					"topics": [deposit_topic]
+				}]),
+				res => json!([]);
+			"eth_getBlockByNumber" =>
+				req => json!(["0x1003", false]),
+				res => block(2);
+			"eth_getLogs" =>
+				req => json!([{
+					"address": "0x0000000000000000000000000000000000000001",
+					"fromBlock": "0x804",
+					"toBlock": "0x1003",
+					"topics": [deposit_topic]
+				}]),
+				res => json!([]);
+			"eth_getBlockByNumber" =>
+				req => json!(["0x1005", false]),
+				res => block(3);
+			"eth_getLogs" =>
+				req => json!([{
+					"address": "0x0000000000000000000000000000000000000001",
+					"fromBlock": "0x1004",
+					"toBlock": "0x1005",
+					"topics": [deposit_topic]
+				}]),
+				res => json!([]);
+		);
+
+		let log_stream = LogStream::new(LogStreamOptions {
+			request_timeout: Duration::from_secs(1),
+			poll_interval: Duration::from_secs(0),
+			confirmations: 12,
+			transport: transport.clone(),
+			contract_address: "0000000000000000000000000000000000000001".parse().unwrap(),
+			after: 3,
+			filter: contracts::main::events::relay_message::filter(),
+			max_block_range: Some(0x800),
+			new_heads: None,
+		});
+
+		let mut event_loop = Core::new().unwrap();
+		let log_ranges = event_loop.run(log_stream.take(3).collect()).unwrap();
+
+		assert_eq!(
+			log_ranges,
+			vec![
+				LogsInBlockRange {
+					from: 0x4,
+					to: 0x803,
+					logs: vec![],
+					reverted: false,
+				},
+				LogsInBlockRange {
+					from: 0x804,
+					to: 0x1003,
+					logs: vec![],
+					reverted: false,
+				},
+				LogsInBlockRange {
+					from: 0x1004,
+					to: 0x1005,
+					logs: vec![],
+					reverted: false,
+				},
+			]
+		);
+		assert_eq!(transport.actual_requests(), transport.expected_requests());
+	}
+
+	#[test]
+	fn test_log_stream_until_ends_after_block() {
+		let deposit_topic = contracts::main::events::relay_message::filter().topic0;
+
+		let transport = mock_transport!(
+			"eth_blockNumber" =>
+				req => json!([]),
+				res => json!("0x1011");
+			"eth_getBlockByNumber" =>
+				req => json!(["0x10", false]),
+				res => block(1);
+			"eth_getLogs" =>
+				req => json!([{
+					"address": "0x0000000000000000000000000000000000000001",
+					"fromBlock": "0x4",
+					"toBlock": "0x10",
+					"topics": [deposit_topic]
+				}]),
+				res => json!([]);
+		);
+
+		let log_stream = LogStream::new(LogStreamOptions {
+			request_timeout: Duration::from_secs(1),
+			poll_interval: Duration::from_secs(0),
+			confirmations: 12,
+			transport: transport.clone(),
+			contract_address: "0000000000000000000000000000000000000001".parse().unwrap(),
+			after: 3,
+			filter: contracts::main::events::relay_message::filter(),
+			max_block_range: None,
+			new_heads: None,
+		})
+		.until(0x10);
+
+		let mut event_loop = Core::new().unwrap();
+		let log_ranges = event_loop.run(log_stream.collect()).unwrap();
+
+		assert_eq!(
+			log_ranges,
+			vec![LogsInBlockRange {
+				from: 0x4,
+				to: 0x10,
+				logs: vec![],
+				reverted: false,
+			}]
+		);
+		assert_eq!(transport.actual_requests(), transport.expected_requests());
+	}
+
+	#[test]
+	fn test_is_block_range_too_large() {
+		let rpc_error = |message: &str| -> error::Error {
+			web3::Error::Rpc(rpc::Error {
+				code: rpc::ErrorCode::ServerError(-32005),
+				message: message.into(),
+				data: None,
+			})
+			.into()
+		};
+		assert!(is_block_range_too_large(&rpc_error(
+			"query returned more than 10000 results"
+		)));
+		assert!(is_block_range_too_large(&rpc_error(
+			"exceed maximum block range: 5000"
+		)));
+		assert!(!is_block_range_too_large(&rpc_error(
+			"gas required exceeds allowance or always failing transaction, more than 10 calls limited to 5"
+		)));
+		assert!(!is_block_range_too_large(&rpc_error("rate limit exceeded")));
+	}
 }
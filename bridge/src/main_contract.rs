@@ -17,13 +17,20 @@ use config::Config;
 use contracts;
 use database::State;
 use ethabi::FunctionOutputDecoder;
-use ethereum_types::{Address, H256, U256};
+use ethereum_types::{Address, H256};
+use gas_limit::GasLimit;
+use gas_price::GasPriceOracle;
 use helpers::{AsyncCall, AsyncTransaction};
 use log_stream::{LogStream, LogStreamOptions};
 use message_to_main::MessageToMain;
+use nonce_manager::NonceManager;
+use send_tx_with_receipt::{TrackedTransaction, TrackedTransactionOptions};
 use signature::Signature;
+use signer::Signer;
 use std::time::Duration;
+use web3::types::{Bytes, TransactionRequest};
 use web3::Transport;
+use ws_transport::WsTransport;
 
 /// highlevel wrapper around the auto generated ethabi contract `bridge_contracts::main`
 #[derive(Clone)]
@@ -31,22 +38,52 @@ pub struct MainContract<T> {
 	pub transport: T,
 	pub contract_address: Address,
 	pub authority_address: Address,
-	pub submit_collected_signatures_gas: U256,
+	pub submit_collected_signatures_gas: GasLimit,
+	pub submit_collected_signatures_gas_price: GasPriceOracle,
 	pub request_timeout: Duration,
 	pub logs_poll_interval: Duration,
 	pub required_log_confirmations: u32,
+	/// if set transactions are signed locally instead of by the node
+	pub signer: Option<Signer>,
+	pub nonce_manager: NonceManager<T>,
+	pub resubmit_after: Duration,
+	pub gas_price_bump_percent: u32,
+	/// maximum number of blocks per `eth_getLogs` call of the log streams
+	pub max_log_block_range: Option<u64>,
+	/// if set the log streams learn about new blocks through a subscription
+	pub new_heads: Option<WsTransport>,
 }
 
 impl<T: Transport> MainContract<T> {
-	pub fn new(transport: T, config: &Config, state: &State) -> Self {
+	pub fn new(
+		transport: T,
+		new_heads: Option<WsTransport>,
+		config: &Config,
+		state: &State,
+		signer: Option<Signer>,
+	) -> Self {
 		Self {
+			nonce_manager: NonceManager::new(
+				transport.clone(),
+				config.address,
+				config.main.request_timeout,
+			),
 			transport,
 			contract_address: state.main_contract_address,
 			authority_address: config.address,
-			submit_collected_signatures_gas: config.estimated_gas_cost_of_withdraw,
+			submit_collected_signatures_gas: GasLimit::new(
+				config.estimated_gas_cost_of_withdraw,
+				&config.txs.withdraw_relay,
+			),
+			submit_collected_signatures_gas_price: GasPriceOracle::new(&config.txs.withdraw_relay),
 			request_timeout: config.main.request_timeout,
 			logs_poll_interval: config.main.poll_interval,
 			required_log_confirmations: config.main.required_confirmations,
+			signer,
+			resubmit_after: config.main.resubmit_after,
+			gas_price_bump_percent: config.main.gas_price_bump_percent,
+			max_log_block_range: config.main.max_log_block_range,
+			new_heads,
 		}
 	}
 
@@ -64,6 +101,47 @@ impl<T: Transport> MainContract<T> {
 		)
 	}
 
+	/// send a transaction with `payload` to the main contract.
+	/// the returned `Future` resolves once it is mined with `required_log_confirmations`.
+	/// the gas limit comes from `gas` and the gas price from `gas_price`
+	/// which also caps re-submissions
+	pub fn transaction(
+		&self,
+		gas: &GasLimit,
+		gas_price: &GasPriceOracle,
+		payload: Vec<u8>,
+	) -> TrackedTransaction<T> {
+		let request = TransactionRequest {
+			from: self.authority_address,
+			to: Some(self.contract_address),
+			gas: None,
+			gas_price: None,
+			value: None,
+			data: Some(Bytes(payload)),
+			nonce: None,
+			condition: None,
+		};
+		let transaction = AsyncTransaction::new(
+			&self.transport,
+			self.signer.as_ref(),
+			&self.nonce_manager,
+			gas.gas(&self.transport, &request, self.request_timeout),
+			gas_price.fees(&self.transport, self.request_timeout),
+			request,
+			self.request_timeout,
+		);
+		TrackedTransaction::new(TrackedTransactionOptions {
+			transport: self.transport.clone(),
+			transaction,
+			request_timeout: self.request_timeout,
+			poll_interval: self.logs_poll_interval,
+			confirmations: self.required_log_confirmations,
+			resubmit_after: self.resubmit_after,
+			gas_price_bump_percent: self.gas_price_bump_percent,
+			max_gas_price: gas_price.max_gas_price(),
+		})
+	}
+
 	pub fn is_main_contract(
 		&self,
 	) -> AsyncCall<T, contracts::main::functions::is_main_bridge_contract::Decoder> {
@@ -77,7 +155,7 @@ impl<T: Transport> MainContract<T> {
 		message: &MessageToMain,
 		signatures: &Vec<Signature>,
 		data: Vec<u8>,
-	) -> AsyncTransaction<T> {
+	) -> TrackedTransaction<T> {
 		let payload = contracts::main::functions::accept_message::encode_input(
 			signatures.iter().map(|x| x.v),
 			signatures.iter().map(|x| x.r),
@@ -88,15 +166,9 @@ impl<T: Transport> MainContract<T> {
 			message.recipient,
 		);
 
-		AsyncTransaction::new(
-			&self.transport,
-			self.contract_address,
-			self.authority_address,
-			self.submit_collected_signatures_gas,
-			// TODO:
-			//message.main_gas_price,
-			1000.into(),
-			self.request_timeout,
+		self.transaction(
+			&self.submit_collected_signatures_gas,
+			&self.submit_collected_signatures_gas_price,
 			payload,
 		)
 	}
@@ -110,6 +182,8 @@ impl<T: Transport> MainContract<T> {
 			transport: self.transport.clone(),
 			contract_address: self.contract_address,
 			after,
+			max_block_range: self.max_log_block_range,
+			new_heads: self.new_heads.clone(),
 		})
 	}
 
@@ -0,0 +1,315 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+// This file is part of Parity-Bridge.
+
+// Parity-Bridge is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity-Bridge is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity-Bridge.  If not, see <http://www.gnu.org/licenses/>.
+
+//! relays the bridge logs of a single transaction for `parity-bridge relay`.
+//! lets operators retry a stuck relay without rewinding the database.
+//! the relay futures check whether the relay already happened as usual.
+
+use accept_message_from_main::LogToAcceptMessageFromMain;
+use contracts;
+use error;
+use ethabi;
+use futures::future::{self, join_all, Either};
+use futures::Future;
+use journal::Journal;
+use main_contract::MainContract;
+use relay_stream::LogToFuture;
+use side_contract::SideContract;
+use side_to_main_sign::LogToSideToMainSign;
+use side_to_main_signatures::LogToSideToMainSignatures;
+use std::time::Duration;
+use tokio_timer::Timer;
+use web3;
+use web3::api::Namespace;
+use web3::types::{Address, Log, H256};
+use web3::Transport;
+
+fn topic_matches(topic: &ethabi::Topic<ethabi::Hash>, value: Option<&H256>) -> bool {
+	match (topic, value) {
+		(ethabi::Topic::Any, _) => true,
+		(ethabi::Topic::OneOf(options), Some(value)) => options.contains(value),
+		(ethabi::Topic::This(hash), Some(value)) => hash == value,
+		_ => false,
+	}
+}
+
+/// the logs in `logs` emitted by `address` that `filter` matches
+fn matching_logs(logs: &[Log], filter: &ethabi::TopicFilter, address: Address) -> Vec<Log> {
+	logs.iter()
+		.filter(|log| {
+			log.address == address
+				&& topic_matches(&filter.topic0, log.topics.get(0))
+				&& topic_matches(&filter.topic1, log.topics.get(1))
+				&& topic_matches(&filter.topic2, log.topics.get(2))
+				&& topic_matches(&filter.topic3, log.topics.get(3))
+		})
+		.cloned()
+		.collect()
+}
+
+/// resolves with the logs of the transaction `tx_hash`.
+/// fails unless it is mined with `required_confirmations` like the bridge
+/// only relays logs of confirmed blocks
+fn transaction_logs<T: Transport>(
+	transport: &T,
+	tx_hash: H256,
+	required_confirmations: u32,
+	request_timeout: Duration,
+) -> impl Future<Item = Vec<Log>, Error = error::Error> {
+	let eth = web3::api::Eth::new(transport.clone());
+	let timer = Timer::default();
+	let receipt = timer.timeout(eth.transaction_receipt(tx_hash).from_err(), request_timeout);
+	let head = timer.timeout(eth.block_number().from_err(), request_timeout);
+	receipt.join(head).and_then(move |(receipt, head)| {
+		let receipt = match receipt {
+			Some(receipt) => receipt,
+			None => bail!("transaction {:?} is unknown or not mined yet", tx_hash),
+		};
+		let block_number = match receipt.block_number {
+			Some(block_number) => block_number.as_u64(),
+			None => bail!("transaction {:?} is not mined yet", tx_hash),
+		};
+		let confirmed = head
+			.as_u64()
+			.saturating_sub(u64::from(required_confirmations));
+		if block_number > confirmed {
+			bail!(
+				"transaction {:?} in block {} is not confirmed yet. the last confirmed block is {}",
+				tx_hash,
+				block_number,
+				confirmed
+			);
+		}
+		Ok(receipt.logs)
+	})
+}
+
+/// relays the `RelayMessage` logs of main transaction `tx_hash` to side.
+/// resolves with the number of relays
+pub fn relay_main_transaction<T: Transport>(
+	main: MainContract<T>,
+	side: SideContract<T>,
+	journal: Journal,
+	tx_hash: H256,
+) -> impl Future<Item = usize, Error = error::Error> {
+	transaction_logs(
+		&main.transport,
+		tx_hash,
+		main.required_log_confirmations,
+		main.request_timeout,
+	)
+	.and_then(move |logs| {
+		let logs = matching_logs(
+			&logs,
+			&contracts::main::events::relay_message::filter(),
+			main.contract_address,
+		);
+		if logs.is_empty() {
+			return Either::A(future::err(
+				format!(
+					"transaction {:?} has no `RelayMessage` logs of the main contract",
+					tx_hash
+				)
+				.into(),
+			));
+		}
+		info!(
+			"relaying {} messages of main transaction {:?}",
+			logs.len(),
+			tx_hash
+		);
+		let log_to_future = LogToAcceptMessageFromMain {
+			main,
+			side,
+			journal,
+		};
+		let relays = logs
+			.iter()
+			.map(|log| {
+				log_to_future.log_seen(log);
+				log_to_future.log_to_future(log)
+			})
+			.collect::<Vec<_>>();
+		Either::B(join_all(relays).map(|relays| relays.len()))
+	})
+}
+
+/// relays the `RelayMessage` logs (signing them) and the `SignedMessage` logs this
+/// authority is responsible for (submitting the signatures to main) of side
+/// transaction `tx_hash`. resolves with the number of relays
+pub fn relay_side_transaction<T: Transport>(
+	main: MainContract<T>,
+	side: SideContract<T>,
+	journal: Journal,
+	tx_hash: H256,
+) -> impl Future<Item = usize, Error = error::Error> {
+	transaction_logs(
+		&side.transport,
+		tx_hash,
+		side.required_log_confirmations,
+		side.request_timeout,
+	)
+	.and_then(move |logs| {
+		let sign_logs = matching_logs(
+			&logs,
+			&contracts::side::events::relay_message::filter(),
+			side.contract_address,
+		);
+		let signatures_logs = matching_logs(
+			&logs,
+			&contracts::side::events::signed_message::filter(main.authority_address),
+			side.contract_address,
+		);
+		if sign_logs.is_empty() && signatures_logs.is_empty() {
+			return Either::A(future::err(
+				format!(
+					"transaction {:?} has no `RelayMessage` or `SignedMessage` logs of the side contract for this authority",
+					tx_hash
+				)
+				.into(),
+			));
+		}
+		info!(
+			"relaying {} messages to sign and {} collected signatures of side transaction {:?}",
+			sign_logs.len(),
+			signatures_logs.len(),
+			tx_hash
+		);
+		let to_sign = LogToSideToMainSign {
+			side: side.clone(),
+			journal: journal.clone(),
+		};
+		let to_signatures = LogToSideToMainSignatures {
+			main,
+			side,
+			journal,
+		};
+		let sign = sign_logs
+			.iter()
+			.map(|log| {
+				to_sign.log_seen(log);
+				to_sign.log_to_future(log)
+			})
+			.collect::<Vec<_>>();
+		let signatures = signatures_logs
+			.iter()
+			.map(|log| {
+				to_signatures.log_seen(log);
+				to_signatures.log_to_future(log)
+			})
+			.collect::<Vec<_>>();
+		Either::B(
+			join_all(sign)
+				.join(join_all(signatures))
+				.map(|(sign, signatures)| sign.len() + signatures.len()),
+		)
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use tokio_core::reactor::Core;
+
+	#[test]
+	fn test_matching_logs() {
+		let address: Address = "0000000000000000000000000000000000000001".parse().unwrap();
+		let authority: Address = "0000000000000000000000000000000000000002".parse().unwrap();
+		let filter = contracts::side::events::signed_message::filter(authority);
+		let other_filter = contracts::side::events::signed_message::filter(address);
+
+		let log = |address: Address, authority_topic: H256| Log {
+			address,
+			topics: vec![filter.topic0[0], authority_topic],
+			data: Default::default(),
+			transaction_hash: None,
+			block_hash: None,
+			block_number: None,
+			transaction_index: None,
+			log_index: None,
+			transaction_log_index: None,
+			log_type: None,
+			removed: None,
+		};
+		let logs = vec![
+			log(address, filter.topic1[0]),
+			// emitted by another contract
+			log(authority, filter.topic1[0]),
+			// another authority is responsible
+			log(address, other_filter.topic1[0]),
+		];
+
+		assert_eq!(
+			matching_logs(&logs, &filter, address),
+			vec![log(address, filter.topic1[0])]
+		);
+	}
+
+	#[test]
+	fn test_transaction_logs_requires_confirmations() {
+		let receipt = json!({
+			"blockHash": "0xe0bdcf35b14a292d2998308d9b3fdea93a8c3d9c0b6c824c633fb9b15f9c3919",
+			"blockNumber": "0x1010",
+			"contractAddress": null,
+			"cumulativeGasUsed": "0x1c1999",
+			"gasUsed": "0xcdb5d",
+			"logs": [],
+			"logsBloom": "0x00000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000",
+			"root": null,
+			"status": "0x1",
+			"transactionHash": "0x0000000000000000000000000000000000000000000000000000000000000001",
+			"transactionIndex": "0x4"
+		});
+		let transport = mock_transport!(
+			"eth_getTransactionReceipt" =>
+				req => json!(["0x0000000000000000000000000000000000000000000000000000000000000001"]),
+				res => receipt.clone();
+			"eth_blockNumber" =>
+				req => json!([]),
+				res => json!("0x1011");
+			"eth_getTransactionReceipt" =>
+				req => json!(["0x0000000000000000000000000000000000000000000000000000000000000001"]),
+				res => receipt;
+			"eth_blockNumber" =>
+				req => json!([]),
+				res => json!("0x1012");
+		);
+		let tx_hash = H256::from_low_u64_be(1);
+		let mut event_loop = Core::new().unwrap();
+
+		let err = event_loop
+			.run(transaction_logs(
+				&transport,
+				tx_hash,
+				2,
+				Duration::from_secs(1),
+			))
+			.unwrap_err();
+		assert!(err.to_string().contains("not confirmed yet"));
+		assert_eq!(
+			event_loop
+				.run(transaction_logs(
+					&transport,
+					tx_hash,
+					2,
+					Duration::from_secs(1)
+				))
+				.unwrap(),
+			vec![]
+		);
+		assert_eq!(transport.actual_requests(), transport.expected_requests());
+	}
+}
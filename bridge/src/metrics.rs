@@ -0,0 +1,447 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+// This file is part of Parity-Bridge.
+
+// Parity-Bridge is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity-Bridge is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity-Bridge.  If not, see <http://www.gnu.org/licenses/>.
+
+//! prometheus metrics of the bridge process, served over http at `/metrics`
+//! if a `[metrics]` section is configured. the same server answers `/health`
+//! and `/ready` for orchestrators.
+//!
+//! the parts of the bridge record into a shared `Metrics` instance:
+//! `Bridge` the relay cursors and relays in flight, the `Journal` every
+//! relay step and `NodeTransport` the duration of every rpc request.
+//! chain heads and authority balances are polled by `watch_chain`.
+
+use error::{self, ResultExt};
+use futures::{Future, Stream};
+use journal::{RelayKind, RelayStatus};
+use prometheus::{
+	Encoder, GaugeVec, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry,
+	TextEncoder,
+};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use tokio_core::net::TcpListener;
+use tokio_core::reactor::Handle;
+use tokio_io::io::{read, write_all};
+use tokio_timer::Timer;
+use web3;
+use web3::api::Namespace;
+use web3::types::Address;
+use web3::Transport;
+
+/// relays and the chain whose logs they watch
+const RELAYS: [(RelayKind, &str); 3] = [
+	(RelayKind::MainToSideSign, "main"),
+	(RelayKind::SideToMainSign, "side"),
+	(RelayKind::SideToMainSignatures, "side"),
+];
+
+fn seconds(duration: Duration) -> f64 {
+	duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) / 1_000_000_000.0
+}
+
+/// `clone`d versions record into the same metrics
+#[derive(Clone)]
+pub struct Metrics {
+	registry: Registry,
+	last_checked_block: IntGaugeVec,
+	head_block: IntGaugeVec,
+	confirmed_block: IntGaugeVec,
+	lag_blocks: IntGaugeVec,
+	relays_in_flight: IntGaugeVec,
+	relay_steps: IntCounterVec,
+	rpc_duration: HistogramVec,
+	authority_balance: GaugeVec,
+	started: Instant,
+	/// last response of a node per chain (rpc errors included)
+	last_responses: Rc<RefCell<HashMap<String, Instant>>>,
+}
+
+/// when `/ready` reports a bridge node as not ready
+#[derive(Debug, Clone)]
+pub struct Readiness {
+	/// longest time without a response from the node of a chain
+	pub max_unreachable: Duration,
+	/// most blocks a relay may lag behind the confirmed head of its chain
+	pub max_lag: u64,
+}
+
+impl Metrics {
+	pub fn new() -> Result<Self, error::Error> {
+		let metrics = Self {
+			registry: Registry::new(),
+			last_checked_block: IntGaugeVec::new(
+				Opts::new(
+					"bridge_relay_last_checked_block",
+					"last block checked for the relay",
+				),
+				&["relay"],
+			)?,
+			head_block: IntGaugeVec::new(
+				Opts::new("bridge_chain_head_block", "latest block of the chain"),
+				&["chain"],
+			)?,
+			confirmed_block: IntGaugeVec::new(
+				Opts::new(
+					"bridge_chain_confirmed_block",
+					"latest block of the chain with the required confirmations",
+				),
+				&["chain"],
+			)?,
+			lag_blocks: IntGaugeVec::new(
+				Opts::new(
+					"bridge_relay_lag_blocks",
+					"blocks between the last checked block of the relay and the confirmed head of its chain",
+				),
+				&["relay"],
+			)?,
+			relays_in_flight: IntGaugeVec::new(
+				Opts::new(
+					"bridge_relays_in_flight",
+					"relay futures that have not completed yet",
+				),
+				&["relay"],
+			)?,
+			relay_steps: IntCounterVec::new(
+				Opts::new(
+					"bridge_relay_steps_total",
+					"relay steps reached, counted per attempt",
+				),
+				&["relay", "step"],
+			)?,
+			rpc_duration: HistogramVec::new(
+				HistogramOpts::new(
+					"bridge_rpc_request_duration_seconds",
+					"duration of rpc requests to the nodes",
+				),
+				&["chain", "method"],
+			)?,
+			authority_balance: GaugeVec::new(
+				Opts::new(
+					"bridge_authority_balance_wei",
+					"balance of the authority address",
+				),
+				&["chain"],
+			)?,
+			started: Instant::now(),
+			last_responses: Default::default(),
+		};
+		metrics
+			.registry
+			.register(Box::new(metrics.last_checked_block.clone()))?;
+		metrics
+			.registry
+			.register(Box::new(metrics.head_block.clone()))?;
+		metrics
+			.registry
+			.register(Box::new(metrics.confirmed_block.clone()))?;
+		metrics
+			.registry
+			.register(Box::new(metrics.lag_blocks.clone()))?;
+		metrics
+			.registry
+			.register(Box::new(metrics.relays_in_flight.clone()))?;
+		metrics
+			.registry
+			.register(Box::new(metrics.relay_steps.clone()))?;
+		metrics
+			.registry
+			.register(Box::new(metrics.rpc_duration.clone()))?;
+		metrics
+			.registry
+			.register(Box::new(metrics.authority_balance.clone()))?;
+		Ok(metrics)
+	}
+
+	/// all metrics in the prometheus text format
+	pub fn encode(&self) -> Result<String, error::Error> {
+		let mut buffer = Vec::new();
+		TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+		String::from_utf8(buffer).chain_err(|| "metrics are not valid utf8")
+	}
+
+	pub fn relay_progress(&self, kind: RelayKind, last_checked_block: u64, in_flight: usize) {
+		self.last_checked_block
+			.with_label_values(&[kind.name()])
+			.set(last_checked_block as i64);
+		self.relays_in_flight
+			.with_label_values(&[kind.name()])
+			.set(in_flight as i64);
+		self.update_lag();
+	}
+
+	pub fn relay_step(&self, kind: RelayKind, status: RelayStatus) {
+		self.relay_steps
+			.with_label_values(&[kind.name(), status.name()])
+			.inc();
+	}
+
+	/// `reachable` is `false` if the request failed to reach the node
+	pub fn rpc_request(&self, chain: &str, method: &str, duration: Duration, reachable: bool) {
+		self.rpc_duration
+			.with_label_values(&[chain, method])
+			.observe(seconds(duration));
+		if reachable {
+			self.last_responses
+				.borrow_mut()
+				.insert(chain.to_owned(), Instant::now());
+		}
+	}
+
+	/// `Err` with the reason if the bridge node is not ready according to `readiness`.
+	/// checks that only become possible once the first heads are known pass until then
+	pub fn ready(&self, readiness: &Readiness) -> Result<(), String> {
+		for chain in &["main", "side"] {
+			let last_response = self
+				.last_responses
+				.borrow()
+				.get(*chain)
+				.cloned()
+				.unwrap_or(self.started);
+			let unreachable_for = last_response.elapsed();
+			if unreachable_for >= readiness.max_unreachable {
+				return Err(format!(
+					"{} chain unreachable for {} seconds",
+					chain,
+					unreachable_for.as_secs()
+				));
+			}
+		}
+		for &(kind, chain) in &RELAYS {
+			let lag = match self.lag(kind, chain) {
+				Some(lag) => lag as u64,
+				None => continue,
+			};
+			if lag > readiness.max_lag {
+				return Err(format!(
+					"{} relay lags {} blocks behind the confirmed head of {}",
+					kind.name(),
+					lag,
+					chain
+				));
+			}
+		}
+		Ok(())
+	}
+
+	fn chain(
+		&self,
+		chain: &str,
+		head: u64,
+		required_confirmations: u32,
+		balance: web3::types::U256,
+	) {
+		self.head_block.with_label_values(&[chain]).set(head as i64);
+		self.confirmed_block
+			.with_label_values(&[chain])
+			.set(head.saturating_sub(u64::from(required_confirmations)) as i64);
+		// precise enough for monitoring
+		self.authority_balance
+			.with_label_values(&[chain])
+			.set(balance.low_u128() as f64);
+		self.update_lag();
+	}
+
+	/// blocks the relay of `kind` lags behind the confirmed head of `chain`.
+	/// `None` until both are known
+	fn lag(&self, kind: RelayKind, chain: &str) -> Option<i64> {
+		let confirmed = self.confirmed_block.with_label_values(&[chain]).get();
+		let last_checked_block = self
+			.last_checked_block
+			.with_label_values(&[kind.name()])
+			.get();
+		if confirmed > 0 && last_checked_block > 0 {
+			Some(confirmed.saturating_sub(last_checked_block).max(0))
+		} else {
+			None
+		}
+	}
+
+	fn update_lag(&self) {
+		for &(kind, chain) in &RELAYS {
+			if let Some(lag) = self.lag(kind, chain) {
+				self.lag_blocks.with_label_values(&[kind.name()]).set(lag);
+			}
+		}
+	}
+
+	/// returns a `Future` that updates the head of `chain`, the last block on it with
+	/// `required_confirmations` and the balance of `authority_address` on it every
+	/// `interval`. failures are logged. never resolves
+	pub fn watch_chain<T: Transport + 'static>(
+		&self,
+		chain: &'static str,
+		transport: T,
+		required_confirmations: u32,
+		authority_address: Address,
+		request_timeout: Duration,
+		interval: Duration,
+	) -> Box<dyn Future<Item = (), Error = ()>> {
+		let metrics = self.clone();
+		let timer = Timer::default();
+		let watch = timer
+			.interval(interval)
+			.map_err(error::Error::from)
+			.for_each(move |_| {
+				let metrics = metrics.clone();
+				let eth = web3::api::Eth::new(transport.clone());
+				let head = timer.timeout(eth.block_number().from_err(), request_timeout);
+				let balance = timer.timeout(
+					eth.balance(authority_address, None).from_err(),
+					request_timeout,
+				);
+				head.join(balance).then(move |result| {
+					match result {
+						Ok((head, balance)) => {
+							metrics.chain(chain, head.as_u64(), required_confirmations, balance)
+						}
+						Err(err) => warn!("Metrics: cannot update {} chain: {}", chain, err),
+					}
+					Ok::<(), error::Error>(())
+				})
+			})
+			.map_err(|err| error!("Metrics: watching chain failed: {}", err));
+		Box::new(watch)
+	}
+}
+
+/// response to an http request for `path`
+fn respond(metrics: &Metrics, readiness: &Readiness, path: &str) -> String {
+	let (status, body) = match path {
+		"/metrics" => match metrics.encode() {
+			Ok(body) => ("200 OK", body),
+			Err(err) => ("500 Internal Server Error", err.to_string()),
+		},
+		// answering at all means the event loop is running
+		"/health" => ("200 OK", "ok\n".to_owned()),
+		"/ready" => match metrics.ready(readiness) {
+			Ok(()) => ("200 OK", "ready\n".to_owned()),
+			Err(reason) => ("503 Service Unavailable", format!("{}\n", reason)),
+		},
+		_ => ("404 Not Found", "not found\n".to_owned()),
+	};
+	format!(
+		"HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+		status,
+		body.len(),
+		body
+	)
+}
+
+/// serves `metrics` at `http://<address>/metrics` and the `/health` and `/ready`
+/// endpoints on the event loop of `handle`
+pub fn serve(
+	address: &SocketAddr,
+	handle: &Handle,
+	metrics: Metrics,
+	readiness: Readiness,
+) -> Result<(), error::Error> {
+	let listener = TcpListener::bind(address, handle)
+		.chain_err(|| format!("Cannot listen for metrics requests on {}", address))?;
+	let connection_handle = handle.clone();
+	let server = listener
+		.incoming()
+		.for_each(move |(stream, _)| {
+			let metrics = metrics.clone();
+			// requests are tiny. the request line is all that matters
+			let connection = read(stream, vec![0; 1024])
+				.and_then(move |(stream, buffer, len)| {
+					let request = String::from_utf8_lossy(&buffer[..len]);
+					let path = request.split_whitespace().nth(1).unwrap_or("/").to_owned();
+					write_all(stream, respond(&metrics, &readiness, &path).into_bytes())
+				})
+				.then(|result| {
+					if let Err(err) = result {
+						debug!("Metrics: request failed: {}", err);
+					}
+					Ok(())
+				});
+			connection_handle.spawn(connection);
+			Ok(())
+		})
+		.map_err(|err| error!("Metrics: listener failed: {}", err));
+	handle.spawn(server);
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_metrics_lag_and_encoding() {
+		let metrics = Metrics::new().unwrap();
+		metrics.relay_progress(RelayKind::MainToSideSign, 88, 2);
+		metrics.chain("main", 100, 2, 5.into());
+		metrics.relay_step(RelayKind::MainToSideSign, RelayStatus::Mined);
+
+		let text = metrics.encode().unwrap();
+		assert!(text.contains("bridge_chain_confirmed_block{chain=\"main\"} 98"));
+		assert!(text.contains("bridge_relay_lag_blocks{relay=\"main_to_side_sign\"} 10"));
+		assert!(text.contains("bridge_relays_in_flight{relay=\"main_to_side_sign\"} 2"));
+		assert!(
+			text.contains("bridge_relay_steps_total{relay=\"main_to_side_sign\",step=\"mined\"} 1")
+		);
+		assert!(text.contains("bridge_authority_balance_wei{chain=\"main\"} 5"));
+
+		let readiness = readiness();
+		assert!(respond(&metrics, &readiness, "/metrics").starts_with("HTTP/1.1 200 OK\r\n"));
+		assert!(respond(&metrics, &readiness, "/").starts_with("HTTP/1.1 404 Not Found\r\n"));
+	}
+
+	fn readiness() -> Readiness {
+		Readiness {
+			max_unreachable: Duration::from_secs(60),
+			max_lag: 10,
+		}
+	}
+
+	#[test]
+	fn test_metrics_ready() {
+		let metrics = Metrics::new().unwrap();
+		let readiness = readiness();
+		// nothing is known right after the start
+		assert_eq!(metrics.ready(&readiness), Ok(()));
+
+		metrics.chain("main", 100, 2, 0.into());
+		metrics.relay_progress(RelayKind::MainToSideSign, 88, 0);
+		assert_eq!(metrics.ready(&readiness), Ok(()));
+		assert!(respond(&metrics, &readiness, "/health").starts_with("HTTP/1.1 200 OK\r\n"));
+		assert!(respond(&metrics, &readiness, "/ready").starts_with("HTTP/1.1 200 OK\r\n"));
+
+		metrics.relay_progress(RelayKind::MainToSideSign, 87, 0);
+		assert_eq!(
+			metrics.ready(&readiness),
+			Err("main_to_side_sign relay lags 11 blocks behind the confirmed head of main".into())
+		);
+		assert!(respond(&metrics, &readiness, "/ready")
+			.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+		// still alive
+		assert!(respond(&metrics, &readiness, "/health").starts_with("HTTP/1.1 200 OK\r\n"));
+
+		metrics.relay_progress(RelayKind::MainToSideSign, 98, 0);
+		let unreachable = Readiness {
+			max_unreachable: Duration::from_secs(0),
+			..readiness
+		};
+		assert_eq!(
+			metrics.ready(&unreachable),
+			Err("main chain unreachable for 0 seconds".into())
+		);
+	}
+}
@@ -0,0 +1,295 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+// This file is part of Parity-Bridge.
+
+// Parity-Bridge is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity-Bridge is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity-Bridge.  If not, see <http://www.gnu.org/licenses/>.
+
+//! hands out nonces for the transactions the authority sends on a chain.
+//!
+//! all relay futures sending on one chain share a `NonceManager`
+//! (through `clone`) so concurrent relays never race on the nonce
+//! assignment of the node.
+
+use error::{self, ResultExt};
+use futures::future::FromErr;
+use futures::{Async, Future, Poll};
+use std::cell::RefCell;
+use std::cmp;
+use std::collections::BTreeSet;
+use std::rc::Rc;
+use std::time::Duration;
+use tokio_timer::{Timeout, Timer};
+use web3;
+use web3::api::Namespace;
+use web3::helpers::CallFuture;
+use web3::types::{Address, BlockNumber, U256};
+use web3::Transport;
+
+#[derive(Debug, Default)]
+struct Nonces {
+	/// nonce to hand out next. `None` if (re)synchronisation with the node is required
+	next: Option<U256>,
+	/// highest nonce handed out so far
+	highest: Option<U256>,
+	/// nonces handed out whose transactions were rejected. handed out again first
+	gaps: BTreeSet<U256>,
+}
+
+/// hands out nonces for transactions sent by `address` in order.
+/// `clone`d versions share the same nonces.
+#[derive(Clone)]
+pub struct NonceManager<T> {
+	transport: T,
+	address: Address,
+	request_timeout: Duration,
+	nonces: Rc<RefCell<Nonces>>,
+}
+
+impl<T: Transport> NonceManager<T> {
+	pub fn new(transport: T, address: Address, request_timeout: Duration) -> Self {
+		Self {
+			transport,
+			address,
+			request_timeout,
+			nonces: Default::default(),
+		}
+	}
+
+	pub fn address(&self) -> Address {
+		self.address
+	}
+
+	/// returns a `Future` that fetches the pending transaction count from the node
+	/// and uses it as the next nonce. should be run once at startup.
+	pub fn sync(&self) -> SyncNonce<T> {
+		self.nonces.borrow_mut().next = None;
+		SyncNonce {
+			manager: self.clone(),
+			future: self.fetch(),
+		}
+	}
+
+	/// returns a `Future` that resolves with the next unused nonce.
+	/// only hits the node if a (re)synchronisation is required.
+	pub fn next(&self) -> NextNonce<T> {
+		NextNonce {
+			manager: self.clone(),
+			future: None,
+		}
+	}
+
+	/// to be called when a transaction with `nonce` could not be submitted.
+	/// that leaves a gap which stalls all following transactions.
+	/// the gap is filled by the next transaction unless the node
+	/// reports the nonce as used after a resynchronisation.
+	pub fn failed(&self, nonce: U256) {
+		warn!(
+			"NonceManager ({:?}): transaction with nonce {} failed. resynchronising with node",
+			self.address, nonce
+		);
+		let mut nonces = self.nonces.borrow_mut();
+		nonces.gaps.insert(nonce);
+		nonces.next = None;
+	}
+
+	/// to be called when it is unknown whether the transaction with `nonce`
+	/// reached the node (for example after a timeout). `nonce` is kept
+	/// and the next nonce is determined by a resynchronisation with the node.
+	pub fn resync(&self, nonce: U256) {
+		warn!(
+			"NonceManager ({:?}): transaction with nonce {} may not have been sent. resynchronising with node",
+			self.address, nonce
+		);
+		self.nonces.borrow_mut().next = None;
+	}
+
+	fn fetch(&self) -> Timeout<FromErr<CallFuture<U256, T::Out>, error::Error>> {
+		let future = web3::api::Eth::new(&self.transport)
+			.transaction_count(self.address, Some(BlockNumber::Pending))
+			.from_err();
+		Timer::default().timeout(future, self.request_timeout)
+	}
+
+	fn take_next(&self) -> Option<U256> {
+		let mut nonces = self.nonces.borrow_mut();
+		let next = nonces.next?;
+		if let Some(gap) = nonces.gaps.iter().next().cloned() {
+			nonces.gaps.remove(&gap);
+			return Some(gap);
+		}
+		let nonce = next;
+		nonces.next = Some(nonce + 1);
+		nonces.highest = Some(nonce);
+		Some(nonce)
+	}
+
+	fn synced(&self, pending_transaction_count: U256) {
+		let mut nonces = self.nonces.borrow_mut();
+		if nonces.next.is_some() {
+			// another future synchronised in the meantime
+			return;
+		}
+		// gaps below the pending transaction count were filled by the node
+		nonces.gaps = nonces.gaps.split_off(&pending_transaction_count);
+		if !nonces.gaps.is_empty() {
+			warn!(
+				"NonceManager ({:?}): detected nonce gaps {:?}. filling them first",
+				self.address, nonces.gaps
+			);
+		}
+		// nonces handed out before may still be pending. they are never reused
+		let next = match nonces.highest {
+			Some(highest) => cmp::max(pending_transaction_count, highest + 1),
+			None => pending_transaction_count,
+		};
+		info!(
+			"NonceManager ({:?}): synchronised. next nonce is {}",
+			self.address, next
+		);
+		nonces.next = Some(next);
+	}
+}
+
+/// `Future` returned by `NonceManager::sync`. resolves with the next nonce.
+pub struct SyncNonce<T: Transport> {
+	manager: NonceManager<T>,
+	future: Timeout<FromErr<CallFuture<U256, T::Out>, error::Error>>,
+}
+
+impl<T: Transport> Future for SyncNonce<T> {
+	type Item = U256;
+	type Error = error::Error;
+
+	fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+		let pending_transaction_count = try_ready!(self
+			.future
+			.poll()
+			.chain_err(|| "NonceManager: fetching pending transaction count failed"));
+		self.manager.synced(pending_transaction_count);
+		Ok(Async::Ready(pending_transaction_count))
+	}
+}
+
+/// `Future` returned by `NonceManager::next`
+pub struct NextNonce<T: Transport> {
+	manager: NonceManager<T>,
+	future: Option<Timeout<FromErr<CallFuture<U256, T::Out>, error::Error>>>,
+}
+
+impl<T: Transport> Future for NextNonce<T> {
+	type Item = U256;
+	type Error = error::Error;
+
+	fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+		loop {
+			if self.future.is_none() {
+				if let Some(nonce) = self.manager.take_next() {
+					return Ok(Async::Ready(nonce));
+				}
+				self.future = Some(self.manager.fetch());
+			}
+
+			let pending_transaction_count = try_ready!(self
+				.future
+				.as_mut()
+				.expect("future is set above; qed")
+				.poll()
+				.chain_err(|| "NonceManager: fetching pending transaction count failed"));
+			self.future = None;
+			self.manager.synced(pending_transaction_count);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use tokio_core::reactor::Core;
+
+	#[test]
+	fn test_nonce_manager_hands_out_nonces_in_order() {
+		let transport = mock_transport!(
+			"eth_getTransactionCount" =>
+				req => json!(["0x0000000000000000000000000000000000000001", "pending"]),
+				res => json!("0x10");
+		);
+
+		let nonce_manager = NonceManager::new(
+			transport.clone(),
+			"0000000000000000000000000000000000000001".parse().unwrap(),
+			Duration::from_secs(1),
+		);
+
+		let mut event_loop = Core::new().unwrap();
+		assert_eq!(event_loop.run(nonce_manager.sync()).unwrap(), 0x10.into());
+		assert_eq!(event_loop.run(nonce_manager.next()).unwrap(), 0x10.into());
+		assert_eq!(
+			event_loop.run(nonce_manager.clone().next()).unwrap(),
+			0x11.into()
+		);
+		assert_eq!(event_loop.run(nonce_manager.next()).unwrap(), 0x12.into());
+		assert_eq!(transport.actual_requests(), transport.expected_requests());
+	}
+
+	#[test]
+	fn test_nonce_manager_resynchronises_after_failure() {
+		let transport = mock_transport!(
+			"eth_getTransactionCount" =>
+				req => json!(["0x0000000000000000000000000000000000000001", "pending"]),
+				res => json!("0x10");
+			"eth_getTransactionCount" =>
+				req => json!(["0x0000000000000000000000000000000000000001", "pending"]),
+				res => json!("0x10");
+		);
+
+		let nonce_manager = NonceManager::new(
+			transport.clone(),
+			"0000000000000000000000000000000000000001".parse().unwrap(),
+			Duration::from_secs(1),
+		);
+
+		let mut event_loop = Core::new().unwrap();
+		assert_eq!(event_loop.run(nonce_manager.next()).unwrap(), 0x10.into());
+		assert_eq!(event_loop.run(nonce_manager.next()).unwrap(), 0x11.into());
+		nonce_manager.failed(0x10.into());
+		// the gap at 0x10 gets filled. 0x11 may still be pending at the node
+		assert_eq!(event_loop.run(nonce_manager.next()).unwrap(), 0x10.into());
+		assert_eq!(event_loop.run(nonce_manager.next()).unwrap(), 0x12.into());
+		assert_eq!(transport.actual_requests(), transport.expected_requests());
+	}
+
+	#[test]
+	fn test_nonce_manager_keeps_nonce_of_uncertain_transaction() {
+		let transport = mock_transport!(
+			"eth_getTransactionCount" =>
+				req => json!(["0x0000000000000000000000000000000000000001", "pending"]),
+				res => json!("0x10");
+			"eth_getTransactionCount" =>
+				req => json!(["0x0000000000000000000000000000000000000001", "pending"]),
+				res => json!("0x10");
+		);
+
+		let nonce_manager = NonceManager::new(
+			transport.clone(),
+			"0000000000000000000000000000000000000001".parse().unwrap(),
+			Duration::from_secs(1),
+		);
+
+		let mut event_loop = Core::new().unwrap();
+		assert_eq!(event_loop.run(nonce_manager.next()).unwrap(), 0x10.into());
+		nonce_manager.resync(0x10.into());
+		// the transaction with 0x10 may still reach the node
+		assert_eq!(event_loop.run(nonce_manager.next()).unwrap(), 0x11.into());
+		assert_eq!(transport.actual_requests(), transport.expected_requests());
+	}
+}
@@ -83,6 +83,19 @@ impl<O: Ord, F: Future> OrderedStream<O, F> {
 		});
 	}
 
+	/// removes all futures whose `order` is greater than or equal to `order`.
+	/// returns the count of removed futures
+	pub fn remove_from(&mut self, order: &O) -> usize {
+		let len = self.entries.len();
+		self.entries.retain(|entry| entry.order < *order);
+		len - self.entries.len()
+	}
+
+	/// returns whether there are no futures in the stream
+	pub fn is_empty(&self) -> bool {
+		self.entries.is_empty()
+	}
+
 	/// returns the count of futures that have completed but can't be
 	/// yielded since there are futures which are not ready
 	pub fn ready_count(&self) -> usize {
@@ -315,4 +328,34 @@ mod tests {
 			]
 		);
 	}
+
+	#[test]
+	fn test_ordered_stream_remove_from() {
+		let mut ordered_stream: OrderedStream<
+			u32,
+			futures::future::Join<
+				tokio_timer::Sleep,
+				futures::future::FutureResult<&str, tokio_timer::TimerError>,
+			>,
+		> = OrderedStream::new();
+
+		let timer = tokio_timer::Timer::default();
+		for (order, value) in vec![(1, "a"), (4, "b"), (2, "c"), (5, "d")] {
+			ordered_stream.insert(
+				order,
+				timer
+					.sleep(Duration::from_millis(0))
+					.join(futures::future::ok(value)),
+			);
+		}
+
+		assert_eq!(ordered_stream.remove_from(&4), 2);
+		assert_eq!(ordered_stream.not_ready_count(), 2);
+		assert!(!ordered_stream.is_empty());
+
+		let mut event_loop = tokio_core::reactor::Core::new().unwrap();
+
+		let results = event_loop.run(ordered_stream.take(2).collect()).unwrap();
+		assert_eq!(results, vec![(1, ((), "a")), (2, ((), "c"))]);
+	}
 }
@@ -20,6 +20,10 @@
 use error::{self, ResultExt};
 use futures::{Async, Future, Poll, Stream};
 use log_stream::LogsInBlockRange;
+use retry::{NewFuture, Retry};
+use std::cell::Cell;
+use std::cmp;
+use std::rc::Rc;
 use web3::types::Log;
 use OrderedStream;
 
@@ -28,33 +32,101 @@ use OrderedStream;
 pub trait LogToFuture {
 	type Future: Future<Error = error::Error>;
 
+	/// called once for every log. unlike `log_to_future` not again on retries
+	fn log_seen(&self, log: &Log);
+
 	fn log_to_future(&self, log: &Log) -> Self::Future;
 }
 
+/// creates the relay future for `log` on every attempt of `Retry`
+pub struct LogRelay<F: LogToFuture> {
+	log_to_future: Rc<F>,
+	log: Log,
+}
+
+impl<F: LogToFuture> NewFuture for LogRelay<F> {
+	type Future = F::Future;
+
+	fn new_future(&self) -> Self::Future {
+		self.log_to_future.log_to_future(&self.log)
+	}
+}
+
+/// completes with `None` instead of failing if only the relay of the
+/// message failed (see `Error::is_message_failure`).
+/// the relay future has recorded the failure in the `Journal` already
+struct SkipFailedMessage<F> {
+	future: F,
+	/// incremented for every skipped message
+	skipped: Rc<Cell<usize>>,
+}
+
+impl<F: Future<Error = error::Error>> Future for SkipFailedMessage<F> {
+	type Item = Option<F::Item>;
+	type Error = error::Error;
+
+	fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+		match self.future.poll() {
+			Ok(Async::NotReady) => Ok(Async::NotReady),
+			Ok(Async::Ready(item)) => Ok(Async::Ready(Some(item))),
+			Err(ref err) if err.is_message_failure() => {
+				warn!("RelayStream: skipping message: {}", err);
+				self.skipped.set(self.skipped.get() + 1);
+				Ok(Async::Ready(None))
+			}
+			Err(err) => Err(err),
+		}
+	}
+}
+
 /// a tokio `Stream` that when polled fetches all new logs from `stream_of_logs`
 /// calls `log_to_future` for each to obtain relay futures, waits for those
 /// futures to complete and yields the block numbers for which all relay
 /// futures have completed.
+/// relay futures that fail with a transient error are retried after a backoff.
+/// a relay transaction that may have been sent is never part of such an error
+/// (see `TrackedTransaction`) so retries don't send it twice.
+/// messages whose relay transaction would revert or reverted are skipped.
 /// those block numbers can then be persisted since they'll never need to be
 /// checked again.
 pub struct RelayStream<S: Stream<Item = LogsInBlockRange, Error = error::Error>, F: LogToFuture> {
 	stream_of_logs: S,
-	log_to_future: F,
+	log_to_future: Rc<F>,
 	/// reorders relay futures so they are yielded in block order
 	/// rather than the order they complete.
 	/// this is required because relay futures are not guaranteed to
 	/// complete in block order.
-	ordered_stream: OrderedStream<u64, F::Future>,
+	ordered_stream: OrderedStream<u64, SkipFailedMessage<Retry<LogRelay<F>>>>,
+	/// last block of the last range yielded by `stream_of_logs`
+	last_checked_block: u64,
+	/// whether `stream_of_logs` has ended. this stream ends as well
+	/// once the remaining relays have completed
+	logs_ended: bool,
+	/// number of messages whose relay failed and that were skipped
+	skipped_messages: Rc<Cell<usize>>,
 }
 
 impl<S: Stream<Item = LogsInBlockRange, Error = error::Error>, F: LogToFuture> RelayStream<S, F> {
 	pub fn new(stream_of_logs: S, log_to_future: F) -> Self {
 		Self {
 			stream_of_logs,
-			log_to_future,
+			log_to_future: Rc::new(log_to_future),
 			ordered_stream: OrderedStream::new(),
+			last_checked_block: 0,
+			logs_ended: false,
+			skipped_messages: Rc::new(Cell::new(0)),
 		}
 	}
+
+	/// the number of relay futures that have not completed yet
+	pub fn relays_in_flight(&self) -> usize {
+		self.ordered_stream.not_ready_count()
+	}
+
+	/// the number of messages skipped so far because their relay failed
+	pub fn skipped_messages(&self) -> usize {
+		self.skipped_messages.get()
+	}
 }
 
 impl<S: Stream<Item = LogsInBlockRange, Error = error::Error>, F: LogToFuture> Stream
@@ -67,20 +139,66 @@ impl<S: Stream<Item = LogsInBlockRange, Error = error::Error>, F: LogToFuture> S
 		// on each poll we loop until there are neither new logs
 		// nor newly completed relays
 		loop {
-			let maybe_logs_in_block_range = try_maybe_stream!(self
-				.stream_of_logs
-				.poll()
-				.chain_err(|| "RelayStream: fetching logs failed"));
+			let maybe_logs_in_block_range = if self.logs_ended {
+				None
+			} else {
+				match self
+					.stream_of_logs
+					.poll()
+					.chain_err(|| "RelayStream: fetching logs failed")?
+				{
+					Async::Ready(None) => {
+						self.logs_ended = true;
+						None
+					}
+					Async::Ready(logs_in_block_range) => logs_in_block_range,
+					Async::NotReady => None,
+				}
+			};
 
 			if let Some(ref logs_in_block_range) = maybe_logs_in_block_range {
+				if logs_in_block_range.reverted {
+					// the relays of logs from reorganised blocks are obsolete.
+					// they are retried if the logs show up again on the new canonical chain
+					let removed = self.ordered_stream.remove_from(&logs_in_block_range.from);
+					self.last_checked_block = logs_in_block_range.from.saturating_sub(1);
+					warn!(
+						"RelayStream: dropped {} relays from reorganised blocks {} to {}",
+						removed, logs_in_block_range.from, logs_in_block_range.to
+					);
+					if self.ordered_stream.is_empty() {
+						// blocks after the common ancestor must be checked again.
+						// otherwise the remaining relays yield lower block numbers anyway
+						return Ok(Async::Ready(Some(
+							logs_in_block_range.from.saturating_sub(1),
+						)));
+					}
+					continue;
+				}
+
+				self.last_checked_block = logs_in_block_range.to;
+
 				// if there are new logs, create futures from them
 				// which are responsible for the relay and add them to the
 				// ordered stream
 				for log in &logs_in_block_range.logs {
-					let relay_future = self.log_to_future.log_to_future(log);
+					self.log_to_future.log_seen(log);
+					let relay_future = SkipFailedMessage {
+						future: Retry::new(LogRelay {
+							log_to_future: self.log_to_future.clone(),
+							log: log.clone(),
+						}),
+						skipped: self.skipped_messages.clone(),
+					};
 					self.ordered_stream
 						.insert(logs_in_block_range.to, relay_future);
 				}
+
+				if self.ordered_stream.is_empty() {
+					// nothing to relay up to this block.
+					// yield it anyway so progress is persisted while catching up
+					return Ok(Async::Ready(Some(logs_in_block_range.to)));
+				}
 			}
 
 			let maybe_fully_relayed_until_block = try_maybe_stream!(self
@@ -91,11 +209,21 @@ impl<S: Stream<Item = LogsInBlockRange, Error = error::Error>, F: LogToFuture> S
 			if let Some((fully_relayed_until_block, _)) = maybe_fully_relayed_until_block {
 				// all relay futures for this block or before have completed
 				// we can yield the block number which can be safely
-				// persisted since it doesn't need to get checked again
+				// persisted since it doesn't need to get checked again.
+				// once no relays are left the blocks checked meanwhile are done as well
+				if self.ordered_stream.is_empty() {
+					return Ok(Async::Ready(Some(cmp::max(
+						fully_relayed_until_block,
+						self.last_checked_block,
+					))));
+				}
 				return Ok(Async::Ready(Some(fully_relayed_until_block)));
 			}
 
 			if maybe_logs_in_block_range.is_none() && maybe_fully_relayed_until_block.is_none() {
+				if self.logs_ended && self.ordered_stream.is_empty() {
+					return Ok(Async::Ready(None));
+				}
 				// there are neither new logs nor is there a new block number
 				// until which all relays have completed
 				return Ok(Async::NotReady);
@@ -103,3 +231,42 @@ impl<S: Stream<Item = LogsInBlockRange, Error = error::Error>, F: LogToFuture> S
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use error::ErrorKind;
+	use futures::future;
+	use web3::types::H256;
+
+	#[test]
+	fn test_skip_failed_message() {
+		let reverted = || -> error::Error {
+			Err::<(), _>(ErrorKind::TransactionReverted(H256::from_low_u64_be(1)).into())
+				.chain_err(|| "relay failed")
+				.unwrap_err()
+		};
+
+		let counter = Rc::new(Cell::new(0));
+
+		let mut skipped = SkipFailedMessage {
+			future: future::err::<u32, _>(reverted()),
+			skipped: counter.clone(),
+		};
+		assert_eq!(skipped.poll().unwrap(), Async::Ready(None));
+
+		let mut relayed = SkipFailedMessage {
+			future: future::ok(1),
+			skipped: counter.clone(),
+		};
+		assert_eq!(relayed.poll().unwrap(), Async::Ready(Some(1)));
+
+		let mut failed = SkipFailedMessage {
+			future: future::err::<u32, error::Error>(ErrorKind::Signing("bad key".into()).into()),
+			skipped: counter.clone(),
+		};
+		assert!(failed.poll().is_err());
+
+		assert_eq!(counter.get(), 1);
+	}
+}
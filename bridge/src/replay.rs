@@ -0,0 +1,81 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+// This file is part of Parity-Bridge.
+
+// Parity-Bridge is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity-Bridge is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity-Bridge.  If not, see <http://www.gnu.org/licenses/>.
+
+//! runs a single relay over a block range again for `parity-bridge replay`.
+//! relays that already happened are skipped by the relay futures.
+//! the persisted state is not touched so the cursors never move backwards.
+
+use accept_message_from_main::LogToAcceptMessageFromMain;
+use error;
+use futures::{future, Future, Stream};
+use journal::{Journal, RelayKind};
+use main_contract::MainContract;
+use relay_stream::RelayStream;
+use side_contract::SideContract;
+use side_to_main_sign::LogToSideToMainSign;
+use side_to_main_signatures::LogToSideToMainSignatures;
+use web3::Transport;
+
+/// returns a `Future` that completes once the relay `kind` has been run
+/// for all logs in blocks `from` to `to` (inclusive)
+pub fn replay<T: Transport + 'static>(
+	kind: RelayKind,
+	from: u64,
+	to: u64,
+	main: MainContract<T>,
+	side: SideContract<T>,
+	journal: Journal,
+) -> Box<dyn Future<Item = (), Error = error::Error>> {
+	if from == 0 || from > to {
+		return Box::new(future::err(
+			format!("invalid block range {} to {}", from, to).into(),
+		));
+	}
+	let after = from - 1;
+	match kind {
+		RelayKind::MainToSideSign => run(RelayStream::new(
+			main.main_to_side_log_stream(after).until(to),
+			LogToAcceptMessageFromMain {
+				main,
+				side,
+				journal,
+			},
+		)),
+		RelayKind::SideToMainSign => run(RelayStream::new(
+			side.side_to_main_sign_log_stream(after).until(to),
+			LogToSideToMainSign { side, journal },
+		)),
+		RelayKind::SideToMainSignatures => run(RelayStream::new(
+			side.side_to_main_signatures_log_stream(after, main.authority_address)
+				.until(to),
+			LogToSideToMainSignatures {
+				main,
+				side,
+				journal,
+			},
+		)),
+	}
+}
+
+fn run<S>(relay_stream: S) -> Box<dyn Future<Item = (), Error = error::Error>>
+where
+	S: Stream<Item = u64, Error = error::Error> + 'static,
+{
+	Box::new(relay_stream.for_each(|block| {
+		info!("replayed relays up to block {}", block);
+		Ok(())
+	}))
+}
@@ -0,0 +1,192 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+// This file is part of Parity-Bridge.
+
+// Parity-Bridge is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity-Bridge is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity-Bridge.  If not, see <http://www.gnu.org/licenses/>.
+
+//! retries futures that failed with a transient error
+//! (see `Error::is_transient`) after a jittered exponential backoff.
+//! other errors are passed through unchanged.
+
+use error;
+use futures::{Async, Future, Poll};
+use rand::{thread_rng, Rng};
+use std::cmp;
+use std::time::Duration;
+use tokio_timer::{Sleep, Timer};
+
+/// delay before the first retry
+const INITIAL_BACKOFF_MILLIS: u64 = 500;
+/// upper bound of the delay. must stay below the maximum tokio timer timeout
+const MAX_BACKOFF_MILLIS: u64 = 5 * 60 * 1000;
+
+/// delay before retry number `attempt` (starting at 0).
+/// picked uniformly from the upper half of the exponential backoff
+/// so concurrently failing futures don't all retry at the same time
+fn backoff(attempt: u32) -> Duration {
+	let max = cmp::min(
+		INITIAL_BACKOFF_MILLIS.saturating_mul(1 << cmp::min(attempt, 32)),
+		MAX_BACKOFF_MILLIS,
+	);
+	Duration::from_millis(thread_rng().gen_range(max / 2, max + 1))
+}
+
+/// something that can create the same future again and again.
+/// to be called by `Retry` for every attempt.
+pub trait NewFuture {
+	type Future: Future<Error = error::Error>;
+
+	fn new_future(&self) -> Self::Future;
+}
+
+enum State<F> {
+	Run(F),
+	Wait(Sleep),
+}
+
+/// `Future` that runs the futures created by `new_future` until one
+/// succeeds or fails with an error that is not transient
+pub struct Retry<N: NewFuture> {
+	new_future: N,
+	state: State<N::Future>,
+	attempt: u32,
+}
+
+impl<N: NewFuture> Retry<N> {
+	pub fn new(new_future: N) -> Self {
+		let state = State::Run(new_future.new_future());
+		Self {
+			new_future,
+			state,
+			attempt: 0,
+		}
+	}
+}
+
+impl<N: NewFuture> Future for Retry<N> {
+	type Item = <N::Future as Future>::Item;
+	type Error = error::Error;
+
+	fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+		loop {
+			let next_state = match self.state {
+				State::Run(ref mut future) => match future.poll() {
+					Ok(Async::NotReady) => return Ok(Async::NotReady),
+					Ok(Async::Ready(item)) => return Ok(Async::Ready(item)),
+					Err(ref err) if err.is_transient() => {
+						let delay = backoff(self.attempt);
+						self.attempt += 1;
+						warn!(
+							"Retry: attempt {} failed with transient error: {}. retrying in {:?}",
+							self.attempt, err, delay
+						);
+						State::Wait(Timer::default().sleep(delay))
+					}
+					Err(err) => return Err(err),
+				},
+				State::Wait(ref mut sleep) => {
+					try_ready!(sleep.poll());
+					State::Run(self.new_future.new_future())
+				}
+			};
+			self.state = next_state;
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use error::{ErrorKind, ResultExt};
+	use futures::future;
+	use std::cell::Cell;
+	use tokio_core::reactor::Core;
+
+	#[test]
+	fn test_backoff_grows_and_is_capped() {
+		for attempt in 0..40 {
+			let max = cmp::min(
+				INITIAL_BACKOFF_MILLIS.saturating_mul(1 << cmp::min(attempt, 32)),
+				MAX_BACKOFF_MILLIS,
+			);
+			let delay = backoff(attempt);
+			assert!(delay >= Duration::from_millis(max / 2));
+			assert!(delay <= Duration::from_millis(max));
+		}
+	}
+
+	/// fails with `error` until it was called `failures` times
+	struct Flaky {
+		calls: Cell<u32>,
+		failures: u32,
+		error: fn() -> error::Error,
+	}
+
+	impl<'a> NewFuture for &'a Flaky {
+		type Future = future::FutureResult<u32, error::Error>;
+
+		fn new_future(&self) -> Self::Future {
+			self.calls.set(self.calls.get() + 1);
+			if self.calls.get() <= self.failures {
+				future::err((self.error)())
+			} else {
+				future::ok(self.calls.get())
+			}
+		}
+	}
+
+	#[test]
+	fn test_retry_transient_error() {
+		let flaky = Flaky {
+			calls: Cell::new(0),
+			failures: 2,
+			error: || ErrorKind::TimedOut.into(),
+		};
+
+		let mut event_loop = Core::new().unwrap();
+		assert_eq!(event_loop.run(Retry::new(&flaky)).unwrap(), 3);
+		assert_eq!(flaky.calls.get(), 3);
+	}
+
+	#[test]
+	fn test_retry_passes_through_fatal_error() {
+		let flaky = Flaky {
+			calls: Cell::new(0),
+			failures: 2,
+			error: || ErrorKind::Signing("bad key".into()).into(),
+		};
+
+		let mut event_loop = Core::new().unwrap();
+		match *event_loop.run(Retry::new(&flaky)).unwrap_err().kind() {
+			ErrorKind::Signing(_) => {}
+			ref kind => panic!("expected a signing error, got {:?}", kind),
+		}
+		assert_eq!(flaky.calls.get(), 1);
+	}
+
+	#[test]
+	fn test_retry_transient_error_in_chain() {
+		let flaky = Flaky {
+			calls: Cell::new(0),
+			failures: 1,
+			error: || {
+				Err::<(), _>(error::Error::from(ErrorKind::TimedOut))
+					.chain_err(|| "relaying failed")
+					.unwrap_err()
+			},
+		};
+
+		let mut event_loop = Core::new().unwrap();
+		assert_eq!(event_loop.run(Retry::new(&flaky)).unwrap(), 2);
+	}
+}
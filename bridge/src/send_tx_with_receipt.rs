@@ -14,238 +14,337 @@
 // You should have received a copy of the GNU General Public License
 // along with Parity-Bridge.  If not, see <http://www.gnu.org/licenses/>.
 
+use block_number_stream::{BlockNumberStream, BlockNumberStreamOptions};
 use error::{self, ResultExt};
-use futures::future::FromErr;
-use futures::{Future, Poll};
-use std::time::Duration;
+use futures::future::{join_all, FromErr, JoinAll};
+use futures::{Async, Future, Poll, Stream};
+use helpers::{is_rejection, AsyncTransaction};
+use signer::Fees;
+use std::cmp;
+use std::time::{Duration, Instant};
 use tokio_timer::{Timeout, Timer};
 use web3::api::Namespace;
 use web3::helpers::CallFuture;
-use web3::types::{TransactionReceipt, TransactionRequest, U64};
+use web3::types::{TransactionReceipt, H256, U256};
 use web3::{self, Transport};
 
-mod inner {
-	use block_number_stream::{BlockNumberStream, BlockNumberStreamOptions};
-	use error::{self, ResultExt};
-	use futures::future::FromErr;
-	use futures::{Async, Future, Poll, Stream};
-	use std::time::Duration;
-	use tokio_timer::{Timeout, Timer};
-	use web3::api::Namespace;
-	use web3::helpers::CallFuture;
-	use web3::types::{TransactionReceipt, TransactionRequest, H256};
-	use web3::{self, Transport};
-
-	enum State<T: Transport> {
-		AwaitSendTransaction(Timeout<FromErr<CallFuture<H256, T::Out>, error::Error>>),
-		AwaitBlockNumber(H256),
-		AwaitTransactionReceipt {
-			future: Timeout<FromErr<CallFuture<Option<TransactionReceipt>, T::Out>, error::Error>>,
-			transaction_hash: H256,
-			last_block: u64,
-		},
-	}
-
-	pub struct SendTransactionWithReceiptOptions<T: Transport> {
-		pub transport: T,
-		pub request_timeout: Duration,
-		pub poll_interval: Duration,
-		pub confirmations: u32,
-		pub transaction: TransactionRequest,
-		pub after: u64,
-	}
-
-	pub struct SendTransactionWithReceipt<T: Transport> {
-		transport: T,
-		state: State<T>,
-		block_number_stream: BlockNumberStream<T>,
-		request_timeout: Duration,
-		timer: Timer,
-	}
-
-	impl<T: Transport> SendTransactionWithReceipt<T> {
-		pub fn new(options: SendTransactionWithReceiptOptions<T>) -> Self {
-			let timer = Timer::default();
-
-			let block_number_stream_options = BlockNumberStreamOptions {
-				request_timeout: options.request_timeout,
-				poll_interval: options.poll_interval,
-				confirmations: options.confirmations,
-				transport: options.transport.clone(),
-				after: options.after,
-			};
-			let block_number_stream = BlockNumberStream::new(block_number_stream_options);
-			let future =
-				web3::api::Eth::new(&options.transport).send_transaction(options.transaction);
-			let future = timer.timeout(future.from_err(), options.request_timeout);
-
-			SendTransactionWithReceipt {
-				transport: options.transport,
-				state: State::AwaitSendTransaction(future),
-				block_number_stream,
-				request_timeout: options.request_timeout,
-				timer,
-			}
-		}
-	}
-
-	impl<T: Transport> Future for SendTransactionWithReceipt<T> {
-		type Item = TransactionReceipt;
-		type Error = error::Error;
-
-		fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-			loop {
-				let next_state = match self.state {
-					State::AwaitSendTransaction(ref mut future) => {
-						let hash = try_ready!(future.poll().chain_err(|| {
-							"SendTransactionWithReceipt: sending transaction failed"
-						}));
-						info!("SendTransactionWithReceipt: sent transaction {}", hash);
-						State::AwaitBlockNumber(hash)
-					}
-					State::AwaitBlockNumber(transaction_hash) => {
-						let last_block = match try_ready!(
-							self.block_number_stream
-								.poll()
-								.chain_err(|| "SendTransactionWithReceipt: fetching of last confirmed block failed")
-							) {
-							Some(last_block) => last_block,
-							None => bail!("SendTransactionWithReceipt: fetching of last confirmed block failed"),
-						};
-
-						info!(
-							"SendTransactionWithReceipt: fetched confirmed block number {}",
-							last_block
-						);
-						let future = web3::api::Eth::new(&self.transport)
-							.transaction_receipt(transaction_hash);
-						State::AwaitTransactionReceipt {
-							future: self.timer.timeout(future.from_err(), self.request_timeout),
-							transaction_hash,
-							last_block,
-						}
-					}
-					State::AwaitTransactionReceipt {
-						ref mut future,
-						transaction_hash,
-						last_block,
-					} => {
-						let maybe_receipt = try_ready!(future.poll().chain_err(|| {
-							"SendTransactionWithReceipt: getting transaction receipt failed"
-						}));
-
-						match maybe_receipt {
-							// transaction hasn't been mined yet
-							None => State::AwaitBlockNumber(transaction_hash),
-							Some(receipt) => {
-								info!(
-									"SendTransactionWithReceipt: got transaction receipt: {}",
-									transaction_hash
-								);
-								match receipt.block_number {
-									// receipt comes from pending block
-									None => State::AwaitBlockNumber(transaction_hash),
-									Some(receipt_block_number) => {
-										if last_block < receipt_block_number.as_u64() {
-											// transaction does not have enough confirmations
-											State::AwaitBlockNumber(transaction_hash)
-										} else {
-											return Ok(Async::Ready(receipt));
-										}
-									}
-								}
-							}
-						}
-					}
-				};
-
-				self.state = next_state;
-			}
-		}
-	}
-}
-
-enum State<T: Transport> {
-	AwaitBlockNumber {
-		future: Timeout<FromErr<CallFuture<U64, T::Out>, error::Error>>,
-		transaction: Option<TransactionRequest>,
+enum TrackedState<T: Transport> {
+	AwaitSent,
+	AwaitBlockNumber,
+	AwaitReceipts {
+		future: JoinAll<
+			Vec<Timeout<FromErr<CallFuture<Option<TransactionReceipt>, T::Out>, error::Error>>>,
+		>,
+		last_block: u64,
+	},
+	AwaitResent {
+		future: Timeout<FromErr<CallFuture<H256, T::Out>, error::Error>>,
+		fees: Fees,
 	},
-	AwaitReceipt(inner::SendTransactionWithReceipt<T>),
 }
 
-pub struct SendTransactionWithReceiptOptions<T> {
+pub struct TrackedTransactionOptions<T: Transport> {
 	pub transport: T,
+	pub transaction: AsyncTransaction<T>,
 	pub request_timeout: Duration,
 	pub poll_interval: Duration,
 	pub confirmations: u32,
-	pub transaction: TransactionRequest,
+	pub resubmit_after: Duration,
+	pub gas_price_bump_percent: u32,
+	pub max_gas_price: U256,
 }
 
-pub struct SendTransactionWithReceipt<T: Transport> {
+/// `Future` that sends `transaction` and resolves with its receipt once it
+/// is mined with `confirmations`.
+/// if the transaction is still pending `resubmit_after` its last submission
+/// it is re-submitted with the same nonce and its gas price (or both
+/// EIP-1559 fees) bumped by `gas_price_bump_percent` up to `max_gas_price`.
+/// whichever submission gets mined first completes the future.
+/// once the transaction may have been sent transient errors don't fail the
+/// future. it keeps tracking the submissions and re-submits with the same
+/// nonce instead so the transaction can't get mined twice.
+pub struct TrackedTransaction<T: Transport> {
+	transport: T,
+	transaction: AsyncTransaction<T>,
+	block_number_stream: BlockNumberStream<T>,
 	request_timeout: Duration,
 	poll_interval: Duration,
-	transport: T,
-	state: State<T>,
 	confirmations: u32,
+	resubmit_after: Duration,
+	gas_price_bump_percent: u32,
+	max_gas_price: U256,
+	timer: Timer,
+	/// fees of the last submission
+	fees: Fees,
+	/// hashes of all submissions. they share a nonce so only one can get mined
+	transaction_hashes: Vec<H256>,
+	last_submitted_at: Instant,
+	state: TrackedState<T>,
 }
 
-impl<T: Transport> SendTransactionWithReceipt<T> {
-	pub fn new(options: SendTransactionWithReceiptOptions<T>) -> Self {
-		let timer = Timer::default();
-
-		let future = web3::api::Eth::new(&options.transport).block_number();
-
-		let state = State::AwaitBlockNumber {
-			future: timer.timeout(future.from_err(), options.request_timeout),
-			transaction: Some(options.transaction),
-		};
+fn confirmed_block_numbers<T: Transport>(
+	transport: &T,
+	request_timeout: Duration,
+	poll_interval: Duration,
+	confirmations: u32,
+) -> BlockNumberStream<T> {
+	BlockNumberStream::new(BlockNumberStreamOptions {
+		request_timeout,
+		poll_interval,
+		confirmations,
+		transport: transport.clone(),
+		after: 0,
+		new_heads: None,
+	})
+}
 
-		SendTransactionWithReceipt {
+impl<T: Transport> TrackedTransaction<T> {
+	pub fn new(options: TrackedTransactionOptions<T>) -> Self {
+		let block_number_stream = confirmed_block_numbers(
+			&options.transport,
+			options.request_timeout,
+			options.poll_interval,
+			options.confirmations,
+		);
+		TrackedTransaction {
+			transport: options.transport,
+			transaction: options.transaction,
+			block_number_stream,
 			request_timeout: options.request_timeout,
 			poll_interval: options.poll_interval,
-			transport: options.transport,
-			state,
 			confirmations: options.confirmations,
+			resubmit_after: options.resubmit_after,
+			gas_price_bump_percent: options.gas_price_bump_percent,
+			max_gas_price: options.max_gas_price,
+			timer: Timer::default(),
+			fees: Fees::Legacy {
+				gas_price: U256::zero(),
+			},
+			transaction_hashes: Vec::new(),
+			last_submitted_at: Instant::now(),
+			state: TrackedState::AwaitSent,
+		}
+	}
+
+	/// hash of the last submission. `None` until the transaction was sent
+	pub fn transaction_hash(&self) -> Option<H256> {
+		self.transaction_hashes.last().cloned()
+	}
+
+	/// continues tracking after `err` with a fresh block number stream.
+	/// fails with `err` if it isn't transient
+	fn resume_tracking(&mut self, err: error::Error) -> Result<TrackedState<T>, error::Error> {
+		if !err.is_transient() {
+			return Err(err);
+		}
+		warn!(
+			"TrackedTransaction: tracking transaction {:?} failed: {}. resuming",
+			self.transaction_hashes.last(),
+			err
+		);
+		self.block_number_stream = confirmed_block_numbers(
+			&self.transport,
+			self.request_timeout,
+			self.poll_interval,
+			self.confirmations,
+		);
+		Ok(TrackedState::AwaitBlockNumber)
+	}
+
+	/// returns `None` if the fees can't be bumped any further
+	fn bumped_fees(&self) -> Option<Fees> {
+		let bump =
+			|value: U256| value + value * U256::from(self.gas_price_bump_percent) / U256::from(100);
+		match self.fees {
+			Fees::Legacy { gas_price } => {
+				let bumped = cmp::min(bump(gas_price), self.max_gas_price);
+				if bumped > gas_price {
+					Some(Fees::Legacy { gas_price: bumped })
+				} else {
+					None
+				}
+			}
+			Fees::Eip1559 {
+				max_fee_per_gas,
+				max_priority_fee_per_gas,
+			} => {
+				let bumped_max_fee = cmp::min(bump(max_fee_per_gas), self.max_gas_price);
+				let bumped_priority_fee = cmp::min(bump(max_priority_fee_per_gas), bumped_max_fee);
+				// nodes only accept replacements that raise both fees
+				if bumped_max_fee > max_fee_per_gas
+					&& bumped_priority_fee > max_priority_fee_per_gas
+				{
+					Some(Fees::Eip1559 {
+						max_fee_per_gas: bumped_max_fee,
+						max_priority_fee_per_gas: bumped_priority_fee,
+					})
+				} else {
+					None
+				}
+			}
 		}
 	}
 }
 
-impl<T: Transport> Future for SendTransactionWithReceipt<T> {
+impl<T: Transport> Future for TrackedTransaction<T> {
 	type Item = TransactionReceipt;
 	type Error = error::Error;
 
 	fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
 		loop {
 			let next_state = match self.state {
-				State::AwaitBlockNumber {
+				TrackedState::AwaitSent => {
+					match self.transaction.poll() {
+						Ok(Async::NotReady) => return Ok(Async::NotReady),
+						Ok(Async::Ready(hash)) => {
+							info!("TrackedTransaction: sent transaction {}", hash);
+							self.transaction_hashes.push(hash);
+						}
+						// sending it again with a new nonce could get it mined twice.
+						// re-submit it with the same nonce once `resubmit_after` has passed
+						Err(ref err) if self.transaction.may_be_sent() => warn!(
+							"TrackedTransaction: sending transaction failed: {}. it may have been sent",
+							err
+						),
+						Err(err) => {
+							return Err(err)
+								.chain_err(|| "TrackedTransaction: sending transaction failed")
+						}
+					}
+					self.fees = self
+						.transaction
+						.fees()
+						.expect("fees are set once the transaction is sent; qed");
+					self.last_submitted_at = Instant::now();
+					TrackedState::AwaitBlockNumber
+				}
+				TrackedState::AwaitBlockNumber => {
+					let last_block = match self.block_number_stream.poll() {
+						Ok(Async::NotReady) => return Ok(Async::NotReady),
+						Ok(Async::Ready(Some(last_block))) => last_block,
+						Ok(Async::Ready(None)) => {
+							bail!("TrackedTransaction: fetching of last confirmed block failed")
+						}
+						Err(err) => {
+							self.state = self.resume_tracking(err)?;
+							continue;
+						}
+					};
+
+					let eth = web3::api::Eth::new(&self.transport);
+					let futures = self
+						.transaction_hashes
+						.iter()
+						.map(|hash| {
+							self.timer.timeout(
+								eth.transaction_receipt(*hash).from_err(),
+								self.request_timeout,
+							)
+						})
+						.collect::<Vec<_>>();
+					TrackedState::AwaitReceipts {
+						future: join_all(futures),
+						last_block,
+					}
+				}
+				TrackedState::AwaitReceipts {
 					ref mut future,
-					ref mut transaction,
+					last_block,
 				} => {
-					let block_number = try_ready!(future.poll().chain_err(|| {
-						"SendTransactionWithReceipt: fetching last block number failed"
-					}));
-					info!(
-						"SendTransactionWithReceipt: got last block number {}",
-						block_number
-					);
-					let transaction = transaction.take().expect(
-						"SendTransactionWithReceipt is always created with
-							 State::AwaitBlockNumber with transaction set to Some; qed",
-					);
-
-					let inner_options = inner::SendTransactionWithReceiptOptions {
-						transport: self.transport.clone(),
-						request_timeout: self.request_timeout,
-						poll_interval: self.poll_interval,
-						confirmations: self.confirmations,
-						transaction,
-						after: block_number.as_u64(),
+					let receipts = match future.poll() {
+						Ok(Async::NotReady) => return Ok(Async::NotReady),
+						Ok(Async::Ready(receipts)) => receipts,
+						Err(err) => {
+							self.state = self.resume_tracking(err)?;
+							continue;
+						}
 					};
 
-					let future = inner::SendTransactionWithReceipt::new(inner_options);
-					State::AwaitReceipt(future)
+					let mut is_pending = true;
+					// receipts from pending blocks have no block number
+					for receipt in receipts.into_iter().flatten() {
+						if let Some(receipt_block_number) = receipt.block_number {
+							is_pending = false;
+							if receipt_block_number.as_u64() <= last_block {
+								if receipt.status == Some(0.into()) {
+									bail!(error::ErrorKind::TransactionReverted(
+										receipt.transaction_hash
+									));
+								}
+								info!(
+									"TrackedTransaction: transaction {} mined and confirmed",
+									receipt.transaction_hash
+								);
+								return Ok(Async::Ready(receipt));
+							}
+						}
+					}
+
+					if !is_pending || self.last_submitted_at.elapsed() < self.resubmit_after {
+						// mined but not confirmed yet or not pending for long enough
+						TrackedState::AwaitBlockNumber
+					} else if let Some(fees) = self.bumped_fees() {
+						warn!(
+							"TrackedTransaction: transaction {:?} pending for more than {:?}. re-submitting with {:?}",
+							self.transaction_hashes.last(),
+							self.resubmit_after,
+							fees
+						);
+						TrackedState::AwaitResent {
+							future: self.transaction.resend(fees)?,
+							fees,
+						}
+					} else if self.transaction_hashes.is_empty() {
+						// nothing is known to be pending and nothing can be sent anymore
+						return Err(error::Error::from(error::ErrorKind::TimedOut)).chain_err(
+							|| {
+								format!(
+								"TrackedTransaction: sending transaction failed and fees are already at max of {}",
+								self.max_gas_price
+							)
+							},
+						);
+					} else {
+						warn!(
+							"TrackedTransaction: transaction {:?} pending for more than {:?} but fees are already at max of {}",
+							self.transaction_hashes.last(),
+							self.resubmit_after,
+							self.max_gas_price
+						);
+						self.last_submitted_at = Instant::now();
+						TrackedState::AwaitBlockNumber
+					}
+				}
+				TrackedState::AwaitResent {
+					ref mut future,
+					fees,
+				} => {
+					match future.poll() {
+						Ok(Async::NotReady) => return Ok(Async::NotReady),
+						Ok(Async::Ready(hash)) => {
+							info!("TrackedTransaction: re-submitted transaction as {}", hash);
+							self.transaction_hashes.push(hash);
+						}
+						// without a known submission there is nothing to keep tracking.
+						// either the first one got mined, which a new attempt checks,
+						// or it never reached the node and its nonce is free again
+						Err(err) if self.transaction_hashes.is_empty() && is_rejection(&err) => {
+							self.transaction.release_nonce();
+							return Err(err).chain_err(|| {
+								"TrackedTransaction: re-submitting transaction failed"
+							});
+						}
+						// most likely a previous submission got mined in the meantime.
+						// keep tracking the previous submissions
+						Err(err) => warn!(
+							"TrackedTransaction: re-submitting transaction failed: {}",
+							err
+						),
+					}
+					self.fees = fees;
+					self.last_submitted_at = Instant::now();
+					TrackedState::AwaitBlockNumber
 				}
-				State::AwaitReceipt(ref mut future) => return future.poll(),
 			};
 
 			self.state = next_state;
@@ -256,135 +355,283 @@ impl<T: Transport> Future for SendTransactionWithReceipt<T> {
 #[cfg(test)]
 mod tests {
 	use super::*;
+	use gas_limit::GasLimit;
+	use gas_price::GasPriceOracle;
+	use nonce_manager::NonceManager;
+	use test::{MockResponse, MockTransport};
 	use tokio_core::reactor::Core;
+	use web3::types::TransactionRequest;
+
+	const AUTHORITY: &str = "0x006b5dda44dc2606f07ad86c9190fb54fd905f6d";
+
+	/// params of `eth_sendTransaction` for the transaction of `tracked_transaction`
+	fn send_transaction_params(gas_price: &str) -> serde_json::Value {
+		json!([{
+			"data": "0x60",
+			"from": AUTHORITY,
+			"gas": "0xf4240",
+			"gasPrice": gas_price,
+			"nonce": "0x5",
+			"to": "0x0000000000000000000000000000000000000dd1"
+		}])
+	}
+
+	fn receipt(transaction_hash: u64, block_number: &str, status: &str) -> serde_json::Value {
+		json!({
+			"blockHash": "0xe0bdcf35b14a292d2998308d9b3fdea93a8c3d9c0b6c824c633fb9b15f9c3919",
+			"blockNumber": block_number,
+			"contractAddress": null,
+			"cumulativeGasUsed": "0x1c1999",
+			"gasUsed": "0xcdb5d",
+			"logs": [],
+			"logsBloom": "0x00000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000",
+			"root": null,
+			"status": status,
+			"transactionHash": H256::from_low_u64_be(transaction_hash),
+			"transactionIndex": "0x4"
+		})
+	}
+
+	fn nonce_manager(transport: &MockTransport) -> NonceManager<MockTransport> {
+		NonceManager::new(
+			transport.clone(),
+			AUTHORITY.parse().unwrap(),
+			Duration::from_secs(1),
+		)
+	}
+
+	/// sends a transaction with a gas price of 100 through the node.
+	/// re-submissions bump it by 50% up to `max_gas_price`
+	fn tracked_transaction(
+		transport: &MockTransport,
+		nonce_manager: &NonceManager<MockTransport>,
+		resubmit_after: Duration,
+		max_gas_price: U256,
+	) -> TrackedTransaction<MockTransport> {
+		let request = TransactionRequest {
+			from: AUTHORITY.parse().unwrap(),
+			to: Some("0000000000000000000000000000000000000dd1".parse().unwrap()),
+			gas: None,
+			gas_price: None,
+			value: None,
+			data: Some(vec![0x60].into()),
+			nonce: None,
+			condition: None,
+		};
+		let transaction = AsyncTransaction::new(
+			transport,
+			None,
+			nonce_manager,
+			GasLimit::Fixed(0xf4240.into()).gas(transport, &request, Duration::from_secs(1)),
+			GasPriceOracle::fixed(100.into()).fees(transport, Duration::from_secs(1)),
+			request,
+			Duration::from_secs(1),
+		);
+		TrackedTransaction::new(TrackedTransactionOptions {
+			transport: transport.clone(),
+			transaction,
+			request_timeout: Duration::from_secs(1),
+			poll_interval: Duration::from_secs(0),
+			confirmations: 1,
+			resubmit_after,
+			gas_price_bump_percent: 50,
+			max_gas_price,
+		})
+	}
 
 	#[test]
-	fn test_send_tx_with_receipt() {
+	fn test_tracked_transaction_resubmits_with_bumped_gas_price() {
 		let transport = mock_transport!(
+			"eth_getTransactionCount" =>
+				req => json!([AUTHORITY, "pending"]),
+				res => json!("0x5");
+			"eth_sendTransaction" =>
+				req => send_transaction_params("0x64"),
+				res => json!(H256::from_low_u64_be(1));
 			"eth_blockNumber" =>
 				req => json!([]),
 				res => json!("0x1010");
+			"eth_getTransactionReceipt" =>
+				req => json!([H256::from_low_u64_be(1)]),
+				res => json!(null);
 			"eth_sendTransaction" =>
-				req => json!([{
-					"data": "0x60",
-					"from": "0x006b5dda44dc2606f07ad86c9190fb54fd905f6d",
-					"gas": "0xf4240",
-					"gasPrice": "0x0"
-				}]),
-				res => json!("0x36efc16910ea67a2425a1e75f7e39e3c6a94f5763c68a47258f552481e20cd34");
+				req => send_transaction_params("0x96"),
+				res => json!(H256::from_low_u64_be(2));
 			"eth_blockNumber" =>
 				req => json!([]),
 				res => json!("0x1011");
+			"eth_getTransactionReceipt" =>
+				req => json!([H256::from_low_u64_be(1)]),
+				res => json!(null);
+			"eth_getTransactionReceipt" =>
+				req => json!([H256::from_low_u64_be(2)]),
+				res => json!(null);
+			// bump of 50% is capped at `max_gas_price`
+			"eth_sendTransaction" =>
+				req => send_transaction_params("0xc8"),
+				res => json!(H256::from_low_u64_be(3));
 			"eth_blockNumber" =>
 				req => json!([]),
 				res => json!("0x1012");
-			"eth_blockNumber" =>
-				req => json!([]),
-				res => json!("0x1013");
 			"eth_getTransactionReceipt" =>
-				req => json!(["0x36efc16910ea67a2425a1e75f7e39e3c6a94f5763c68a47258f552481e20cd34"]),
+				req => json!([H256::from_low_u64_be(1)]),
+				res => json!(null);
+			"eth_getTransactionReceipt" =>
+				req => json!([H256::from_low_u64_be(2)]),
+				res => receipt(2, "0x1011", "0x1");
+			"eth_getTransactionReceipt" =>
+				req => json!([H256::from_low_u64_be(3)]),
+				res => json!(null);
+		);
+		let nonce_manager = nonce_manager(&transport);
+		let tracked_transaction = tracked_transaction(
+			&transport,
+			&nonce_manager,
+			Duration::from_secs(0),
+			200.into(),
+		);
+
+		let mut event_loop = Core::new().unwrap();
+		let receipt = event_loop.run(tracked_transaction).unwrap();
+		assert_eq!(receipt.transaction_hash, H256::from_low_u64_be(2));
+		assert_eq!(receipt.block_number, Some(0x1011.into()));
+		assert_eq!(transport.actual_requests(), transport.expected_requests());
+	}
+
+	#[test]
+	fn test_tracked_transaction_resubmits_with_same_nonce_if_sending_is_uncertain() {
+		let transport = mock_transport!(
+			"eth_getTransactionCount" =>
+				req => json!([AUTHORITY, "pending"]),
+				res => json!("0x5");
+			// invalid response. the transaction may have been sent
+			"eth_sendTransaction" =>
+				req => send_transaction_params("0x64"),
 				res => json!(null);
 			"eth_blockNumber" =>
 				req => json!([]),
-				res => json!("0x1014");
+				res => json!("0x1010");
+			"eth_sendTransaction" =>
+				req => send_transaction_params("0x96"),
+				res => json!(H256::from_low_u64_be(2));
+			"eth_blockNumber" =>
+				req => json!([]),
+				res => json!("0x1011");
 			"eth_getTransactionReceipt" =>
-				req => json!(["0x36efc16910ea67a2425a1e75f7e39e3c6a94f5763c68a47258f552481e20cd34"]),
-				res => json!({
-					"blockHash": null,
-					"blockNumber": null,
-					"contractAddress": "0xb1ac3a5584519119419a8e56422d912c782d8e5b",
-					"cumulativeGasUsed": "0x1c1999",
-					"gasUsed": "0xcdb5d",
-					"logs": [],
-					"logsBloom": "0x00000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000",
-					"root": null,
-					"status": "0x1",
-					"transactionHash": "0x36efc16910ea67a2425a1e75f7e39e3c6a94f5763c68a47258f552481e20cd34",
-					"transactionIndex":"0x4"
-				});
+				req => json!([H256::from_low_u64_be(2)]),
+				res => receipt(2, "0x1010", "0x1");
+		);
+		let nonce_manager = nonce_manager(&transport);
+		let tracked_transaction = tracked_transaction(
+			&transport,
+			&nonce_manager,
+			Duration::from_secs(0),
+			200.into(),
+		);
+
+		let mut event_loop = Core::new().unwrap();
+		let receipt = event_loop.run(tracked_transaction).unwrap();
+		assert_eq!(receipt.transaction_hash, H256::from_low_u64_be(2));
+		assert_eq!(transport.actual_requests(), transport.expected_requests());
+	}
+
+	#[test]
+	fn test_tracked_transaction_releases_nonce_if_resubmission_is_rejected() {
+		let transport = mock_transport!(
+			"eth_getTransactionCount" =>
+				req => json!([AUTHORITY, "pending"]),
+				res => json!("0x5");
+			// invalid response. the transaction may have been sent
+			"eth_sendTransaction" =>
+				req => send_transaction_params("0x64"),
+				res => json!(null);
 			"eth_blockNumber" =>
 				req => json!([]),
-				res => json!("0x1014");
+				res => json!("0x1010");
+			"eth_sendTransaction" =>
+				req => send_transaction_params("0x96"),
+				res => MockResponse::RpcError("insufficient funds for gas * price + value".into());
+			// the nonce was never used
+			"eth_getTransactionCount" =>
+				req => json!([AUTHORITY, "pending"]),
+				res => json!("0x5");
+		);
+		let nonce_manager = nonce_manager(&transport);
+		let tracked_transaction = tracked_transaction(
+			&transport,
+			&nonce_manager,
+			Duration::from_secs(0),
+			200.into(),
+		);
+
+		let mut event_loop = Core::new().unwrap();
+		assert!(event_loop.run(tracked_transaction).is_err());
+		// the next transaction fills the gap
+		assert_eq!(event_loop.run(nonce_manager.next()).unwrap(), 0x5.into());
+		assert_eq!(transport.actual_requests(), transport.expected_requests());
+	}
+
+	#[test]
+	fn test_tracked_transaction_fails_if_uncertain_send_cant_be_resubmitted() {
+		let transport = mock_transport!(
+			"eth_getTransactionCount" =>
+				req => json!([AUTHORITY, "pending"]),
+				res => json!("0x5");
+			// invalid response. the transaction may have been sent
+			"eth_sendTransaction" =>
+				req => send_transaction_params("0x64"),
+				res => json!(null);
 			"eth_blockNumber" =>
 				req => json!([]),
-				res => json!("0x1015");
-			"eth_getTransactionReceipt" =>
-				req => json!(["0x36efc16910ea67a2425a1e75f7e39e3c6a94f5763c68a47258f552481e20cd34"]),
-				res => json!({
-					"blockHash": "0xe0bdcf35b14a292d2998308d9b3fdea93a8c3d9c0b6c824c633fb9b15f9c3919",
-					"blockNumber": "0x1015",
-					"contractAddress": "0xb1ac3a5584519119419a8e56422d912c782d8e5b",
-					"cumulativeGasUsed": "0x1c1999",
-					"gasUsed": "0xcdb5d",
-					"logs": [],
-					"logsBloom": "0x00000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000",
-					"root": null,
-					"status": "0x1",
-					"transactionHash": "0x36efc16910ea67a2425a1e75f7e39e3c6a94f5763c68a47258f552481e20cd34",
-					"transactionIndex":"0x4"
-				});
+				res => json!("0x1010");
+		);
+		let nonce_manager = nonce_manager(&transport);
+		// the gas price is already at max
+		let tracked_transaction = tracked_transaction(
+			&transport,
+			&nonce_manager,
+			Duration::from_secs(0),
+			100.into(),
+		);
+
+		let mut event_loop = Core::new().unwrap();
+		let err = event_loop.run(tracked_transaction).unwrap_err();
+		assert!(err.to_string().contains("fees are already at max of 100"));
+		assert!(err.is_transient());
+		assert_eq!(transport.actual_requests(), transport.expected_requests());
+	}
+
+	#[test]
+	fn test_tracked_transaction_fails_if_reverted() {
+		let transport = mock_transport!(
+			"eth_getTransactionCount" =>
+				req => json!([AUTHORITY, "pending"]),
+				res => json!("0x5");
+			"eth_sendTransaction" =>
+				req => send_transaction_params("0x64"),
+				res => json!(H256::from_low_u64_be(1));
 			"eth_blockNumber" =>
 				req => json!([]),
-				res => json!("0x1017");
+				res => json!("0x1011");
 			"eth_getTransactionReceipt" =>
-				req => json!(["0x36efc16910ea67a2425a1e75f7e39e3c6a94f5763c68a47258f552481e20cd34"]),
-				res => json!({
-					"blockHash": "0xe0bdcf35b14a292d2998308d9b3fdea93a8c3d9c0b6c824c633fb9b15f9c3919",
-					"blockNumber": "0x1015",
-					"contractAddress": "0xb1ac3a5584519119419a8e56422d912c782d8e5b",
-					"cumulativeGasUsed": "0x1c1999",
-					"gasUsed": "0xcdb5d",
-					"logs": [],
-					"logsBloom": "0x00000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000",
-					"root": null,
-					"status": "0x1",
-					"transactionHash": "0x36efc16910ea67a2425a1e75f7e39e3c6a94f5763c68a47258f552481e20cd34",
-					"transactionIndex":"0x4"
-				});
+				req => json!([H256::from_low_u64_be(1)]),
+				res => receipt(1, "0x1010", "0x0");
+		);
+		let nonce_manager = nonce_manager(&transport);
+		let tracked_transaction = tracked_transaction(
+			&transport,
+			&nonce_manager,
+			Duration::from_secs(60),
+			200.into(),
 		);
-
-		let send_transaction_with_receipt =
-			SendTransactionWithReceipt::new(SendTransactionWithReceiptOptions {
-				transport: transport.clone(),
-				request_timeout: Duration::from_secs(1),
-				poll_interval: Duration::from_secs(0),
-				confirmations: 2,
-				transaction: TransactionRequest {
-					from: "006b5dda44dc2606f07ad86c9190fb54fd905f6d".parse().unwrap(),
-					to: None,
-					gas: Some(0xf4240.into()),
-					gas_price: Some(0.into()),
-					value: None,
-					data: Some(vec![0x60].into()),
-					nonce: None,
-					condition: None,
-				},
-			});
 
 		let mut event_loop = Core::new().unwrap();
-		let receipt = event_loop.run(send_transaction_with_receipt).unwrap();
-		assert_eq!(
-			receipt,
-			TransactionReceipt {
-				transaction_hash:
-					"36efc16910ea67a2425a1e75f7e39e3c6a94f5763c68a47258f552481e20cd34"
-						.parse()
-						.unwrap(),
-				transaction_index: 0x4.into(),
-				block_hash: Some(
-					"e0bdcf35b14a292d2998308d9b3fdea93a8c3d9c0b6c824c633fb9b15f9c3919"
-						.parse()
-						.unwrap()
-				),
-				block_number: Some(0x1015.into()),
-				cumulative_gas_used: 0x1c1999.into(),
-				gas_used: "cdb5d".parse().ok(),
-				contract_address: Some("b1ac3a5584519119419a8e56422d912c782d8e5b".parse().unwrap()),
-				logs: vec![],
-				status: Some(1.into()),
-				logs_bloom: Default::default(),
+		match *event_loop.run(tracked_transaction).unwrap_err().kind() {
+			error::ErrorKind::TransactionReverted(hash) => {
+				assert_eq!(hash, H256::from_low_u64_be(1))
 			}
-		);
+			ref kind => panic!("unexpected error {:?}", kind),
+		}
 		assert_eq!(transport.actual_requests(), transport.expected_requests());
 	}
 }
@@ -18,13 +18,19 @@ use contracts;
 use database::State;
 use ethabi::FunctionOutputDecoder;
 use futures::future::{join_all, JoinAll};
+use gas_limit::GasLimit;
+use gas_price::GasPriceOracle;
 use helpers::{AsyncCall, AsyncTransaction};
 use log_stream::{LogStream, LogStreamOptions};
 use message_to_main::MessageToMain;
+use nonce_manager::NonceManager;
+use send_tx_with_receipt::{TrackedTransaction, TrackedTransactionOptions};
 use signature::Signature;
+use signer::Signer;
 use std::time::Duration;
-use web3::types::{Address, H256, U256};
+use web3::types::{Address, Bytes, TransactionRequest, H256};
 use web3::Transport;
+use ws_transport::WsTransport;
 
 /// highlevel wrapper around the auto generated ethabi contract `bridge_contracts::side`
 #[derive(Clone)]
@@ -37,15 +43,35 @@ pub struct SideContract<T> {
 	pub request_timeout: Duration,
 	pub logs_poll_interval: Duration,
 	pub required_log_confirmations: u32,
-	pub sign_main_to_side_gas: U256,
-	pub sign_main_to_side_gas_price: U256,
-	pub sign_side_to_main_gas: U256,
-	pub sign_side_to_main_gas_price: U256,
+	pub sign_main_to_side_gas: GasLimit,
+	pub sign_main_to_side_gas_price: GasPriceOracle,
+	pub sign_side_to_main_gas: GasLimit,
+	pub sign_side_to_main_gas_price: GasPriceOracle,
+	/// if set messages and transactions are signed locally instead of by the node
+	pub signer: Option<Signer>,
+	pub nonce_manager: NonceManager<T>,
+	pub resubmit_after: Duration,
+	pub gas_price_bump_percent: u32,
+	/// maximum number of blocks per `eth_getLogs` call of the log streams
+	pub max_log_block_range: Option<u64>,
+	/// if set the log streams learn about new blocks through a subscription
+	pub new_heads: Option<WsTransport>,
 }
 
 impl<T: Transport> SideContract<T> {
-	pub fn new(transport: T, config: &Config, state: &State) -> Self {
+	pub fn new(
+		transport: T,
+		new_heads: Option<WsTransport>,
+		config: &Config,
+		state: &State,
+		signer: Option<Signer>,
+	) -> Self {
 		Self {
+			nonce_manager: NonceManager::new(
+				transport.clone(),
+				config.address,
+				config.side.request_timeout,
+			),
 			transport,
 			contract_address: state.side_contract_address,
 			authority_address: config.address,
@@ -53,10 +79,21 @@ impl<T: Transport> SideContract<T> {
 			request_timeout: config.side.request_timeout,
 			logs_poll_interval: config.side.poll_interval,
 			required_log_confirmations: config.side.required_confirmations,
-			sign_main_to_side_gas: config.txs.deposit_relay.gas,
-			sign_main_to_side_gas_price: config.txs.deposit_relay.gas_price,
-			sign_side_to_main_gas: config.txs.withdraw_confirm.gas,
-			sign_side_to_main_gas_price: config.txs.withdraw_confirm.gas_price,
+			sign_main_to_side_gas: GasLimit::new(
+				config.txs.deposit_relay.gas,
+				&config.txs.deposit_relay,
+			),
+			sign_main_to_side_gas_price: GasPriceOracle::new(&config.txs.deposit_relay),
+			sign_side_to_main_gas: GasLimit::new(
+				config.txs.withdraw_confirm.gas,
+				&config.txs.withdraw_confirm,
+			),
+			sign_side_to_main_gas_price: GasPriceOracle::new(&config.txs.withdraw_confirm),
+			signer,
+			resubmit_after: config.side.resubmit_after,
+			gas_price_bump_percent: config.side.gas_price_bump_percent,
+			max_log_block_range: config.side.max_log_block_range,
+			new_heads,
 		}
 	}
 
@@ -74,6 +111,47 @@ impl<T: Transport> SideContract<T> {
 		)
 	}
 
+	/// send a transaction with `payload` to the side contract.
+	/// the returned `Future` resolves once it is mined with `required_log_confirmations`.
+	/// the gas limit comes from `gas` and the gas price from `gas_price`
+	/// which also caps re-submissions
+	pub fn transaction(
+		&self,
+		gas: &GasLimit,
+		gas_price: &GasPriceOracle,
+		payload: Vec<u8>,
+	) -> TrackedTransaction<T> {
+		let request = TransactionRequest {
+			from: self.authority_address,
+			to: Some(self.contract_address),
+			gas: None,
+			gas_price: None,
+			value: None,
+			data: Some(Bytes(payload)),
+			nonce: None,
+			condition: None,
+		};
+		let transaction = AsyncTransaction::new(
+			&self.transport,
+			self.signer.as_ref(),
+			&self.nonce_manager,
+			gas.gas(&self.transport, &request, self.request_timeout),
+			gas_price.fees(&self.transport, self.request_timeout),
+			request,
+			self.request_timeout,
+		);
+		TrackedTransaction::new(TrackedTransactionOptions {
+			transport: self.transport.clone(),
+			transaction,
+			request_timeout: self.request_timeout,
+			poll_interval: self.logs_poll_interval,
+			confirmations: self.required_log_confirmations,
+			resubmit_after: self.resubmit_after,
+			gas_price_bump_percent: self.gas_price_bump_percent,
+			max_gas_price: gas_price.max_gas_price(),
+		})
+	}
+
 	pub fn is_side_contract(
 		&self,
 	) -> AsyncCall<T, contracts::side::functions::is_side_bridge_contract::Decoder> {
@@ -121,7 +199,7 @@ impl<T: Transport> SideContract<T> {
 		data: Vec<u8>,
 		sender: Address,
 		recipient: Address,
-	) -> AsyncTransaction<T> {
+	) -> TrackedTransaction<T> {
 		let payload = contracts::side::functions::accept_message::encode_input(
 			transaction_hash,
 			data,
@@ -129,13 +207,9 @@ impl<T: Transport> SideContract<T> {
 			recipient,
 		);
 
-		AsyncTransaction::new(
-			&self.transport,
-			self.contract_address,
-			self.authority_address,
-			self.sign_main_to_side_gas,
-			self.sign_main_to_side_gas_price,
-			self.request_timeout,
+		self.transaction(
+			&self.sign_main_to_side_gas,
+			&self.sign_main_to_side_gas_price,
 			payload,
 		)
 	}
@@ -149,6 +223,8 @@ impl<T: Transport> SideContract<T> {
 			transport: self.transport.clone(),
 			contract_address: self.contract_address,
 			after,
+			max_block_range: self.max_log_block_range,
+			new_heads: self.new_heads.clone(),
 		})
 	}
 
@@ -161,6 +237,8 @@ impl<T: Transport> SideContract<T> {
 			transport: self.transport.clone(),
 			contract_address: self.contract_address,
 			after,
+			max_block_range: self.max_log_block_range,
+			new_heads: self.new_heads.clone(),
 		})
 	}
 
@@ -168,18 +246,14 @@ impl<T: Transport> SideContract<T> {
 		&self,
 		message: &MessageToMain,
 		signature: &Signature,
-	) -> AsyncTransaction<T> {
+	) -> TrackedTransaction<T> {
 		let payload = contracts::side::functions::submit_signed_message::encode_input(
 			signature.to_bytes(),
 			message.to_bytes(),
 		);
-		AsyncTransaction::new(
-			&self.transport,
-			self.contract_address,
-			self.authority_address,
-			self.sign_side_to_main_gas,
-			self.sign_side_to_main_gas_price,
-			self.request_timeout,
+		self.transaction(
+			&self.sign_side_to_main_gas,
+			&self.sign_side_to_main_gas_price,
 			payload,
 		)
 	}
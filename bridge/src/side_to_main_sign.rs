@@ -17,9 +17,11 @@ use contracts;
 use error::{self, ResultExt};
 use futures::future::FromErr;
 use futures::{Async, Future, Poll};
-use helpers::{AsyncCall, AsyncTransaction};
+use helpers::AsyncCall;
+use journal::{Journal, RelayJournal, RelayKind};
 use message_to_main::{MessageToMain, MESSAGE_LENGTH};
 use relay_stream::LogToFuture;
+use send_tx_with_receipt::TrackedTransaction;
 use side_contract::SideContract;
 use signature::Signature;
 use tokio_timer::{Timeout, Timer};
@@ -34,7 +36,7 @@ enum State<T: Transport> {
 		AsyncCall<T, contracts::side::functions::has_authority_signed_message::Decoder>,
 	),
 	AwaitSignature(Timeout<FromErr<CallFuture<H520, T::Out>, error::Error>>),
-	AwaitTransaction(AsyncTransaction<T>),
+	AwaitTransaction(TrackedTransaction<T>),
 }
 
 /// `Future` that is responsible for calling `sideContract.submitSignature`
@@ -46,13 +48,18 @@ pub struct SideToMainSign<T: Transport> {
 	side: SideContract<T>,
 	message: MessageToMain,
 	state: State<T>,
+	journal: RelayJournal,
 }
 
 impl<T: Transport> SideToMainSign<T> {
-	pub fn new(log: &Log, side: SideContract<T>) -> Self {
+	pub fn new(log: &Log, side: SideContract<T>, journal: &Journal) -> Self {
 		let tx_hash = log
 			.transaction_hash
 			.expect("`log` must be mined and contain `transaction_hash`. q.e.d.");
+		let log_index = log
+			.log_index
+			.expect("`log` must be mined and contain `log_index`. q.e.d.")
+			.as_u64();
 
 		let message =
 			MessageToMain::from_log(log).expect("`log` must contain valid message. q.e.d.");
@@ -65,15 +72,18 @@ impl<T: Transport> SideToMainSign<T> {
 			MESSAGE_LENGTH
 		);
 
+		let journal = journal.relay(RelayKind::SideToMainSign, tx_hash, log_index);
+		journal.message_id(message.message_id);
+
 		let future = side.is_side_to_main_signed_on_side(&message);
 		let state = State::AwaitCheckAlreadySigned(future);
-		info!("{:?} - step 1/3 - about to sign message", tx_hash);
 
 		Self {
 			side,
 			tx_hash,
 			message,
 			state,
+			journal,
 		}
 	}
 }
@@ -84,46 +94,62 @@ impl<T: Transport> Future for SideToMainSign<T> {
 	type Error = error::Error;
 
 	fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+		let result = self.poll_state();
+		if let Err(ref err) = result {
+			self.journal.failed(err);
+		}
+		result
+	}
+}
+
+impl<T: Transport> SideToMainSign<T> {
+	fn poll_state(&mut self) -> Poll<Option<H256>, error::Error> {
 		loop {
 			let next_state = match self.state {
 				State::AwaitCheckAlreadySigned(ref mut future) => {
 					let is_already_signed = try_ready!(future
 						.poll()
-						.chain_err(|| "WithdrawConfirm: message signing failed"));
+						.chain_err(|| "SideToMainSign: message signing failed"));
 					if is_already_signed {
+						self.journal.skipped();
 						return Ok(Async::Ready(None));
 					}
 
-					let inner_future = web3::api::Eth::new(self.side.transport.clone())
-						.sign(self.side.authority_address, Bytes(self.message.to_bytes()))
-						.from_err();
-					let timeout_future =
-						Timer::default().timeout(inner_future, self.side.request_timeout);
-					State::AwaitSignature(timeout_future)
+					if let Some(ref signer) = self.side.signer {
+						let signature = signer.sign_message(&self.message.to_bytes())?;
+						self.journal.signed();
+						let future = self.side.submit_signed_message(&self.message, &signature);
+						State::AwaitTransaction(future)
+					} else {
+						let inner_future = web3::api::Eth::new(self.side.transport.clone())
+							.sign(self.side.authority_address, Bytes(self.message.to_bytes()))
+							.from_err();
+						let timeout_future =
+							Timer::default().timeout(inner_future, self.side.request_timeout);
+						State::AwaitSignature(timeout_future)
+					}
 				}
 				State::AwaitSignature(ref mut future) => {
 					let signature_bytes = try_ready!(future
 						.poll()
-						.chain_err(|| "WithdrawConfirm: message signing failed"));
-					info!(
-						"{:?} - step 2/3 - message signed. about to send transaction",
-						self.tx_hash
-					);
+						.chain_err(|| "SideToMainSign: message signing failed"));
 
 					let signature = Signature::from_bytes(&signature_bytes.as_bytes())?;
+					self.journal.signed();
 
 					let future = self.side.submit_signed_message(&self.message, &signature);
 					State::AwaitTransaction(future)
 				}
 				State::AwaitTransaction(ref mut future) => {
-					let tx_hash = try_ready!(future
+					let result = future
 						.poll()
-						.chain_err(|| "WithdrawConfirm: sending transaction failed"));
-					info!(
-						"{:?} - step 3/3 - DONE - transaction sent {:?}",
-						self.tx_hash, tx_hash
-					);
-					return Ok(Async::Ready(Some(tx_hash)));
+						.chain_err(|| "SideToMainSign: sending transaction failed");
+					if let Some(hash) = future.transaction_hash() {
+						self.journal.submitted(hash);
+					}
+					let receipt = try_ready!(result);
+					self.journal.mined(receipt.transaction_hash);
+					return Ok(Async::Ready(Some(receipt.transaction_hash)));
 				}
 			};
 			self.state = next_state;
@@ -133,14 +159,21 @@ impl<T: Transport> Future for SideToMainSign<T> {
 
 pub struct LogToSideToMainSign<T: Transport> {
 	pub side: SideContract<T>,
+	pub journal: Journal,
 }
 
 /// from the options and a log a relay future can be made
 impl<T: Transport> LogToFuture for LogToSideToMainSign<T> {
 	type Future = SideToMainSign<T>;
 
+	fn log_seen(&self, log: &Log) {
+		self.journal
+			.relay_of_log(RelayKind::SideToMainSign, log)
+			.seen()
+	}
+
 	fn log_to_future(&self, log: &Log) -> Self::Future {
-		SideToMainSign::new(log, self.side.clone())
+		SideToMainSign::new(log, self.side.clone(), &self.journal)
 	}
 }
 
@@ -149,8 +182,13 @@ mod tests {
 	use super::*;
 	use contracts;
 	use ethabi;
+	use ethsign::SecretKey;
+	use gas_limit::GasLimit;
+	use gas_price::GasPriceOracle;
+	use nonce_manager::NonceManager;
 	use rustc_hex::FromHex;
 	use rustc_hex::ToHex;
+	use signer::{Fees, Key, Signer, UnsignedTransaction};
 	use tokio_core::reactor::Core;
 	use web3::types::{Address, Bytes, Log};
 
@@ -185,7 +223,7 @@ mod tests {
 			block_hash: None,
 			block_number: None,
 			transaction_index: None,
-			log_index: None,
+			log_index: Some(0.into()),
 			transaction_log_index: None,
 			log_type: None,
 			removed: None,
@@ -229,15 +267,37 @@ mod tests {
 					format!("0x{}", message.to_bytes().to_hex::<String>())
 				]),
 				res => json!(format!("0x{}", signature));
+			"eth_getTransactionCount" =>
+				req => json!([format!("0x{:x}", authority_address), "pending"]),
+				res => json!("0x0");
 			"eth_sendTransaction" =>
 				req => json!([{
 					"data": format!("0x{}", tx_data.to_hex::<String>()),
 					"from": format!("0x{:x}", authority_address),
 					"gas": "0xfd",
 					"gasPrice": "0xa0",
+					"nonce": "0x0",
 					"to": format!("0x{:x}", side_contract_address),
 				}]),
 				res => json!(format!("0x{}", tx_hash));
+			"eth_blockNumber" =>
+				req => json!([]),
+				res => json!("0x1011");
+			"eth_getTransactionReceipt" =>
+				req => json!([format!("0x{}", tx_hash)]),
+				res => json!({
+					"blockHash": "0xe0bdcf35b14a292d2998308d9b3fdea93a8c3d9c0b6c824c633fb9b15f9c3919",
+					"blockNumber": "0x1011",
+					"contractAddress": null,
+					"cumulativeGasUsed": "0x1c1999",
+					"gasUsed": "0xcdb5d",
+					"logs": [],
+					"logsBloom": format!("0x{}", "0".repeat(512)),
+					"root": null,
+					"status": "0x1",
+					"transactionHash": format!("0x{}", tx_hash),
+					"transactionIndex": "0x4"
+				});
 		);
 
 		let side_contract = SideContract {
@@ -248,13 +308,23 @@ mod tests {
 			request_timeout: ::std::time::Duration::from_millis(0),
 			logs_poll_interval: ::std::time::Duration::from_millis(0),
 			required_log_confirmations: 0,
-			sign_main_to_side_gas: 0.into(),
-			sign_main_to_side_gas_price: 0.into(),
-			sign_side_to_main_gas: 0xfd.into(),
-			sign_side_to_main_gas_price: 0xa0.into(),
+			sign_main_to_side_gas: GasLimit::Fixed(0.into()),
+			sign_main_to_side_gas_price: GasPriceOracle::fixed(0.into()),
+			sign_side_to_main_gas: GasLimit::Fixed(0xfd.into()),
+			sign_side_to_main_gas_price: GasPriceOracle::fixed(0xa0.into()),
+			signer: None,
+			nonce_manager: NonceManager::new(
+				transport.clone(),
+				authority_address,
+				::std::time::Duration::from_millis(0),
+			),
+			resubmit_after: ::std::time::Duration::from_millis(0),
+			gas_price_bump_percent: 0,
+			max_log_block_range: None,
+			new_heads: None,
 		};
 
-		let future = SideToMainSign::new(&raw_log, side_contract);
+		let future = SideToMainSign::new(&raw_log, side_contract, &Journal::in_memory());
 
 		let mut event_loop = Core::new().unwrap();
 		let result = event_loop.run(future).unwrap();
@@ -294,7 +364,7 @@ mod tests {
 			block_hash: None,
 			block_number: None,
 			transaction_index: None,
-			log_index: None,
+			log_index: Some(0.into()),
 			transaction_log_index: None,
 			log_type: None,
 			removed: None,
@@ -334,13 +404,23 @@ mod tests {
 			request_timeout: ::std::time::Duration::from_millis(0),
 			logs_poll_interval: ::std::time::Duration::from_millis(0),
 			required_log_confirmations: 0,
-			sign_main_to_side_gas: 0.into(),
-			sign_main_to_side_gas_price: 0.into(),
-			sign_side_to_main_gas: 0xfd.into(),
-			sign_side_to_main_gas_price: 0xa0.into(),
+			sign_main_to_side_gas: GasLimit::Fixed(0.into()),
+			sign_main_to_side_gas_price: GasPriceOracle::fixed(0.into()),
+			sign_side_to_main_gas: GasLimit::Fixed(0xfd.into()),
+			sign_side_to_main_gas_price: GasPriceOracle::fixed(0xa0.into()),
+			signer: None,
+			nonce_manager: NonceManager::new(
+				transport.clone(),
+				authority_address,
+				::std::time::Duration::from_millis(0),
+			),
+			resubmit_after: ::std::time::Duration::from_millis(0),
+			gas_price_bump_percent: 0,
+			max_log_block_range: None,
+			new_heads: None,
 		};
 
-		let future = SideToMainSign::new(&raw_log, side_contract);
+		let future = SideToMainSign::new(&raw_log, side_contract, &Journal::in_memory());
 
 		let mut event_loop = Core::new().unwrap();
 		let result = event_loop.run(future).unwrap();
@@ -348,4 +428,148 @@ mod tests {
 
 		assert_eq!(transport.actual_requests(), transport.expected_requests());
 	}
+
+	#[test]
+	fn test_side_to_main_sign_relay_future_signed_locally() {
+		let topic = contracts::side::events::relay_message::filter().topic0;
+
+		let log = contracts::side::logs::RelayMessage {
+			message_id: "884edad9ce6fa2440d8a54cc123490eb96d2768479d49ff9c7366125a94243ff"
+				.parse()
+				.unwrap(),
+			sender: "aff3454fce5edbc8cca8697c15331677e6ebccff".parse().unwrap(),
+			recipient: "aff3454fce5edbc8cca8697c15331677e6ebcccc".parse().unwrap(),
+		};
+
+		let log_data = ethabi::encode(&[
+			ethabi::Token::FixedBytes(log.message_id.as_bytes().to_vec()),
+			ethabi::Token::Address(log.sender),
+			ethabi::Token::Address(log.recipient),
+		]);
+
+		let log_tx_hash = "884edad9ce6fa2440d8a54cc123490eb96d2768479d49ff9c7366125a9424364"
+			.parse()
+			.unwrap();
+
+		let raw_log = Log {
+			address: "0000000000000000000000000000000000000001".parse().unwrap(),
+			topics: topic.into(),
+			data: Bytes(log_data),
+			transaction_hash: Some(log_tx_hash),
+			block_hash: None,
+			block_number: None,
+			transaction_index: None,
+			log_index: Some(0.into()),
+			transaction_log_index: None,
+			log_type: None,
+			removed: None,
+		};
+
+		let secret: Vec<u8> = "4646464646464646464646464646464646464646464646464646464646464646"
+			.from_hex()
+			.unwrap();
+		let signer = Signer::new(
+			Key::from_secret_key(SecretKey::from_raw(&secret).unwrap()),
+			77,
+		);
+		let authority_address = signer.address();
+
+		let tx_hash = "1db8f385535c0d178b8f40016048f3a3cffee8f94e68978ea4b277f57b638f0b";
+		let side_contract_address = "0000000000000000000000000000000000000dd1".parse().unwrap();
+
+		let message = MessageToMain {
+			side_tx_hash: log_tx_hash,
+			message_id: log.message_id,
+			recipient: log.recipient,
+			sender: log.sender,
+		};
+
+		let call_data = contracts::side::functions::has_authority_signed_message::encode_input(
+			authority_address,
+			message.to_bytes(),
+		);
+
+		let signature = signer.sign_message(&message.to_bytes()).unwrap();
+
+		let raw_transaction = signer
+			.sign_transaction(&UnsignedTransaction {
+				nonce: 0x12.into(),
+				fees: Fees::Legacy {
+					gas_price: 0xa0.into(),
+				},
+				gas: 0xfd.into(),
+				to: Some(side_contract_address),
+				value: 0.into(),
+				data: contracts::side::functions::submit_signed_message::encode_input(
+					signature.to_bytes(),
+					message.to_bytes(),
+				),
+			})
+			.unwrap();
+
+		let transport = mock_transport!(
+			"eth_call" =>
+				req => json!([{
+					"data": format!("0x{}", call_data.to_hex::<String>()),
+					"to": format!("0x{:x}", side_contract_address),
+				}, "latest"]),
+				res => json!(format!("0x{}", ethabi::encode(&[ethabi::Token::Bool(false)]).to_hex::<String>()));
+			"eth_getTransactionCount" =>
+				req => json!([format!("0x{:x}", authority_address), "pending"]),
+				res => json!("0x12");
+			"eth_sendRawTransaction" =>
+				req => json!([format!("0x{}", raw_transaction.0.to_hex::<String>())]),
+				res => json!(format!("0x{}", tx_hash));
+			"eth_blockNumber" =>
+				req => json!([]),
+				res => json!("0x1011");
+			"eth_getTransactionReceipt" =>
+				req => json!([format!("0x{}", tx_hash)]),
+				res => json!({
+					"blockHash": "0xe0bdcf35b14a292d2998308d9b3fdea93a8c3d9c0b6c824c633fb9b15f9c3919",
+					"blockNumber": "0x1011",
+					"contractAddress": null,
+					"cumulativeGasUsed": "0x1c1999",
+					"gasUsed": "0xcdb5d",
+					"logs": [],
+					"logsBloom": format!("0x{}", "0".repeat(512)),
+					"root": null,
+					"status": "0x1",
+					"transactionHash": format!("0x{}", tx_hash),
+					"transactionIndex": "0x4"
+				});
+		);
+
+		let side_contract = SideContract {
+			transport: transport.clone(),
+			contract_address: side_contract_address,
+			authority_address,
+			required_signatures: 1,
+			request_timeout: ::std::time::Duration::from_millis(0),
+			logs_poll_interval: ::std::time::Duration::from_millis(0),
+			required_log_confirmations: 0,
+			sign_main_to_side_gas: GasLimit::Fixed(0.into()),
+			sign_main_to_side_gas_price: GasPriceOracle::fixed(0.into()),
+			sign_side_to_main_gas: GasLimit::Fixed(0xfd.into()),
+			sign_side_to_main_gas_price: GasPriceOracle::fixed(0xa0.into()),
+			signer: Some(signer),
+			nonce_manager: NonceManager::new(
+				transport.clone(),
+				authority_address,
+				::std::time::Duration::from_millis(0),
+			),
+			resubmit_after: ::std::time::Duration::from_millis(0),
+			gas_price_bump_percent: 0,
+			max_log_block_range: None,
+			new_heads: None,
+		};
+
+		let future = SideToMainSign::new(&raw_log, side_contract, &Journal::in_memory());
+
+		let mut event_loop = Core::new().unwrap();
+		let result = event_loop.run(future).unwrap();
+		assert_eq!(result, Some(tx_hash.parse().unwrap()));
+
+		assert_eq!(transport.actual_requests(), transport.expected_requests());
+	}
 }
@@ -19,10 +19,12 @@ use error::{self, ResultExt};
 use futures::future::JoinAll;
 use futures::{Async, Future, Poll};
 use helpers;
-use helpers::{AsyncCall, AsyncTransaction};
+use helpers::AsyncCall;
+use journal::{Journal, RelayJournal, RelayKind};
 use main_contract::MainContract;
 use message_to_main::MessageToMain;
 use relay_stream::LogToFuture;
+use send_tx_with_receipt::TrackedTransaction;
 use side_contract::SideContract;
 use signature::Signature;
 use web3::types::{Log, H256};
@@ -43,7 +45,7 @@ enum State<T: Transport> {
 		message: MessageToMain,
 		signatures: Vec<Signature>,
 	},
-	AwaitTxSent(AsyncTransaction<T>),
+	AwaitTxSent(TrackedTransaction<T>),
 }
 
 /// `Future` that completes a transfer from side to main by calling
@@ -55,13 +57,23 @@ pub struct SideToMainSignatures<T: Transport> {
 	main: MainContract<T>,
 	side: SideContract<T>,
 	state: State<T>,
+	journal: RelayJournal,
 }
 
 impl<T: Transport> SideToMainSignatures<T> {
-	pub fn new(raw_log: &Log, main: MainContract<T>, side: SideContract<T>) -> Self {
+	pub fn new(
+		raw_log: &Log,
+		main: MainContract<T>,
+		side: SideContract<T>,
+		journal: &Journal,
+	) -> Self {
 		let side_tx_hash = raw_log
 			.transaction_hash
 			.expect("`log` must be mined and contain `transaction_hash`. q.e.d.");
+		let log_index = raw_log
+			.log_index
+			.expect("`log` must be mined and contain `log_index`. q.e.d.")
+			.as_u64();
 
 		let log = helpers::parse_log(contracts::side::events::signed_message::parse_log, raw_log)
 			.expect("`Log` must be a from a `CollectedSignatures` event. q.e.d.");
@@ -74,7 +86,9 @@ impl<T: Transport> SideToMainSignatures<T> {
 			"incorrectly set up collected_signatures filter, we should only received logs where authority_responsible_for_relay == main.authority_address; qed"
 		);
 
-		info!("{:?} - step 1/3 - about to fetch message", side_tx_hash,);
+		// the message id is known once the message is fetched
+		let journal = journal.relay(RelayKind::SideToMainSignatures, side_tx_hash, log_index);
+
 		let (payload, decoder) = contracts::side::functions::message::call(log.message_hash);
 		let state = State::AwaitMessage(side.call(payload, decoder));
 
@@ -83,6 +97,7 @@ impl<T: Transport> SideToMainSignatures<T> {
 			main,
 			side,
 			state,
+			journal,
 		}
 	}
 }
@@ -92,13 +107,24 @@ impl<T: Transport> Future for SideToMainSignatures<T> {
 	type Error = error::Error;
 
 	fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+		let result = self.poll_state();
+		if let Err(ref err) = result {
+			self.journal.failed(err);
+		}
+		result
+	}
+}
+
+impl<T: Transport> SideToMainSignatures<T> {
+	fn poll_state(&mut self) -> Poll<Option<H256>, error::Error> {
 		loop {
 			let next_state = match self.state {
 				State::AwaitMessage(ref mut future) => {
 					let message_bytes = try_ready!(future
 						.poll()
-						.chain_err(|| "SubmitSignature: fetching message failed"));
+						.chain_err(|| "SideToMainSignatures: fetching message failed"));
 					let message = MessageToMain::from_bytes(&message_bytes)?;
+					self.journal.message_id(message.message_id);
 
 					let (payload, decoder) =
 						contracts::main::functions::accepted_messages::call(message.keccak256());
@@ -113,9 +139,10 @@ impl<T: Transport> Future for SideToMainSignatures<T> {
 				} => {
 					let is_relayed = try_ready!(future
 						.poll()
-						.chain_err(|| "SubmitSignature: fetching message failed"));
+						.chain_err(|| "SideToMainSignatures: fetching message failed"));
 
 					if is_relayed {
+						self.journal.skipped();
 						return Ok(Async::Ready(None));
 					}
 
@@ -128,14 +155,13 @@ impl<T: Transport> Future for SideToMainSignatures<T> {
 					ref mut future,
 					ref message,
 				} => {
-					let raw_signatures = try_ready!(future
-						.poll()
-						.chain_err(|| "WithdrawRelay: fetching message and signatures failed"));
+					let raw_signatures = try_ready!(future.poll().chain_err(|| {
+						"SideToMainSignatures: fetching message and signatures failed"
+					}));
 					let signatures: Vec<Signature> = raw_signatures
 						.iter()
 						.map(|x| Signature::from_bytes(x))
 						.collect::<Result<_, _>>()?;
-					info!("{:?} - step 2/3 - message and {} signatures received. about to send transaction", self.side_tx_hash, signatures.len());
 
 					let (payload, decoder) =
 						contracts::side::functions::relayed_messages::call(message.message_id);
@@ -152,7 +178,7 @@ impl<T: Transport> Future for SideToMainSignatures<T> {
 				} => {
 					let message_data = try_ready!(future
 						.poll()
-						.chain_err(|| "SubmitSignature: fetching message failed"));
+						.chain_err(|| "SideToMainSignatures: fetching message failed"));
 
 					State::AwaitTxSent(self.main.relay_side_to_main(
 						&message,
@@ -161,14 +187,15 @@ impl<T: Transport> Future for SideToMainSignatures<T> {
 					))
 				}
 				State::AwaitTxSent(ref mut future) => {
-					let main_tx_hash = try_ready!(future
+					let result = future
 						.poll()
-						.chain_err(|| "WithdrawRelay: sending transaction failed"));
-					info!(
-						"{:?} - step 3/3 - DONE - transaction sent {:?}",
-						self.side_tx_hash, main_tx_hash
-					);
-					return Ok(Async::Ready(Some(main_tx_hash)));
+						.chain_err(|| "SideToMainSignatures: sending transaction failed");
+					if let Some(hash) = future.transaction_hash() {
+						self.journal.submitted(hash);
+					}
+					let receipt = try_ready!(result);
+					self.journal.mined(receipt.transaction_hash);
+					return Ok(Async::Ready(Some(receipt.transaction_hash)));
 				}
 			};
 			self.state = next_state;
@@ -180,14 +207,21 @@ impl<T: Transport> Future for SideToMainSignatures<T> {
 pub struct LogToSideToMainSignatures<T> {
 	pub main: MainContract<T>,
 	pub side: SideContract<T>,
+	pub journal: Journal,
 }
 
 /// from the options and a log a relay future can be made
 impl<T: Transport> LogToFuture for LogToSideToMainSignatures<T> {
 	type Future = SideToMainSignatures<T>;
 
+	fn log_seen(&self, log: &Log) {
+		self.journal
+			.relay_of_log(RelayKind::SideToMainSignatures, log)
+			.seen()
+	}
+
 	fn log_to_future(&self, log: &Log) -> Self::Future {
-		SideToMainSignatures::new(log, self.main.clone(), self.side.clone())
+		SideToMainSignatures::new(log, self.main.clone(), self.side.clone(), &self.journal)
 	}
 }
 
@@ -196,6 +230,9 @@ mod tests {
 	use super::*;
 	use contracts;
 	use ethabi;
+	use gas_limit::GasLimit;
+	use gas_price::GasPriceOracle;
+	use nonce_manager::NonceManager;
 	use rustc_hex::FromHex;
 	use rustc_hex::ToHex;
 	use tokio_core::reactor::Core;
@@ -242,7 +279,7 @@ mod tests {
 			block_hash: None,
 			block_number: None,
 			transaction_index: None,
-			log_index: None,
+			log_index: Some(0.into()),
 			transaction_log_index: None,
 			log_type: None,
 			removed: None,
@@ -268,6 +305,9 @@ mod tests {
 					"to": format!("0x{:x}", main_contract_address),
 				}, "latest"]),
 				res => json!(format!("0x{}", ethabi::encode(&[ethabi::Token::Bool(false)]).to_hex::<String>()));
+			"eth_getTransactionCount" =>
+				req => json!([format!("0x{:x}", authority_address), "pending"]),
+				res => json!("0x0");
 			"eth_sendTransaction" =>
 				req => json!([{
 					"data": format!(
@@ -284,11 +324,29 @@ mod tests {
 					),
 					"from": format!("0x{:x}", authority_address),
 					"gas": "0xfd",
-					// TODO: fix gasPrice
-					"gasPrice": format!("0x{:x}", 1000),
+					"gasPrice": "0xa0",
+					"nonce": "0x0",
 					"to": format!("0x{:x}", main_contract_address),
 				}]),
 				res => json!(format!("0x{:}", tx_hash));
+			"eth_blockNumber" =>
+				req => json!([]),
+				res => json!("0x1011");
+			"eth_getTransactionReceipt" =>
+				req => json!([format!("0x{}", tx_hash)]),
+				res => json!({
+					"blockHash": "0xe0bdcf35b14a292d2998308d9b3fdea93a8c3d9c0b6c824c633fb9b15f9c3919",
+					"blockNumber": "0x1011",
+					"contractAddress": null,
+					"cumulativeGasUsed": "0x1c1999",
+					"gasUsed": "0xcdb5d",
+					"logs": [],
+					"logsBloom": format!("0x{}", "0".repeat(512)),
+					"root": null,
+					"status": "0x1",
+					"transactionHash": format!("0x{}", tx_hash),
+					"transactionIndex": "0x4"
+				});
 		);
 
 		let side_transport = mock_transport!(
@@ -322,7 +380,18 @@ mod tests {
 			request_timeout: ::std::time::Duration::from_millis(0),
 			logs_poll_interval: ::std::time::Duration::from_millis(0),
 			required_log_confirmations: 0,
-			submit_collected_signatures_gas: 0xfd.into(),
+			submit_collected_signatures_gas: GasLimit::Fixed(0xfd.into()),
+			submit_collected_signatures_gas_price: GasPriceOracle::fixed(0xa0.into()),
+			signer: None,
+			nonce_manager: NonceManager::new(
+				main_transport.clone(),
+				authority_address,
+				::std::time::Duration::from_millis(0),
+			),
+			resubmit_after: ::std::time::Duration::from_millis(0),
+			gas_price_bump_percent: 0,
+			max_log_block_range: None,
+			new_heads: None,
 		};
 
 		let side_contract = SideContract {
@@ -333,13 +402,28 @@ mod tests {
 			request_timeout: ::std::time::Duration::from_millis(0),
 			logs_poll_interval: ::std::time::Duration::from_millis(0),
 			required_log_confirmations: 0,
-			sign_main_to_side_gas: 0.into(),
-			sign_main_to_side_gas_price: 0.into(),
-			sign_side_to_main_gas: 0xfd.into(),
-			sign_side_to_main_gas_price: 0xa0.into(),
+			sign_main_to_side_gas: GasLimit::Fixed(0.into()),
+			sign_main_to_side_gas_price: GasPriceOracle::fixed(0.into()),
+			sign_side_to_main_gas: GasLimit::Fixed(0xfd.into()),
+			sign_side_to_main_gas_price: GasPriceOracle::fixed(0xa0.into()),
+			signer: None,
+			nonce_manager: NonceManager::new(
+				side_transport.clone(),
+				authority_address,
+				::std::time::Duration::from_millis(0),
+			),
+			resubmit_after: ::std::time::Duration::from_millis(0),
+			gas_price_bump_percent: 0,
+			max_log_block_range: None,
+			new_heads: None,
 		};
 
-		let future = SideToMainSignatures::new(&raw_log, main_contract, side_contract);
+		let future = SideToMainSignatures::new(
+			&raw_log,
+			main_contract,
+			side_contract,
+			&Journal::in_memory(),
+		);
 
 		let mut event_loop = Core::new().unwrap();
 		let result = event_loop.run(future).unwrap();
@@ -396,7 +480,7 @@ mod tests {
 			block_hash: None,
 			block_number: None,
 			transaction_index: None,
-			log_index: None,
+			log_index: Some(0.into()),
 			transaction_log_index: None,
 			log_type: None,
 			removed: None,
@@ -434,7 +518,18 @@ mod tests {
 			request_timeout: ::std::time::Duration::from_millis(0),
 			logs_poll_interval: ::std::time::Duration::from_millis(0),
 			required_log_confirmations: 0,
-			submit_collected_signatures_gas: 0xfd.into(),
+			submit_collected_signatures_gas: GasLimit::Fixed(0xfd.into()),
+			submit_collected_signatures_gas_price: GasPriceOracle::fixed(0xa0.into()),
+			signer: None,
+			nonce_manager: NonceManager::new(
+				main_transport.clone(),
+				authority_address,
+				::std::time::Duration::from_millis(0),
+			),
+			resubmit_after: ::std::time::Duration::from_millis(0),
+			gas_price_bump_percent: 0,
+			max_log_block_range: None,
+			new_heads: None,
 		};
 
 		let side_contract = SideContract {
@@ -445,13 +540,28 @@ mod tests {
 			request_timeout: ::std::time::Duration::from_millis(0),
 			logs_poll_interval: ::std::time::Duration::from_millis(0),
 			required_log_confirmations: 0,
-			sign_main_to_side_gas: 0.into(),
-			sign_main_to_side_gas_price: 0.into(),
-			sign_side_to_main_gas: 0xfd.into(),
-			sign_side_to_main_gas_price: 0xa0.into(),
+			sign_main_to_side_gas: GasLimit::Fixed(0.into()),
+			sign_main_to_side_gas_price: GasPriceOracle::fixed(0.into()),
+			sign_side_to_main_gas: GasLimit::Fixed(0xfd.into()),
+			sign_side_to_main_gas_price: GasPriceOracle::fixed(0xa0.into()),
+			signer: None,
+			nonce_manager: NonceManager::new(
+				side_transport.clone(),
+				authority_address,
+				::std::time::Duration::from_millis(0),
+			),
+			resubmit_after: ::std::time::Duration::from_millis(0),
+			gas_price_bump_percent: 0,
+			max_log_block_range: None,
+			new_heads: None,
 		};
 
-		let future = SideToMainSignatures::new(&raw_log, main_contract, side_contract);
+		let future = SideToMainSignatures::new(
+			&raw_log,
+			main_contract,
+			side_contract,
+			&Journal::in_memory(),
+		);
 
 		let mut event_loop = Core::new().unwrap();
 		let result = event_loop.run(future).unwrap();
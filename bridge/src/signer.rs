@@ -0,0 +1,381 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+// This file is part of Parity-Bridge.
+
+// Parity-Bridge is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity-Bridge is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity-Bridge.  If not, see <http://www.gnu.org/licenses/>.
+
+//! local signing of messages and transactions with an authority key
+//! loaded from an encrypted json keystore file.
+//! removes the need for an unlocked account on the parity nodes.
+
+use config::KeystoreConfig;
+use error::{Error, ErrorKind, ResultExt};
+use ethereum_types::{Address, H256, U256};
+use ethsign::{KeyFile, Protected, SecretKey};
+use rlp::RlpStream;
+use serde_json;
+use signature::Signature;
+use std::fs;
+use std::io::Read;
+use std::sync::Arc;
+use tiny_keccak::{self, Hasher};
+use web3::types::Bytes;
+
+/// EIP-2718 type of EIP-1559 transactions
+const EIP1559_TRANSACTION_TYPE: u8 = 2;
+
+fn keccak256(bytes: &[u8]) -> H256 {
+	let mut output = [0u8; 32];
+	let mut keccak = tiny_keccak::Keccak::v256();
+	keccak.update(bytes);
+	keccak.finalize(&mut output);
+	H256::from_slice(&output)
+}
+
+/// an authority key decrypted from a json keystore file
+#[derive(Clone)]
+pub struct Key {
+	secret: Arc<SecretKey>,
+	address: Address,
+}
+
+impl Key {
+	/// decrypts the keystore file at `config.path` with the password
+	/// read from the file at `config.password`
+	pub fn load(config: &KeystoreConfig) -> Result<Self, Error> {
+		let keystore_file = fs::File::open(&config.path).chain_err(|| {
+			format!(
+				"Cannot open keystore file at {}",
+				config.path.to_string_lossy()
+			)
+		})?;
+		let key_file: KeyFile =
+			serde_json::from_reader(keystore_file).chain_err(|| "Cannot parse keystore file")?;
+
+		let mut password = String::new();
+		fs::File::open(&config.password)
+			.chain_err(|| {
+				format!(
+					"Cannot open password file at {}",
+					config.password.to_string_lossy()
+				)
+			})?
+			.read_to_string(&mut password)?;
+		// password files written by editors usually end with a newline
+		let password: Protected = password.trim_end_matches(&['\r', '\n'][..]).into();
+
+		let secret = key_file
+			.to_secret_key(&password)
+			.map_err(|err| ErrorKind::Signing(format!("{:?}", err)))
+			.chain_err(|| "Cannot decrypt keystore file. is the password correct?")?;
+
+		Ok(Self::from_secret_key(secret))
+	}
+
+	pub fn from_secret_key(secret: SecretKey) -> Self {
+		let address = Address::from_slice(secret.public().address());
+		Self {
+			secret: Arc::new(secret),
+			address,
+		}
+	}
+
+	pub fn address(&self) -> Address {
+		self.address
+	}
+
+	/// signs `message` the same way `eth_sign` does:
+	/// `keccak256("\x19Ethereum Signed Message:\n" + len(message) + message)`.
+	/// `v` of the returned signature is `27` or `28`.
+	pub fn sign_message(&self, message: &[u8]) -> Result<Signature, Error> {
+		let mut prefixed = format!("\x19Ethereum Signed Message:\n{}", message.len()).into_bytes();
+		prefixed.extend_from_slice(message);
+		let (v, r, s) = self.sign_hash(keccak256(&prefixed))?;
+		Ok(Signature { v: v + 27, r, s })
+	}
+
+	/// returns `(recovery_id, r, s)`
+	fn sign_hash(&self, hash: H256) -> Result<(u8, H256, H256), Error> {
+		let signature = self
+			.secret
+			.sign(hash.as_bytes())
+			.map_err(|err| ErrorKind::Signing(format!("{:?}", err)))?;
+		Ok((
+			signature.v,
+			H256::from_slice(&signature.r),
+			H256::from_slice(&signature.s),
+		))
+	}
+}
+
+/// how a transaction pays for its gas
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Fees {
+	/// legacy transaction with a single gas price
+	Legacy { gas_price: U256 },
+	/// EIP-1559 (type 2) transaction for chains with a base fee
+	Eip1559 {
+		max_fee_per_gas: U256,
+		max_priority_fee_per_gas: U256,
+	},
+}
+
+/// a transaction that has everything needed to be signed by a `Signer`
+#[derive(Debug, PartialEq, Clone)]
+pub struct UnsignedTransaction {
+	pub nonce: U256,
+	pub fees: Fees,
+	pub gas: U256,
+	/// `None` for contract creations
+	pub to: Option<Address>,
+	pub value: U256,
+	pub data: Vec<u8>,
+}
+
+impl UnsignedTransaction {
+	fn rlp_append_to_value_data(&self, stream: &mut RlpStream) {
+		match self.to {
+			Some(ref to) => stream.append(to),
+			None => stream.append_empty_data(),
+		};
+		stream.append(&self.value);
+		stream.append(&self.data);
+	}
+
+	fn rlp_append_legacy(&self, stream: &mut RlpStream, gas_price: U256) {
+		stream.append(&self.nonce);
+		stream.append(&gas_price);
+		stream.append(&self.gas);
+		self.rlp_append_to_value_data(stream);
+	}
+
+	fn rlp_append_eip1559(
+		&self,
+		stream: &mut RlpStream,
+		chain_id: u64,
+		max_fee_per_gas: U256,
+		max_priority_fee_per_gas: U256,
+	) {
+		stream.append(&chain_id);
+		stream.append(&self.nonce);
+		stream.append(&max_priority_fee_per_gas);
+		stream.append(&max_fee_per_gas);
+		stream.append(&self.gas);
+		self.rlp_append_to_value_data(stream);
+		// empty access list
+		stream.begin_list(0);
+	}
+}
+
+/// signs messages and transactions for a single chain with `key`.
+/// transactions are signed with EIP-155 replay protection for `chain_id`.
+#[derive(Clone)]
+pub struct Signer {
+	key: Key,
+	chain_id: u64,
+}
+
+impl Signer {
+	pub fn new(key: Key, chain_id: u64) -> Self {
+		Self { key, chain_id }
+	}
+
+	pub fn address(&self) -> Address {
+		self.key.address()
+	}
+
+	pub fn chain_id(&self) -> u64 {
+		self.chain_id
+	}
+
+	/// see `Key::sign_message`
+	pub fn sign_message(&self, message: &[u8]) -> Result<Signature, Error> {
+		self.key.sign_message(message)
+	}
+
+	/// returns the encoded signed transaction.
+	/// ready to be submitted via `eth_sendRawTransaction`.
+	/// `Fees::Eip1559` transactions are wrapped in a type 2 envelope.
+	pub fn sign_transaction(&self, transaction: &UnsignedTransaction) -> Result<Bytes, Error> {
+		match transaction.fees {
+			Fees::Legacy { gas_price } => self.sign_legacy_transaction(transaction, gas_price),
+			Fees::Eip1559 {
+				max_fee_per_gas,
+				max_priority_fee_per_gas,
+			} => self.sign_eip1559_transaction(
+				transaction,
+				max_fee_per_gas,
+				max_priority_fee_per_gas,
+			),
+		}
+	}
+
+	fn sign_legacy_transaction(
+		&self,
+		transaction: &UnsignedTransaction,
+		gas_price: U256,
+	) -> Result<Bytes, Error> {
+		let mut stream = RlpStream::new_list(9);
+		transaction.rlp_append_legacy(&mut stream, gas_price);
+		stream.append(&self.chain_id);
+		stream.append(&0u8);
+		stream.append(&0u8);
+		let hash = keccak256(&stream.out());
+
+		let (recovery_id, r, s) = self.key.sign_hash(hash)?;
+
+		let mut stream = RlpStream::new_list(9);
+		transaction.rlp_append_legacy(&mut stream, gas_price);
+		stream.append(&(self.chain_id * 2 + 35 + recovery_id as u64));
+		stream.append(&U256::from_big_endian(r.as_bytes()));
+		stream.append(&U256::from_big_endian(s.as_bytes()));
+		Ok(Bytes(stream.out()))
+	}
+
+	fn sign_eip1559_transaction(
+		&self,
+		transaction: &UnsignedTransaction,
+		max_fee_per_gas: U256,
+		max_priority_fee_per_gas: U256,
+	) -> Result<Bytes, Error> {
+		let mut stream = RlpStream::new_list(9);
+		transaction.rlp_append_eip1559(
+			&mut stream,
+			self.chain_id,
+			max_fee_per_gas,
+			max_priority_fee_per_gas,
+		);
+		let mut payload = vec![EIP1559_TRANSACTION_TYPE];
+		payload.extend_from_slice(&stream.out());
+		let hash = keccak256(&payload);
+
+		let (recovery_id, r, s) = self.key.sign_hash(hash)?;
+
+		let mut stream = RlpStream::new_list(12);
+		transaction.rlp_append_eip1559(
+			&mut stream,
+			self.chain_id,
+			max_fee_per_gas,
+			max_priority_fee_per_gas,
+		);
+		stream.append(&recovery_id);
+		stream.append(&U256::from_big_endian(r.as_bytes()));
+		stream.append(&U256::from_big_endian(s.as_bytes()));
+		let mut signed = vec![EIP1559_TRANSACTION_TYPE];
+		signed.extend_from_slice(&stream.out());
+		Ok(Bytes(signed))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use ethsign;
+	use rlp::Rlp;
+	use rustc_hex::{FromHex, ToHex};
+
+	fn eip155_example_key() -> Key {
+		let secret: Vec<u8> = "4646464646464646464646464646464646464646464646464646464646464646"
+			.from_hex()
+			.unwrap();
+		Key::from_secret_key(SecretKey::from_raw(&secret).unwrap())
+	}
+
+	#[test]
+	fn test_key_address() {
+		assert_eq!(
+			eip155_example_key().address(),
+			"9d8a62f656a8d1615c1294fd71e9cfb3e4855a4f".parse().unwrap()
+		);
+	}
+
+	#[test]
+	fn test_sign_transaction_eip155_example() {
+		// https://github.com/ethereum/EIPs/blob/master/EIPS/eip-155.md#example
+		let signer = Signer::new(eip155_example_key(), 1);
+		let transaction = UnsignedTransaction {
+			nonce: 9.into(),
+			fees: Fees::Legacy {
+				gas_price: 20_000_000_000u64.into(),
+			},
+			gas: 21000.into(),
+			to: Some("3535353535353535353535353535353535353535".parse().unwrap()),
+			value: 1_000_000_000_000_000_000u64.into(),
+			data: vec![],
+		};
+
+		let signed = signer.sign_transaction(&transaction).unwrap();
+		assert_eq!(
+			signed.0.to_hex::<String>(),
+			"f86c098504a817c800825208943535353535353535353535353535353535353535880de0b6b3a76400008025a028ef61340bd939bc2195fe537567866003e1a15d3c71ff63e1590620aa636276a067cbe9d8997f761aecb703304b3800ccf555c9f3dc64214b297fb1966a3b6d83"
+		);
+	}
+
+	#[test]
+	fn test_sign_transaction_eip1559() {
+		let key = eip155_example_key();
+		let signer = Signer::new(key.clone(), 1);
+		let transaction = UnsignedTransaction {
+			nonce: 9.into(),
+			fees: Fees::Eip1559 {
+				max_fee_per_gas: 30_000_000_000u64.into(),
+				max_priority_fee_per_gas: 2_000_000_000u64.into(),
+			},
+			gas: 21000.into(),
+			to: Some("3535353535353535353535353535353535353535".parse().unwrap()),
+			value: 1_000_000_000_000_000_000u64.into(),
+			data: vec![],
+		};
+
+		let signed = signer.sign_transaction(&transaction).unwrap();
+		assert_eq!(signed.0[0], EIP1559_TRANSACTION_TYPE);
+
+		let rlp = Rlp::new(&signed.0[1..]);
+		assert_eq!(rlp.item_count().unwrap(), 12);
+		assert_eq!(rlp.val_at::<u64>(0).unwrap(), 1);
+		assert_eq!(rlp.val_at::<U256>(1).unwrap(), 9.into());
+		assert_eq!(rlp.val_at::<U256>(2).unwrap(), 2_000_000_000u64.into());
+		assert_eq!(rlp.val_at::<U256>(3).unwrap(), 30_000_000_000u64.into());
+		assert_eq!(rlp.at(8).unwrap().item_count().unwrap(), 0);
+
+		// the signature must recover to the address of the key
+		let mut stream = RlpStream::new_list(9);
+		transaction.rlp_append_eip1559(
+			&mut stream,
+			1,
+			30_000_000_000u64.into(),
+			2_000_000_000u64.into(),
+		);
+		let mut payload = vec![EIP1559_TRANSACTION_TYPE];
+		payload.extend_from_slice(&stream.out());
+		let mut signature = ethsign::Signature {
+			v: rlp.val_at(9).unwrap(),
+			r: [0u8; 32],
+			s: [0u8; 32],
+		};
+		rlp.val_at::<U256>(10)
+			.unwrap()
+			.to_big_endian(&mut signature.r);
+		rlp.val_at::<U256>(11)
+			.unwrap()
+			.to_big_endian(&mut signature.s);
+		let public = signature.recover(keccak256(&payload).as_bytes()).unwrap();
+		assert_eq!(Address::from_slice(public.address()), key.address());
+	}
+
+	#[test]
+	fn test_sign_message_has_eth_sign_v() {
+		let signature = eip155_example_key().sign_message(b"message").unwrap();
+		assert!(signature.v == 27 || signature.v == 28);
+	}
+}
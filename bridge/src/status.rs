@@ -0,0 +1,193 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+// This file is part of Parity-Bridge.
+
+// Parity-Bridge is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity-Bridge is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity-Bridge.  If not, see <http://www.gnu.org/licenses/>.
+
+//! snapshot of a bridge node for `parity-bridge status`:
+//! chain heads, authority balances, contract checks and how far
+//! each relay lags behind the last confirmed block of the chain it watches.
+
+use database::State;
+use error;
+use futures::Future;
+use main_contract::MainContract;
+use side_contract::SideContract;
+use std::fmt;
+use std::time::Duration;
+use tokio_timer::Timer;
+use web3;
+use web3::types::{Address, U256};
+use web3::Transport;
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct ChainStatus {
+	/// number of the latest block
+	pub head: u64,
+	/// balance of the authority in wei
+	pub balance: U256,
+	/// whether the configured contract answers `is_*_bridge_contract` with `true`
+	pub is_bridge_contract: bool,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct RelayProgress {
+	/// number of the last block that has been checked for this relay
+	pub last_checked_block: u64,
+	/// blocks between `last_checked_block` and the last block with the
+	/// required confirmations. the bridge doesn't check blocks after it
+	pub lag: u64,
+}
+
+impl RelayProgress {
+	fn new(last_checked_block: u64, head: u64, required_confirmations: u32) -> Self {
+		Self {
+			last_checked_block,
+			lag: head
+				.saturating_sub(u64::from(required_confirmations))
+				.saturating_sub(last_checked_block),
+		}
+	}
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Status {
+	pub main: ChainStatus,
+	pub side: ChainStatus,
+	pub main_to_side_sign: RelayProgress,
+	pub side_to_main_sign: RelayProgress,
+	pub side_to_main_signatures: RelayProgress,
+}
+
+impl Status {
+	/// returns a `Future` that queries both chains and resolves with the status
+	/// of a bridge node whose database contains `state`
+	pub fn query<T: Transport>(
+		main: &MainContract<T>,
+		side: &SideContract<T>,
+		state: &State,
+	) -> impl Future<Item = Self, Error = error::Error> {
+		let main_status = chain_status(
+			&main.transport,
+			main.authority_address,
+			main.request_timeout,
+			main.is_main_contract(),
+		);
+		let side_status = chain_status(
+			&side.transport,
+			side.authority_address,
+			side.request_timeout,
+			side.is_side_contract(),
+		);
+		let last_main_to_side_sign = state.last_main_to_side_sign_at_block;
+		let last_side_to_main_sign = state.last_side_to_main_sign_at_block;
+		let last_side_to_main_signatures = state.last_side_to_main_signatures_at_block;
+		let main_confirmations = main.required_log_confirmations;
+		let side_confirmations = side.required_log_confirmations;
+		main_status.join(side_status).map(move |(main, side)| Self {
+			main_to_side_sign: RelayProgress::new(
+				last_main_to_side_sign,
+				main.head,
+				main_confirmations,
+			),
+			side_to_main_sign: RelayProgress::new(
+				last_side_to_main_sign,
+				side.head,
+				side_confirmations,
+			),
+			side_to_main_signatures: RelayProgress::new(
+				last_side_to_main_signatures,
+				side.head,
+				side_confirmations,
+			),
+			main,
+			side,
+		})
+	}
+}
+
+fn chain_status<T: Transport, F>(
+	transport: &T,
+	authority_address: Address,
+	request_timeout: Duration,
+	is_bridge_contract: F,
+) -> impl Future<Item = ChainStatus, Error = error::Error>
+where
+	F: Future<Item = bool, Error = error::Error>,
+{
+	let eth = web3::api::Eth::new(transport.clone());
+	let timer = Timer::default();
+	let head = timer.timeout(eth.block_number().from_err(), request_timeout);
+	let balance = timer.timeout(
+		eth.balance(authority_address, None).from_err(),
+		request_timeout,
+	);
+	// a failing call means there is no bridge contract at the address
+	let is_bridge_contract =
+		is_bridge_contract.then(|result| Ok::<_, error::Error>(result.unwrap_or(false)));
+	head.join3(balance, is_bridge_contract)
+		.map(|(head, balance, is_bridge_contract)| ChainStatus {
+			head: head.as_u64(),
+			balance,
+			is_bridge_contract,
+		})
+}
+
+impl fmt::Display for Status {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		writeln!(f, "main:")?;
+		writeln!(f, "  head: {}", self.main.head)?;
+		writeln!(f, "  authority balance: {} wei", self.main.balance)?;
+		writeln!(
+			f,
+			"  is_main_bridge_contract: {}",
+			self.main.is_bridge_contract
+		)?;
+		writeln!(f, "side:")?;
+		writeln!(f, "  head: {}", self.side.head)?;
+		writeln!(f, "  authority balance: {} wei", self.side.balance)?;
+		writeln!(
+			f,
+			"  is_side_bridge_contract: {}",
+			self.side.is_bridge_contract
+		)?;
+		writeln!(f, "relays:")?;
+		for &(name, progress) in &[
+			("main to side sign", &self.main_to_side_sign),
+			("side to main sign", &self.side_to_main_sign),
+			("side to main signatures", &self.side_to_main_signatures),
+		] {
+			writeln!(
+				f,
+				"  {}: checked up to block {} ({} blocks behind)",
+				name, progress.last_checked_block, progress.lag
+			)?;
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_relay_progress_lag() {
+		assert_eq!(RelayProgress::new(90, 100, 0).lag, 10);
+		// blocks without the required confirmations are not behind yet
+		assert_eq!(RelayProgress::new(90, 100, 12).lag, 0);
+		assert_eq!(RelayProgress::new(80, 100, 12).lag, 8);
+		// the cursor can be ahead of a node that is still syncing
+		assert_eq!(RelayProgress::new(110, 100, 12).lag, 0);
+	}
+}
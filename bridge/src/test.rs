@@ -22,6 +22,7 @@ use serde_json;
 use std::cell::RefCell;
 use std::rc::Rc;
 use web3;
+use web3::rpc;
 use web3::Transport;
 
 #[derive(Debug, Clone, PartialEq)]
@@ -39,13 +40,27 @@ impl From<(&'static str, serde_json::Value)> for RequestData {
 	}
 }
 
+/// response of a `MockTransport` to a request
+#[derive(Debug, Clone)]
+pub enum MockResponse {
+	Result(serde_json::Value),
+	/// the node rejects the request with this message
+	RpcError(String),
+}
+
+impl From<serde_json::Value> for MockResponse {
+	fn from(value: serde_json::Value) -> Self {
+		MockResponse::Result(value)
+	}
+}
+
 /// a `Transport` that and will return the specified responses
 /// `clone`d versions have the same storage
 #[derive(Debug, Clone)]
 pub struct MockTransport {
 	pub expected_requests: Vec<RequestData>,
 	pub actual_requests: Rc<RefCell<Vec<RequestData>>>,
-	pub mock_responses: Vec<serde_json::Value>,
+	pub mock_responses: Vec<MockResponse>,
 }
 
 impl MockTransport {
@@ -103,8 +118,15 @@ impl Transport for MockTransport {
 			.iter()
 			.nth(current_request_index - 1)
 			.expect("missing response");
-		let f = futures::finished(response.clone());
-		Box::new(f)
+		let result = match *response {
+			MockResponse::Result(ref value) => Ok(value.clone()),
+			MockResponse::RpcError(ref message) => Err(web3::Error::Rpc(rpc::Error {
+				code: rpc::ErrorCode::ServerError(-32000),
+				message: message.clone(),
+				data: None,
+			})),
+		};
+		Box::new(futures::done(result))
 	}
 }
 
@@ -121,7 +143,7 @@ macro_rules! mock_transport {
 				.into_iter())
 				.map(Into::into)
 				.collect(),
-			mock_responses: vec![$($res),*],
+			mock_responses: vec![$($crate::test::MockResponse::from($res)),*],
 		}
 	}
 }
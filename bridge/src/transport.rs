@@ -0,0 +1,298 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+// This file is part of Parity-Bridge.
+
+// Parity-Bridge is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity-Bridge is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity-Bridge.  If not, see <http://www.gnu.org/licenses/>.
+
+//! transport to a node that fails over between its configured endpoints.
+//! requests go to the highest priority endpoint that is known to work.
+//! on connection errors and timeouts the next endpoint is used and the
+//! request is retried there. each endpoint gets an equal share of the
+//! `request_timeout` so that failing over fits into the timeout callers
+//! put around the request. while a lower priority endpoint is in use
+//! the first endpoint is health checked (`eth_blockNumber`) every
+//! `health_check_interval` and used again once it responds.
+//! in dry run mode transactions are logged instead of sent (see `dry_run`).
+
+use config::{NodeConfig, NodeEndpoint};
+use dry_run::DryRun;
+use error::{self, ResultExt};
+use futures::{future, Future};
+use metrics::Metrics;
+use std::cell::Cell;
+use std::fmt;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use tokio_core::reactor::Handle;
+use tokio_timer::Timer;
+use web3;
+use web3::helpers::build_request;
+use web3::transports::{Http, Ipc};
+use web3::{rpc, RequestId, Transport};
+
+type Response = Box<dyn Future<Item = rpc::Value, Error = web3::Error>>;
+
+/// connection to a single endpoint
+#[derive(Debug, Clone)]
+enum Connection {
+	Http(Http),
+	Ipc(Ipc),
+}
+
+impl Connection {
+	/// `max_parallel_requests` only applies to http
+	fn new(
+		endpoint: &NodeEndpoint,
+		handle: &Handle,
+		max_parallel_requests: usize,
+	) -> Result<Self, error::Error> {
+		let connection = match *endpoint {
+			NodeEndpoint::Http(ref url) => Connection::Http(
+				Http::with_event_loop(url, handle, max_parallel_requests)
+					.chain_err(|| format!("Cannot connect to {}", url))?,
+			),
+			NodeEndpoint::Ipc(ref path) => Connection::Ipc(
+				Ipc::with_event_loop(path, handle)
+					.chain_err(|| format!("Cannot connect to {}", path.display()))?,
+			),
+		};
+		Ok(connection)
+	}
+
+	fn send(&self, id: RequestId, request: rpc::Call) -> Response {
+		match *self {
+			Connection::Http(ref transport) => Box::new(transport.send(id, request)),
+			Connection::Ipc(ref transport) => Box::new(transport.send(id, request)),
+		}
+	}
+}
+
+/// whether `err` means that the endpoint (rather than the request) is the problem
+fn is_endpoint_error(err: &web3::Error) -> bool {
+	match *err {
+		web3::Error::Unreachable | web3::Error::Transport(_) | web3::Error::Io(_) => true,
+		_ => false,
+	}
+}
+
+/// `clone`d versions share the connections and the endpoint in use
+#[derive(Clone)]
+pub struct NodeTransport {
+	endpoints: Rc<Vec<(NodeEndpoint, Connection)>>,
+	/// index into `endpoints` of the endpoint in use
+	current: Rc<Cell<usize>>,
+	last_health_check: Rc<Cell<Instant>>,
+	/// ids are shared by all endpoints so retried requests keep theirs
+	next_id: Rc<Cell<RequestId>>,
+	/// time a single endpoint gets to respond
+	endpoint_timeout: Duration,
+	health_check_interval: Duration,
+	timer: Timer,
+	handle: Handle,
+	dry_run: Option<Rc<DryRun>>,
+	/// records request durations labelled with the chain
+	metrics: Option<(Metrics, &'static str)>,
+}
+
+impl NodeTransport {
+	/// connects to all endpoints of `config`.
+	/// `max_parallel_requests` only applies to http
+	pub fn new(
+		config: &NodeConfig,
+		handle: &Handle,
+		max_parallel_requests: usize,
+	) -> Result<Self, error::Error> {
+		assert!(
+			!config.endpoints.is_empty(),
+			"config ensures there is at least one endpoint; qed"
+		);
+		let endpoints = config
+			.endpoints
+			.iter()
+			.map(|endpoint| {
+				Connection::new(endpoint, handle, max_parallel_requests)
+					.map(|connection| (endpoint.clone(), connection))
+			})
+			.collect::<Result<Vec<_>, _>>()?;
+		Ok(Self {
+			endpoints: Rc::new(endpoints),
+			current: Rc::new(Cell::new(0)),
+			last_health_check: Rc::new(Cell::new(Instant::now())),
+			next_id: Rc::new(Cell::new(1)),
+			endpoint_timeout: config.request_timeout / config.endpoints.len() as u32,
+			health_check_interval: config.health_check_interval,
+			timer: Timer::default(),
+			handle: handle.clone(),
+			dry_run: None,
+			metrics: None,
+		})
+	}
+
+	/// logs transactions instead of sending them
+	pub fn dry_run(mut self) -> Self {
+		self.dry_run = Some(Rc::new(DryRun::default()));
+		self
+	}
+
+	/// records the duration of every request in `metrics` labelled with `chain`
+	pub fn with_metrics(mut self, metrics: Metrics, chain: &'static str) -> Self {
+		self.metrics = Some((metrics, chain));
+		self
+	}
+
+	/// the endpoint requests are currently sent to
+	pub fn current_endpoint(&self) -> &NodeEndpoint {
+		&self.endpoints[self.current.get()].0
+	}
+
+	/// fails `response` with a transport error after `endpoint_timeout`
+	fn with_timeout(&self, response: Response) -> Response {
+		let timeout =
+			self.timer
+				.sleep(self.endpoint_timeout)
+				.then(|_| -> Result<rpc::Value, web3::Error> {
+					Err(web3::Error::Transport("request timed out".into()))
+				});
+		Box::new(
+			response
+				.select(timeout)
+				.map(|(value, _)| value)
+				.map_err(|(err, _)| err),
+		)
+	}
+
+	/// sends `request` to the endpoint at `index`. fails over to the
+	/// following endpoints until `retries` is used up
+	fn send_to(&self, index: usize, retries: usize, id: RequestId, request: rpc::Call) -> Response {
+		let this = self.clone();
+		let response = self
+			.with_timeout(self.endpoints[index].1.send(id, request.clone()))
+			.or_else(move |err| -> Response {
+				if !is_endpoint_error(&err) {
+					return Box::new(future::err(err));
+				}
+				let next = this.failed(index, &err);
+				if retries == 0 {
+					return Box::new(future::err(err));
+				}
+				this.send_to(next, retries - 1, id, request)
+			});
+		Box::new(response)
+	}
+
+	/// moves on from the endpoint at `index` unless that already happened.
+	/// returns the index of the endpoint to use from now on
+	fn failed(&self, index: usize, err: &web3::Error) -> usize {
+		if self.current.get() == index {
+			let next = (index + 1) % self.endpoints.len();
+			warn!(
+				"NodeTransport: endpoint {} failed ({:?}), switching to {}",
+				self.endpoints[index].0, err, self.endpoints[next].0
+			);
+			self.current.set(next);
+			self.last_health_check.set(Instant::now());
+		}
+		self.current.get()
+	}
+
+	/// starts a health check of the first endpoint if a lower
+	/// priority one is in use and the last check is long enough ago
+	fn check_health(&self) {
+		if self.current.get() == 0
+			|| self.last_health_check.get().elapsed() < self.health_check_interval
+		{
+			return;
+		}
+		self.last_health_check.set(Instant::now());
+
+		let (id, request) = self.prepare("eth_blockNumber", vec![]);
+		let current = self.current.clone();
+		let primary = self.endpoints[0].0.clone();
+		let health_check = self
+			.with_timeout(self.endpoints[0].1.send(id, request))
+			.then(move |result| {
+				match result {
+					Ok(_) => {
+						info!("NodeTransport: endpoint {} is healthy again", primary);
+						current.set(0);
+					}
+					Err(err) => {
+						debug!(
+							"NodeTransport: endpoint {} still failing: {:?}",
+							primary, err
+						)
+					}
+				}
+				Ok(())
+			});
+		self.handle.spawn(health_check);
+	}
+}
+
+impl fmt::Debug for NodeTransport {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("NodeTransport")
+			.field(
+				"endpoints",
+				&self
+					.endpoints
+					.iter()
+					.map(|&(ref endpoint, _)| endpoint.to_string())
+					.collect::<Vec<_>>(),
+			)
+			.field("current", &self.current.get())
+			.field("dry_run", &self.dry_run.is_some())
+			.field("metrics", &self.metrics.as_ref().map(|&(_, chain)| chain))
+			.finish()
+	}
+}
+
+impl Transport for NodeTransport {
+	type Out = Response;
+
+	fn prepare(&self, method: &str, params: Vec<rpc::Value>) -> (RequestId, rpc::Call) {
+		let id = self.next_id.get();
+		self.next_id.set(id + 1);
+		(id, build_request(id, method, params))
+	}
+
+	fn send(&self, id: RequestId, request: rpc::Call) -> Self::Out {
+		if let Some(ref dry_run) = self.dry_run {
+			if let Some(response) = dry_run.intercept(&request) {
+				return Box::new(future::ok(response));
+			}
+		}
+		self.check_health();
+		let retries = self.endpoints.len() - 1;
+		let (metrics, chain) = match self.metrics {
+			Some((ref metrics, chain)) => (metrics.clone(), chain),
+			None => return self.send_to(self.current.get(), retries, id, request),
+		};
+		let method = match request {
+			rpc::Call::MethodCall(ref call) => call.method.clone(),
+			_ => "unknown".to_owned(),
+		};
+		let started = Instant::now();
+		let response = self
+			.send_to(self.current.get(), retries, id, request)
+			.then(move |result| {
+				let reachable = match result {
+					Ok(_) => true,
+					Err(ref err) => !is_endpoint_error(err),
+				};
+				metrics.rpc_request(chain, &method, started.elapsed(), reachable);
+				result
+			});
+		Box::new(response)
+	}
+}
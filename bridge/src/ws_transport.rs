@@ -0,0 +1,102 @@
+// Copyright 2017 Parity Technologies (UK) Ltd.
+// This file is part of Parity-Bridge.
+
+// Parity-Bridge is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity-Bridge is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity-Bridge.  If not, see <http://www.gnu.org/licenses/>.
+
+//! websocket connection to a node used for subscriptions (`eth_subscribe`).
+//! unlike the plain web3 `WebSocket` it can be reconnected once it broke.
+//! all subscriptions of a connection end when it is replaced so every
+//! subscriber has to subscribe again. a broken connection is only replaced once
+//! no matter how many of its subscribers report it.
+
+use error;
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::rc::Rc;
+use tokio_core::reactor::Handle;
+use web3::api::SubscriptionId;
+use web3::transports::WebSocket;
+use web3::{rpc, DuplexTransport, RequestId, Transport};
+
+/// `clone`d versions share the same connection
+#[derive(Clone)]
+pub struct WsTransport {
+	url: String,
+	handle: Handle,
+	socket: Rc<RefCell<WebSocket>>,
+	/// incremented whenever `socket` is replaced
+	generation: Rc<Cell<u64>>,
+}
+
+impl WsTransport {
+	pub fn new(url: &str, handle: &Handle) -> Result<Self, error::Error> {
+		let socket = WebSocket::with_event_loop(url, handle)?;
+		Ok(Self {
+			url: url.to_owned(),
+			handle: handle.clone(),
+			socket: Rc::new(RefCell::new(socket)),
+			generation: Rc::new(Cell::new(0)),
+		})
+	}
+
+	/// identifies the current connection. subscriptions made now belong to it
+	pub fn generation(&self) -> u64 {
+		self.generation.get()
+	}
+
+	/// replaces the connection `generation` once it broke.
+	/// does nothing if it has been replaced already
+	pub fn reconnect(&self, generation: u64) -> Result<(), error::Error> {
+		if self.generation.get() != generation {
+			return Ok(());
+		}
+		info!("WsTransport: reconnecting to {}", self.url);
+		let socket = WebSocket::with_event_loop(&self.url, &self.handle)?;
+		*self.socket.borrow_mut() = socket;
+		self.generation.set(generation + 1);
+		Ok(())
+	}
+}
+
+impl fmt::Debug for WsTransport {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("WsTransport")
+			.field("url", &self.url)
+			.finish()
+	}
+}
+
+impl Transport for WsTransport {
+	type Out = <WebSocket as Transport>::Out;
+
+	fn prepare(&self, method: &str, params: Vec<rpc::Value>) -> (RequestId, rpc::Call) {
+		self.socket.borrow().prepare(method, params)
+	}
+
+	fn send(&self, id: RequestId, request: rpc::Call) -> Self::Out {
+		self.socket.borrow().send(id, request)
+	}
+}
+
+impl DuplexTransport for WsTransport {
+	type NotificationStream = <WebSocket as DuplexTransport>::NotificationStream;
+
+	fn subscribe(&self, id: &SubscriptionId) -> Self::NotificationStream {
+		self.socket.borrow().subscribe(id)
+	}
+
+	fn unsubscribe(&self, id: &SubscriptionId) {
+		self.socket.borrow().unsubscribe(id)
+	}
+}
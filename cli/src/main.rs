@@ -22,36 +22,129 @@ extern crate log;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+extern crate serde_json;
 extern crate tokio_core;
 extern crate web3;
 
 use docopt::Docopt;
 use futures::Stream;
 use std::env;
-use std::path::PathBuf;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process;
 use tokio_core::reactor::Core;
-use web3::transports::http::Http;
+use web3::api::Namespace;
+use web3::types::H256;
 
-use bridge::config::Config;
-use bridge::database::{Database, TomlFileDatabase};
+use bridge::config::{Config, NodeConfig};
+use bridge::database::{self as db, Database, SledDatabase, TomlFileDatabase};
 use bridge::error::{self, ResultExt};
 use bridge::helpers::StreamExt;
+use bridge::journal::{Journal, RelayKind};
+use bridge::log_format::{self, LogFormat};
+use bridge::manual_relay as relay;
+use bridge::metrics::{Metrics, Readiness};
+use bridge::signer::{Key, Signer};
+use bridge::{NodeTransport, Status};
 
 const MAX_PARALLEL_REQUESTS: usize = 10;
 
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DatabaseBackend {
+	Toml,
+	Sled,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Relay {
+	MainToSide,
+	SideToMainSign,
+	SideToMainSignatures,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Args {
+	cmd_import_toml_database: bool,
+	cmd_journal: bool,
+	cmd_relay: bool,
+	cmd_replay: bool,
+	cmd_status: bool,
 	arg_config: PathBuf,
 	arg_database: PathBuf,
+	arg_toml: PathBuf,
+	flag_database_backend: DatabaseBackend,
+	flag_journal: Option<PathBuf>,
+	flag_json: bool,
+	flag_log_format: LogFormat,
+	flag_once: bool,
+	flag_dry_run: bool,
+	flag_main_tx: Option<String>,
+	flag_side_tx: Option<String>,
+	flag_tx: Option<String>,
+	flag_message_id: Option<String>,
+	flag_relay: Option<Relay>,
+	flag_from: Option<u64>,
+	flag_to: Option<u64>,
+}
+
+/// the latest block on the chain of `node` with its `required_confirmations`
+fn confirmed_block(
+	event_loop: &mut Core,
+	transport: &NodeTransport,
+	node: &NodeConfig,
+) -> Result<u64, error::Error> {
+	let block = event_loop.run(web3::api::Eth::new(transport).block_number())?;
+	Ok(block
+		.as_u64()
+		.saturating_sub(node.required_confirmations as u64))
+}
+
+/// parses a transaction hash with or without `0x` prefix
+fn parse_tx_hash(tx_hash: &str) -> Result<H256, error::Error> {
+	let hex = tx_hash.trim_start_matches("0x");
+	hex.parse()
+		.map_err(|_| format!("invalid transaction hash {:?}", tx_hash).into())
+}
+
+/// parses a message id with or without `0x` prefix
+fn parse_message_id(message_id: &str) -> Result<H256, error::Error> {
+	let hex = message_id.trim_start_matches("0x");
+	hex.parse()
+		.map_err(|_| format!("invalid message id {:?}", message_id).into())
+}
+
+/// the journal file that belongs to the toml database at `database`
+fn journal_path(database: &Path, journal: &Option<PathBuf>) -> PathBuf {
+	journal.clone().unwrap_or_else(|| {
+		let mut path = database.as_os_str().to_owned();
+		path.push(".journal");
+		path.into()
+	})
+}
+
+/// logs to stderr in `format`. the level is set through `RUST_LOG` as usual
+fn init_logger(format: LogFormat) {
+	let mut builder = env_logger::Builder::from_default_env();
+	if format == LogFormat::Json {
+		builder.format(|buf, record| {
+			let timestamp = buf.timestamp().to_string();
+			writeln!(buf, "{}", log_format::json_record(&timestamp, record))
+		});
+	}
+	let _ = builder.try_init();
 }
 
 fn main() {
-	let _ = env_logger::init();
 	let result = execute(env::args());
 
 	match result {
 		Ok(s) => println!("{}", s),
-		Err(err) => print_err(err),
+		Err(err) => {
+			print_err(err);
+			process::exit(1);
+		}
 	}
 }
 
@@ -77,20 +170,100 @@ Parity-bridge
 	Commit: {}
 
 Usage:
-	parity-bridge --config <config> --database <database>
+	parity-bridge --config <config> --database <database> [--database-backend <backend>] [--journal <journal>] [--once] [--dry-run] [--log-format <format>]
+	parity-bridge import-toml-database <toml> --database <database> [--journal <journal>] [--log-format <format>]
+	parity-bridge relay --config <config> --database <database> [--database-backend <backend>] [--journal <journal>] [--dry-run] [--log-format <format>] (--main-tx <hash> | --side-tx <hash>)
+	parity-bridge replay --config <config> --database <database> [--database-backend <backend>] [--journal <journal>] [--dry-run] [--log-format <format>] --relay <relay> --from <block> --to <block>
+	parity-bridge status --config <config> --database <database> [--database-backend <backend>] [--json] [--log-format <format>]
+	parity-bridge journal --database <database> [--database-backend <backend>] [--journal <journal>] [--log-format <format>] (--tx <hash> | --message-id <id>)
 	parity-bridge -h | --help
 
 Options:
-	-h, --help           Display help message and exit.
+	-h, --help                    Display help message and exit.
+	--database-backend <backend>  Database to use: `toml` (a file) or `sled` (an embedded
+	                              database that also holds the journal) [default: toml].
+	--journal <journal>           File every relay attempt is recorded in with the `toml`
+	                              backend. Defaults to the database path with `.journal`
+	                              appended.
+	--json                        Print the status as JSON.
+	--log-format <format>         Format of the log output on stderr: `text` or `json`
+	                              (one object per line with the fields of relay steps)
+	                              [default: text].
+	--once                        Relay everything confirmed up to the current heads of
+	                              both chains, persist the state and exit. Fails if
+	                              the relay of any message failed.
+	--dry-run                     Perform all checks but log transactions instead of
+	                              sending them.
+	--main-tx <hash>              Transaction on main whose messages to relay.
+	--side-tx <hash>              Transaction on side whose messages and collected
+	                              signatures to relay.
+	--relay <relay>               Relay to replay: `main-to-side`, `side-to-main-sign` or
+	                              `side-to-main-signatures`.
+	--from <block>                First block to replay.
+	--to <block>                  Last block to replay.
+	--tx <hash>                   Transaction that caused a relay or that is a relay
+	                              transaction.
+	--message-id <id>             Message whose relays to look up.
+
+Commands:
+	import-toml-database          Creates a `sled` database at <database> from the `toml`
+	                              database <toml> and its journal.
+	relay                         Relays the bridge logs of a single transaction unless
+	                              that already happened. The bridge must not be running.
+	replay                        Runs a relay again for the blocks <from> to <to> of the
+	                              chain it watches. Relays that already happened are
+	                              skipped and the database is left untouched. The bridge
+	                              must not be running.
+	status                        Prints the chain heads, how far each relay lags behind,
+	                              the authority's balances and whether the contracts in
+	                              the database are bridge contracts.
+	journal                       Prints the journal entries of the relays of a
+	                              transaction or message as JSON lines. With the `sled`
+	                              backend the bridge must not be running.
 "#,
 		env!("CARGO_PKG_VERSION"),
 		env!("GIT_HASH")
 	);
 
-	info!("Parsing cli arguments");
 	let args: Args = Docopt::new(usage)
 		.and_then(|d| d.argv(command).deserialize())
 		.map_err(|e| e.to_string())?;
+	init_logger(args.flag_log_format);
+
+	if args.cmd_import_toml_database {
+		let journal_path = journal_path(&args.arg_toml, &args.flag_journal);
+		info!(
+			"Importing {:?} with journal {:?} into {:?}",
+			args.arg_toml, journal_path, args.arg_database
+		);
+		let journal = Journal::read(&journal_path)?;
+		db::import_toml_database(&args.arg_toml, &journal, &args.arg_database)
+			.chain_err(|| format!("Cannot import {:?}", args.arg_toml))?;
+		return Ok("Done".into());
+	}
+
+	if args.cmd_journal {
+		// the running bridge only appends to the journal file so it is read without the lock
+		let journal = match args.flag_database_backend {
+			DatabaseBackend::Toml => {
+				Journal::read(journal_path(&args.arg_database, &args.flag_journal))?
+			}
+			DatabaseBackend::Sled => SledDatabase::from_path(&args.arg_database)?.journal()?,
+		};
+		let entries = match (args.flag_tx.as_ref(), args.flag_message_id.as_ref()) {
+			(Some(tx_hash), _) => journal.find(&parse_tx_hash(tx_hash)?),
+			(None, Some(message_id)) => journal.find_by_message_id(&parse_message_id(message_id)?),
+			(None, None) => unreachable!("docopt requires --tx or --message-id; qed"),
+		};
+		if entries.is_empty() {
+			return Err("no journal entries found".into());
+		}
+		let lines = entries
+			.iter()
+			.map(|entry| serde_json::to_string(entry).expect("serialization can't fail; qed"))
+			.collect::<Vec<_>>();
+		return Ok(lines.join("\n"));
+	}
 
 	info!("Loading config from {:?}", args.arg_config);
 	let config = Config::load(&args.arg_config)?;
@@ -99,44 +272,160 @@ Options:
 	let mut event_loop = Core::new().unwrap();
 
 	info!(
-		"Establishing HTTP connection to parity node connected to main chain at {:?}",
-		config.main.http
+		"Establishing connection to parity node connected to main chain at {}",
+		config.main.endpoints[0]
 	);
-	let main_transport = Http::with_event_loop(
-		&config.main.http,
-		&event_loop.handle(),
-		MAX_PARALLEL_REQUESTS,
-	)
-	.chain_err(|| {
-		format!(
-			"Cannot connect to parity node connected to main chain at {}",
-			config.main.http
-		)
-	})?;
+	let main_transport =
+		NodeTransport::new(&config.main, &event_loop.handle(), MAX_PARALLEL_REQUESTS)
+			.chain_err(|| "Cannot connect to parity node connected to main chain")?;
 
 	info!(
-		"Establishing HTTP connection to parity node connected to side chain at {:?}",
-		config.side.http
+		"Establishing connection to parity node connected to side chain at {}",
+		config.side.endpoints[0]
 	);
-	let side_transport = Http::with_event_loop(
-		&config.side.http,
-		&event_loop.handle(),
-		MAX_PARALLEL_REQUESTS,
-	)
-	.chain_err(|| {
-		format!(
-			"Cannot connect to parity node connected to side chain at {}",
-			config.side.http
-		)
-	})?;
+	let side_transport =
+		NodeTransport::new(&config.side, &event_loop.handle(), MAX_PARALLEL_REQUESTS)
+			.chain_err(|| "Cannot connect to parity node connected to side chain")?;
+
+	let (main_transport, side_transport) = if args.flag_dry_run {
+		info!("Dry run. transactions are logged instead of sent");
+		(main_transport.dry_run(), side_transport.dry_run())
+	} else {
+		(main_transport, side_transport)
+	};
+
+	// only the long running bridge serves metrics
+	let metrics = match config.metrics {
+		Some(_) if !(args.cmd_status || args.cmd_relay || args.cmd_replay) => Some(Metrics::new()?),
+		_ => None,
+	};
+	let (main_transport, side_transport) = match metrics {
+		Some(ref metrics) => (
+			main_transport.with_metrics(metrics.clone(), "main"),
+			side_transport.with_metrics(metrics.clone(), "side"),
+		),
+		None => (main_transport, side_transport),
+	};
+
+	if args.cmd_status {
+		// the running bridge holds the lock of the database so it is read without it
+		info!("Reading state from database {:?}", args.arg_database);
+		let state = match args.flag_database_backend {
+			DatabaseBackend::Toml => TomlFileDatabase::read_state(&args.arg_database)?,
+			DatabaseBackend::Sled => SledDatabase::read_state(&args.arg_database)?,
+		};
+		let main_contract = bridge::MainContract::new(main_transport, None, &config, &state, None);
+		let side_contract = bridge::SideContract::new(side_transport, None, &config, &state, None);
+		let status = event_loop
+			.run(Status::query(&main_contract, &side_contract, &state))
+			.chain_err(|| "Cannot query status")?;
+		if args.flag_json {
+			return serde_json::to_string_pretty(&status).map_err(|err| err.to_string().into());
+		}
+		return Ok(status.to_string());
+	}
+
+	let main_new_heads = match config.main.ws {
+		Some(ref ws) => {
+			info!(
+				"Establishing WebSocket connection to parity node connected to main chain at {:?}",
+				ws
+			);
+			let transport = bridge::WsTransport::new(ws, &event_loop.handle()).chain_err(|| {
+				format!(
+					"Cannot connect to parity node connected to main chain at {}",
+					ws
+				)
+			})?;
+			Some(transport)
+		}
+		None => None,
+	};
+
+	let side_new_heads = match config.side.ws {
+		Some(ref ws) => {
+			info!(
+				"Establishing WebSocket connection to parity node connected to side chain at {:?}",
+				ws
+			);
+			let transport = bridge::WsTransport::new(ws, &event_loop.handle()).chain_err(|| {
+				format!(
+					"Cannot connect to parity node connected to side chain at {}",
+					ws
+				)
+			})?;
+			Some(transport)
+		}
+		None => None,
+	};
 
 	info!("Loading database from {:?}", args.arg_database);
-	let mut database = TomlFileDatabase::from_path(&args.arg_database)?;
+	let (mut database, journal): (Box<dyn Database>, Journal) = match args.flag_database_backend {
+		DatabaseBackend::Toml => {
+			let database = TomlFileDatabase::from_path(&args.arg_database)?;
+			let journal_path = journal_path(&args.arg_database, &args.flag_journal);
+			info!("Opening relay journal {:?}", journal_path);
+			(Box::new(database), Journal::open(&journal_path)?)
+		}
+		DatabaseBackend::Sled => {
+			let database = SledDatabase::from_path(&args.arg_database)?;
+			let journal = database.journal()?;
+			(Box::new(database), journal)
+		}
+	};
+
+	let journal = journal.with_log_format(args.flag_log_format);
+	let journal = match metrics {
+		Some(ref metrics) => journal.with_metrics(metrics.clone()),
+		None => journal,
+	};
 
 	info!("Reading initial state from database");
 	let initial_state = database.read();
 
-	let main_contract = bridge::MainContract::new(main_transport.clone(), &config, &initial_state);
+	let key = match config.keystore {
+		Some(ref keystore) => {
+			info!("Loading authority key from keystore {:?}", keystore.path);
+			let key = Key::load(keystore)?;
+			if key.address() != config.address {
+				return Err(format!(
+					"keystore {:?} contains key for {:?} but `address` in config is {:?}",
+					keystore.path,
+					key.address(),
+					config.address
+				)
+				.into());
+			}
+			Some(key)
+		}
+		None => None,
+	};
+	let main_signer = key.clone().map(|key| {
+		Signer::new(
+			key,
+			config
+				.main
+				.chain_id
+				.expect("`Config::load` ensures `main.chain_id` is set if `keystore` is set; qed"),
+		)
+	});
+	let side_signer = key.map(|key| {
+		Signer::new(
+			key,
+			config
+				.side
+				.chain_id
+				.expect("`Config::load` ensures `side.chain_id` is set if `keystore` is set; qed"),
+		)
+	});
+
+	let main_contract = bridge::MainContract::new(
+		main_transport.clone(),
+		main_new_heads,
+		&config,
+		&initial_state,
+		main_signer,
+	);
 	event_loop
 		.run(main_contract.is_main_contract())
 		.chain_err(|| {
@@ -147,7 +436,13 @@ Options:
 		)
 		})?;
 
-	let side_contract = bridge::SideContract::new(side_transport.clone(), &config, &initial_state);
+	let side_contract = bridge::SideContract::new(
+		side_transport.clone(),
+		side_new_heads,
+		&config,
+		&initial_state,
+		side_signer,
+	);
 	event_loop
 		.run(side_contract.is_side_contract())
 		.chain_err(|| {
@@ -158,9 +453,133 @@ Options:
 		)
 		})?;
 
-	let bridge_stream = bridge::Bridge::new(initial_state, main_contract, side_contract);
+	info!("Fetching pending transaction counts to synchronise nonces");
+	event_loop
+		.run(main_contract.nonce_manager.sync())
+		.chain_err(|| "Cannot fetch pending transaction count on main chain")?;
+	event_loop
+		.run(side_contract.nonce_manager.sync())
+		.chain_err(|| "Cannot fetch pending transaction count on side chain")?;
+
+	if args.cmd_relay {
+		let relays = match (args.flag_main_tx, args.flag_side_tx) {
+			(Some(ref tx_hash), _) => {
+				let future = relay::relay_main_transaction(
+					main_contract,
+					side_contract,
+					journal,
+					parse_tx_hash(tx_hash)?,
+				);
+				event_loop.run(future)?
+			}
+			(None, Some(ref tx_hash)) => {
+				let future = relay::relay_side_transaction(
+					main_contract,
+					side_contract,
+					journal,
+					parse_tx_hash(tx_hash)?,
+				);
+				event_loop.run(future)?
+			}
+			(None, None) => unreachable!("docopt requires `--main-tx` or `--side-tx`; qed"),
+		};
+		return Ok(format!("Done. {} relays", relays));
+	}
+
+	if args.cmd_replay {
+		let relay = args.flag_relay.expect("docopt requires `--relay`; qed");
+		let from = args.flag_from.expect("docopt requires `--from`; qed");
+		let to = args.flag_to.expect("docopt requires `--to`; qed");
+		let (kind, confirmed) = match relay {
+			Relay::MainToSide => (
+				RelayKind::MainToSideSign,
+				confirmed_block(&mut event_loop, &main_transport, &config.main)
+					.chain_err(|| "Cannot fetch block number on main chain")?,
+			),
+			Relay::SideToMainSign => (
+				RelayKind::SideToMainSign,
+				confirmed_block(&mut event_loop, &side_transport, &config.side)
+					.chain_err(|| "Cannot fetch block number on side chain")?,
+			),
+			Relay::SideToMainSignatures => (
+				RelayKind::SideToMainSignatures,
+				confirmed_block(&mut event_loop, &side_transport, &config.side)
+					.chain_err(|| "Cannot fetch block number on side chain")?,
+			),
+		};
+		if to > confirmed {
+			return Err(format!(
+				"block {} is not confirmed yet. the last confirmed block is {}",
+				to, confirmed
+			)
+			.into());
+		}
+		info!("Replaying {:?} for blocks {} to {}", relay, from, to);
+		event_loop.run(bridge::replay(
+			kind,
+			from,
+			to,
+			main_contract,
+			side_contract,
+			journal,
+		))?;
+		return Ok(format!("Done. replayed blocks {} to {}", from, to));
+	}
+
+	let catch_up_to = if args.flag_once {
+		let main_block = confirmed_block(&mut event_loop, &main_transport, &config.main)
+			.chain_err(|| "Cannot fetch block number on main chain")?;
+		let side_block = confirmed_block(&mut event_loop, &side_transport, &config.side)
+			.chain_err(|| "Cannot fetch block number on side chain")?;
+		info!(
+			"Catching up with block {} on main and block {} on side",
+			main_block, side_block
+		);
+		Some((main_block, side_block))
+	} else {
+		None
+	};
+
+	let mut bridge_stream =
+		bridge::Bridge::new(initial_state, main_contract, side_contract, journal);
+	if let Some((main_block, side_block)) = catch_up_to {
+		bridge_stream = bridge_stream.until_caught_up(main_block, side_block);
+	}
+	if let (Some(metrics), Some(metrics_config)) = (metrics, config.metrics.as_ref()) {
+		info!(
+			"Serving metrics at http://{0}/metrics and health checks at http://{0}/health and http://{0}/ready",
+			metrics_config.address
+		);
+		let readiness = Readiness {
+			max_unreachable: metrics_config.ready_max_unreachable,
+			max_lag: metrics_config.ready_max_lag,
+		};
+		bridge::metrics::serve(
+			&metrics_config.address,
+			&event_loop.handle(),
+			metrics.clone(),
+			readiness,
+		)?;
+		event_loop.handle().spawn(metrics.watch_chain(
+			"main",
+			main_transport.clone(),
+			config.main.required_confirmations,
+			config.address,
+			config.main.request_timeout,
+			metrics_config.poll_interval,
+		));
+		event_loop.handle().spawn(metrics.watch_chain(
+			"side",
+			side_transport.clone(),
+			config.side.required_confirmations,
+			config.address,
+			config.side.request_timeout,
+			metrics_config.poll_interval,
+		));
+		bridge_stream = bridge_stream.with_metrics(metrics);
+	}
 	info!("Started polling logs");
-	let persisted_bridge_stream = bridge_stream.and_then(|state| {
+	let persisted_bridge_stream = bridge_stream.by_ref().and_then(|state| {
 		database.write(&state)?;
 		// info!("state change: {}", state);
 		Ok(())
@@ -168,5 +587,14 @@ Options:
 
 	event_loop.run(persisted_bridge_stream.last())?;
 
+	let skipped_messages = bridge_stream.skipped_messages();
+	if skipped_messages > 0 {
+		return Err(format!(
+			"{} messages were skipped because their relay failed. see the journal",
+			skipped_messages
+		)
+		.into());
+	}
+
 	Ok("Done".into())
 }
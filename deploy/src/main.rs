@@ -29,12 +29,12 @@ use docopt::Docopt;
 use std::path::PathBuf;
 use std::{env, fs};
 use tokio_core::reactor::Core;
-use web3::transports::http::Http;
 
 use bridge::config::Config;
 use bridge::database::State;
 use bridge::deploy::{DeployMain, DeploySide};
 use bridge::error::{self, ResultExt};
+use bridge::NodeTransport;
 
 const MAX_PARALLEL_REQUESTS: usize = 10;
 
@@ -98,26 +98,20 @@ Options:
 	let mut event_loop = Core::new().unwrap();
 
 	info!(
-		"Establishing HTTP connection to main {:?}",
-		config.main.http
+		"Establishing connection to main {}",
+		config.main.endpoints[0]
 	);
-	let main_transport = Http::with_event_loop(
-		&config.main.http,
-		&event_loop.handle(),
-		MAX_PARALLEL_REQUESTS,
-	)
-	.chain_err(|| format!("Cannot connect to main at {}", config.main.http))?;
+	let main_transport =
+		NodeTransport::new(&config.main, &event_loop.handle(), MAX_PARALLEL_REQUESTS)
+			.chain_err(|| "Cannot connect to main")?;
 
 	info!(
-		"Establishing HTTP connection to side {:?}",
-		config.side.http
+		"Establishing connection to side {}",
+		config.side.endpoints[0]
 	);
-	let side_transport = Http::with_event_loop(
-		&config.side.http,
-		&event_loop.handle(),
-		MAX_PARALLEL_REQUESTS,
-	)
-	.chain_err(|| format!("Cannot connect to side at {}", config.side.http))?;
+	let side_transport =
+		NodeTransport::new(&config.side, &event_loop.handle(), MAX_PARALLEL_REQUESTS)
+			.chain_err(|| "Cannot connect to side")?;
 
 	info!(target: "parity-bridge-deploy", "Deploying MainBridge contract");
 	let main_deployed = event_loop.run(DeployMain::new(config.clone(), main_transport))?;